@@ -1,9 +1,101 @@
-use std::{borrow::Cow, fmt::Display, rc::Rc};
+use std::{
+    borrow::Cow,
+    collections::{hash_map::DefaultHasher, BTreeSet, HashMap},
+    fmt::Display,
+    hash::{Hash, Hasher},
+    rc::{Rc, Weak},
+};
+
+use crate::{intern::NameId, span::Span, types::Qualified};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Literal {
+    Int(i64),
+    String(String),
+    /// The unit value `()`, returned by builtins that are run only for
+    /// their side effects.
+    Unit,
+    /// Integer outside of the range of `i64` as decimal digits, from
+    /// literal parsers with arbitrary precision. There is no arithmetic on
+    /// them.
+    #[allow(dead_code)]
+    BigInt(String),
+}
+
+impl Display for Literal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Literal::Int(i) => i.fmt(f),
+            Literal::String(s) => write!(f, "{s:?}"),
+            Literal::Unit => "()".fmt(f),
+            Literal::BigInt(digits) => digits.fmt(f),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    /// Comparisons, which give 1 if they hold and 0 otherwise.
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl BinOp {
+    /// All operators, in no particular order.
+    pub const ALL: [BinOp; 9] = [
+        BinOp::Add,
+        BinOp::Sub,
+        BinOp::Mul,
+        BinOp::Div,
+        BinOp::Eq,
+        BinOp::Lt,
+        BinOp::Le,
+        BinOp::Gt,
+        BinOp::Ge,
+    ];
+
+    /// Binding strength of the operator; higher binds tighter.
+    pub fn precedence(self) -> u8 {
+        match self {
+            BinOp::Eq | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => 1,
+            BinOp::Add | BinOp::Sub => 2,
+            BinOp::Mul | BinOp::Div => 3,
+        }
+    }
+}
+
+impl Display for BinOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BinOp::Add => "+".fmt(f),
+            BinOp::Sub => "-".fmt(f),
+            BinOp::Mul => "*".fmt(f),
+            BinOp::Div => "/".fmt(f),
+            BinOp::Eq => "==".fmt(f),
+            BinOp::Lt => "<".fmt(f),
+            BinOp::Le => "<=".fmt(f),
+            BinOp::Gt => ">".fmt(f),
+            BinOp::Ge => ">=".fmt(f),
+        }
+    }
+}
 
 #[derive(Debug)]
+#[allow(dead_code)]
 pub enum NodeKind<'src, Anno> {
     Name {
         name: Cow<'src, str>,
+        /// Id of the name in the interner of the parser that read it, see
+        /// `Parser::into_interner`. Names made elsewhere, like the fresh
+        /// names of substitution, have none.
+        id: Option<NameId>,
     },
     App {
         fun: Rc<Node<'src, Anno>>,
@@ -13,21 +105,868 @@ pub enum NodeKind<'src, Anno> {
         param: Rc<Node<'src, Anno>>,
         body: Rc<Node<'src, Anno>>,
     },
+    Lit {
+        lit: Literal,
+    },
+    BinOp {
+        op: BinOp,
+        /// The position of the operator itself.
+        op_span: Span,
+        lhs: Rc<Node<'src, Anno>>,
+        rhs: Rc<Node<'src, Anno>>,
+    },
+    Borrow {
+        expr: Rc<Node<'src, Anno>>,
+    },
+    /// Type annotation `(expr : Type)`, also written `expr @Type`.
+    Ann {
+        expr: Rc<Node<'src, Anno>>,
+        ty: Rc<Qualified>,
+    },
+    If {
+        cond: Rc<Node<'src, Anno>>,
+        conseq: Rc<Node<'src, Anno>>,
+        alt: Rc<Node<'src, Anno>>,
+    },
+    /// Multi-way conditional `if | c1 => e1 | c2 => e2 | otherwise => e3 end`,
+    /// which selects the body of the first arm whose condition is true, or
+    /// `otherwise` if there is none.
+    MultiIf {
+        arms: Vec<Arm<'src, Anno>>,
+        otherwise: Option<Rc<Node<'src, Anno>>>,
+    },
+    /// Simultaneous, non-recursive binding of each name to its value in
+    /// `body`. The values are in the scope outside of the `let`.
+    Let {
+        bindings: Vec<Binding<'src, Anno>>,
+        body: Rc<Node<'src, Anno>>,
+    },
+    /// Evaluation of `first`, followed by `second`.
+    Seq {
+        first: Rc<Node<'src, Anno>>,
+        second: Rc<Node<'src, Anno>>,
+    },
+    /// Variable in nameless representation, referring to the binder
+    /// `index` binders further out; see `to_debruijn`.
+    BoundVar {
+        index: usize,
+    },
+    /// List of the values of `elems`, `[a, b, c]`.
+    List {
+        elems: Vec<Rc<Node<'src, Anno>>>,
+    },
+    /// List comprehension `[body | x <- xs, y <- ys, guard]`. Each generator
+    /// binds its name to the elements of its list in the later generators,
+    /// the guards and the body, and `body` is collected for every
+    /// combination of elements for which all guards are true.
+    Comprehension {
+        body: Rc<Node<'src, Anno>>,
+        generators: Vec<Binding<'src, Anno>>,
+        guards: Vec<Rc<Node<'src, Anno>>>,
+    },
 }
 
-#[derive(Debug)]
+/// A name bound by a `let` or a generator, together with its value or the
+/// list it ranges over.
+pub type Binding<'src, Anno> = (Rc<Node<'src, Anno>>, Rc<Node<'src, Anno>>);
+
+/// The condition and body of an arm of a multi-way conditional.
+pub type Arm<'src, Anno> = (Rc<Node<'src, Anno>>, Rc<Node<'src, Anno>>);
+
+#[allow(dead_code)]
 pub struct Node<'src, Anno> {
-    start: usize,
-    end: usize,
+    span: Span,
     anno: Anno,
     kind: NodeKind<'src, Anno>,
 }
 
+#[allow(dead_code)]
+impl<'src, Anno> Node<'src, Anno> {
+    pub fn new(span: Span, anno: Anno, kind: NodeKind<'src, Anno>) -> Self {
+        Node { span, anno, kind }
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn start(&self) -> usize {
+        self.span.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.span.end
+    }
+
+    pub fn anno(&self) -> &Anno {
+        &self.anno
+    }
+
+    pub fn kind(&self) -> &NodeKind<'src, Anno> {
+        &self.kind
+    }
+
+    /// The name of a `Name` node, `None` for other nodes.
+    pub fn as_name(&self) -> Option<&str> {
+        match &self.kind {
+            NodeKind::Name { name, .. } => Some(name),
+            _ => None,
+        }
+    }
+
+    /// The interned id of a `Name` node, `None` for other nodes and names
+    /// without one.
+    pub fn name_id(&self) -> Option<NameId> {
+        match &self.kind {
+            NodeKind::Name { id, .. } => *id,
+            _ => None,
+        }
+    }
+
+    /// Return whether `name` occurs free in the tree, that is, outside of
+    /// any abstraction or `let` binding it. Stops at the first free
+    /// occurrence.
+    pub fn contains_free(&self, name: &str) -> bool {
+        match &self.kind {
+            NodeKind::Name { name: n, .. } => n == name,
+            NodeKind::Abs { param, body } => {
+                param.as_name() != Some(name) && body.contains_free(name)
+            }
+            NodeKind::Let { bindings, body } => {
+                bindings.iter().any(|(_, value)| value.contains_free(name))
+                    || (bindings.iter().all(|(n, _)| n.as_name() != Some(name))
+                        && body.contains_free(name))
+            }
+            NodeKind::Comprehension { body, generators, guards } => {
+                for (n, list) in generators {
+                    if list.contains_free(name) {
+                        return true;
+                    }
+                    if n.as_name() == Some(name) {
+                        return false;
+                    }
+                }
+                guards
+                    .iter()
+                    .chain([body])
+                    .any(|child| child.contains_free(name))
+            }
+            kind => kind
+                .children()
+                .into_iter()
+                .any(|child| child.contains_free(name)),
+        }
+    }
+
+    /// Decompose a nested application `f a b c` into its head `f` and the
+    /// arguments `[a, b, c]`, from left to right. Any other node is its own
+    /// head, with no arguments.
+    pub fn app_spine(&self) -> (&Node<'src, Anno>, Vec<&Node<'src, Anno>>) {
+        let mut head = self;
+        let mut args = Vec::new();
+        while let NodeKind::App { fun, arg } = &head.kind {
+            args.push(arg.as_ref());
+            head = fun;
+        }
+        args.reverse();
+        (head, args)
+    }
+
+    /// Return a copy of the tree with all positions moved `delta` bytes to
+    /// the right.
+    pub fn shifted(&self, delta: usize) -> Rc<Node<'src, Anno>>
+    where
+        Anno: Clone,
+    {
+        let mut kind = self.kind.map_children(|child| child.shifted(delta));
+        if let NodeKind::BinOp { op_span, .. } = &mut kind {
+            *op_span = Span::new(op_span.start + delta, op_span.end + delta);
+        }
+        Rc::new(Node::new(
+            Span::new(self.span.start + delta, self.span.end + delta),
+            self.anno.clone(),
+            kind,
+        ))
+    }
+
+    /// Return a node with the position and annotation of this node, but the
+    /// given kind.
+    pub fn with_kind(&self, kind: NodeKind<'src, Anno>) -> Rc<Node<'src, Anno>>
+    where
+        Anno: Clone,
+    {
+        Rc::new(Node::new(self.span, self.anno.clone(), kind))
+    }
+
+    /// Return a copy of the tree in which the free occurrences of `from` are
+    /// renamed to `to`, keeping their positions. Bound occurrences of `from`
+    /// are left alone, and binders that would capture a renamed occurrence
+    /// are renamed as by `substitute`.
+    pub fn rename_free(&self, from: &str, to: &str) -> Rc<Node<'src, Anno>>
+    where
+        Anno: Clone,
+    {
+        let term = self.with_kind(self.kind.map_children(Rc::clone));
+        substitute_changed(&term, from, &Replacement::Name(to), &mut FreeVarCache::new()).unwrap_or(term)
+    }
+
+    /// The name bound by an abstraction parameter or a `let`.
+    ///
+    /// # Panics
+    /// Panics if the node is not a `Name`.
+    pub fn binder_name(&self) -> &str {
+        self.as_name().expect("binder is a name")
+    }
+}
+
+/// Replace the free occurrences of `name` in `term` by `replacement`,
+/// renaming bound variables where necessary so that free variables of
+/// `replacement` are not captured. Bound occurrences of `name` are left
+/// alone. Subtrees without free occurrences of `name` are shared with
+/// `term`, not copied.
+#[allow(dead_code)]
+pub fn substitute<'src, A: Clone>(
+    term: &Rc<Node<'src, A>>,
+    name: &str,
+    replacement: &Rc<Node<'src, A>>,
+) -> Rc<Node<'src, A>> {
+    substitute_cached(term, name, replacement, &mut FreeVarCache::new())
+}
+
+/// Substitute like `substitute`, with the free names of the trees looked up
+/// in `cache`, so that repeated substitutions into the same trees do not
+/// have to find them again.
+pub fn substitute_cached<'src, A: Clone>(
+    term: &Rc<Node<'src, A>>,
+    name: &str,
+    replacement: &Rc<Node<'src, A>>,
+    cache: &mut FreeVarCache<'src, A>,
+) -> Rc<Node<'src, A>> {
+    substitute_changed(term, name, &Replacement::Term(replacement), cache).unwrap_or_else(|| term.clone())
+}
+
+/// What `substitute_changed` replaces the occurrences of a name with.
+enum Replacement<'a, 'src, A> {
+    /// The same term for every occurrence.
+    Term(&'a Rc<Node<'src, A>>),
+    /// A name, with the position and annotation of each occurrence.
+    Name(&'a str),
+}
+
+impl<'src, A: Clone> Replacement<'_, 'src, A> {
+    fn replace(&self, occurrence: &Node<'src, A>) -> Rc<Node<'src, A>> {
+        match self {
+            Replacement::Term(term) => Rc::clone(term),
+            Replacement::Name(name) => occurrence.with_kind(NodeKind::Name {
+                name: Cow::Owned(name.to_string()),
+                id: None,
+            }),
+        }
+    }
+
+    fn contains_free(&self, name: &str, cache: &mut FreeVarCache<'src, A>) -> bool {
+        match self {
+            Replacement::Term(term) => cache.contains_free(term, name),
+            Replacement::Name(n) => *n == name,
+        }
+    }
+}
+
+/// Implementation of `substitute`, returning `None` if `term` is unchanged.
+fn substitute_changed<'src, A: Clone>(
+    term: &Rc<Node<'src, A>>,
+    name: &str,
+    replacement: &Replacement<'_, 'src, A>,
+    cache: &mut FreeVarCache<'src, A>,
+) -> Option<Rc<Node<'src, A>>> {
+    match &term.kind {
+        NodeKind::Name { name: n, .. } if n == name => Some(replacement.replace(term)),
+        NodeKind::Name { .. } | NodeKind::Lit { .. } | NodeKind::BoundVar { .. } => None,
+        NodeKind::Abs { param, body } => {
+            let (mut params, body) =
+                substitute_under(std::slice::from_ref(param), body, name, replacement, cache)?;
+            let param = params.pop().expect("one parameter");
+            Some(term.with_kind(NodeKind::Abs { param, body }))
+        }
+        NodeKind::Let { bindings, body } => {
+            // The bindings are not recursive, so only the body is in their
+            // scope.
+            let mut changed = false;
+            let values = bindings
+                .iter()
+                .map(|(_, value)| match substitute_changed(value, name, replacement, cache) {
+                    Some(value) => {
+                        changed = true;
+                        value
+                    }
+                    None => value.clone(),
+                })
+                .collect::<Vec<_>>();
+            let params = bindings.iter().map(|(n, _)| n.clone()).collect::<Vec<_>>();
+            let (params, body) = match substitute_under(&params, body, name, replacement, cache) {
+                Some(under) => under,
+                None if changed => (params, body.clone()),
+                None => return None,
+            };
+            Some(term.with_kind(NodeKind::Let {
+                bindings: params.into_iter().zip(values).collect(),
+                body,
+            }))
+        }
+        NodeKind::Comprehension { body, generators, guards } if !generators.is_empty() => {
+            // The first generator scopes over the rest of the comprehension,
+            // which is substituted as a comprehension of its own.
+            let (param, list) = &generators[0];
+            let list_changed = substitute_changed(list, name, replacement, cache);
+            let rest = term.with_kind(NodeKind::Comprehension {
+                body: body.clone(),
+                generators: generators[1..].to_vec(),
+                guards: guards.clone(),
+            });
+            let (mut params, rest) = match substitute_under(std::slice::from_ref(param), &rest, name, replacement, cache) {
+                Some(under) => under,
+                None if list_changed.is_some() => (vec![param.clone()], rest),
+                None => return None,
+            };
+            let NodeKind::Comprehension { body, generators, guards } = &rest.kind else {
+                unreachable!("substitution keeps the kind of the node");
+            };
+            let first = (params.pop().expect("one generator"), list_changed.unwrap_or_else(|| list.clone()));
+            Some(term.with_kind(NodeKind::Comprehension {
+                body: body.clone(),
+                generators: [first].into_iter().chain(generators.iter().cloned()).collect(),
+                guards: guards.clone(),
+            }))
+        }
+        kind => {
+            let mut changed = false;
+            let kind = kind.map_children(|child| match substitute_changed(child, name, replacement, cache) {
+                Some(child) => {
+                    changed = true;
+                    child
+                }
+                None => child.clone(),
+            });
+            changed.then(|| term.with_kind(kind))
+        }
+    }
+}
+
+/// Substitute `replacement` for `name` in `body`, which is in the scope of
+/// the binders `params`. Returns the binders, renamed where they would
+/// capture a free variable of `replacement`, and the new body, or `None` if
+/// nothing changes.
+#[allow(clippy::type_complexity)]
+fn substitute_under<'src, A: Clone>(
+    params: &[Rc<Node<'src, A>>],
+    body: &Rc<Node<'src, A>>,
+    name: &str,
+    replacement: &Replacement<'_, 'src, A>,
+    cache: &mut FreeVarCache<'src, A>,
+) -> Option<(Vec<Rc<Node<'src, A>>>, Rc<Node<'src, A>>)> {
+    if params.iter().any(|p| p.binder_name() == name) || !cache.contains_free(body, name) {
+        return None;
+    }
+    let mut params = params.to_vec();
+    let mut body = body.clone();
+    for i in 0..params.len() {
+        let bound = params[i].binder_name().to_string();
+        if !replacement.contains_free(&bound, cache) {
+            continue;
+        }
+        let fresh = (1..)
+            .map(|i| format!("{bound}{i}"))
+            .find(|n| {
+                !replacement.contains_free(n, cache)
+                    && !cache.contains_free(&body, n)
+                    && params.iter().all(|p| p.binder_name() != n)
+            })
+            .expect("infinitely many candidate names");
+        let renamed = params[i].with_kind(NodeKind::Name {
+            name: Cow::Owned(fresh),
+            id: None,
+        });
+        body = substitute_cached(&body, &bound, &renamed, cache);
+        params[i] = renamed;
+    }
+    let substituted = substitute_changed(&body, name, replacement, cache).unwrap_or(body);
+    Some((params, substituted))
+}
+
+impl<'src, Anno: std::fmt::Debug> std::fmt::Debug for Node<'src, Anno> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Node")
+            .field("span", &self.span)
+            .field("anno", &self.anno)
+            .field("kind", &self.kind)
+            .finish()
+    }
+}
+
+/// Debug rendering of a tree without annotations; see
+/// `Node::debug_without_anno`.
+pub struct WithoutAnno<'a, 'src, Anno>(&'a Node<'src, Anno>);
+
+#[allow(dead_code)]
+impl<'src, Anno> Node<'src, Anno> {
+    /// Return a value whose `Debug` output is the same as the node's, but
+    /// without the annotations, which can be large. Unlike the node itself,
+    /// this does not require the annotation type to implement `Debug`.
+    pub fn debug_without_anno(&self) -> WithoutAnno<'_, 'src, Anno> {
+        WithoutAnno(self)
+    }
+}
+
+impl<'a, 'src, Anno> std::fmt::Debug for WithoutAnno<'a, 'src, Anno> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Node")
+            .field("span", &self.0.span)
+            .field("kind", &KindWithoutAnno(&self.0.kind))
+            .finish()
+    }
+}
+
+/// Debug rendering of a node kind with children printed by `WithoutAnno`.
+struct KindWithoutAnno<'a, 'src, Anno>(&'a NodeKind<'src, Anno>);
+
+impl<'a, 'src, Anno> std::fmt::Debug for KindWithoutAnno<'a, 'src, Anno> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let w = |node| WithoutAnno(node);
+        match self.0 {
+            NodeKind::Name { name, id } => f.debug_struct("Name").field("name", name).field("id", id).finish(),
+            NodeKind::App { fun, arg } => f
+                .debug_struct("App")
+                .field("fun", &w(fun))
+                .field("arg", &w(arg))
+                .finish(),
+            NodeKind::Abs { param, body } => f
+                .debug_struct("Abs")
+                .field("param", &w(param))
+                .field("body", &w(body))
+                .finish(),
+            NodeKind::Lit { lit } => f.debug_struct("Lit").field("lit", lit).finish(),
+            NodeKind::BinOp { op, op_span, lhs, rhs } => f
+                .debug_struct("BinOp")
+                .field("op", op)
+                .field("op_span", op_span)
+                .field("lhs", &w(lhs))
+                .field("rhs", &w(rhs))
+                .finish(),
+            NodeKind::Borrow { expr } => f.debug_struct("Borrow").field("expr", &w(expr)).finish(),
+            NodeKind::Ann { expr, ty } => f
+                .debug_struct("Ann")
+                .field("expr", &w(expr))
+                .field("ty", ty)
+                .finish(),
+            NodeKind::If { cond, conseq, alt } => f
+                .debug_struct("If")
+                .field("cond", &w(cond))
+                .field("conseq", &w(conseq))
+                .field("alt", &w(alt))
+                .finish(),
+            NodeKind::MultiIf { arms, otherwise } => {
+                let arms = arms.iter().map(|(cond, body)| (w(cond), w(body))).collect::<Vec<_>>();
+                f.debug_struct("MultiIf")
+                    .field("arms", &arms)
+                    .field("otherwise", &otherwise.as_ref().map(|node| w(node)))
+                    .finish()
+            }
+            NodeKind::Let { bindings, body } => {
+                let bindings = bindings
+                    .iter()
+                    .map(|(name, value)| (w(name), w(value)))
+                    .collect::<Vec<_>>();
+                f.debug_struct("Let")
+                    .field("bindings", &bindings)
+                    .field("body", &w(body))
+                    .finish()
+            }
+            NodeKind::Seq { first, second } => f
+                .debug_struct("Seq")
+                .field("first", &w(first))
+                .field("second", &w(second))
+                .finish(),
+            NodeKind::BoundVar { index } => {
+                f.debug_struct("BoundVar").field("index", index).finish()
+            }
+            NodeKind::List { elems } => {
+                let elems = elems.iter().map(|elem| w(elem)).collect::<Vec<_>>();
+                f.debug_struct("List").field("elems", &elems).finish()
+            }
+            NodeKind::Comprehension { body, generators, guards } => {
+                let generators = generators
+                    .iter()
+                    .map(|(name, list)| (w(name), w(list)))
+                    .collect::<Vec<_>>();
+                let guards = guards.iter().map(|guard| w(guard)).collect::<Vec<_>>();
+                f.debug_struct("Comprehension")
+                    .field("body", &w(body))
+                    .field("generators", &generators)
+                    .field("guards", &guards)
+                    .finish()
+            }
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl<'src, Anno> NodeKind<'src, Anno> {
+    /// Return the child nodes, from left to right.
+    pub fn children(&self) -> Vec<&Rc<Node<'src, Anno>>> {
+        match self {
+            NodeKind::Name { .. } | NodeKind::Lit { .. } | NodeKind::BoundVar { .. } => vec![],
+            NodeKind::App { fun, arg } => vec![fun, arg],
+            NodeKind::Abs { param, body } => vec![param, body],
+            NodeKind::BinOp { lhs, rhs, .. } => vec![lhs, rhs],
+            NodeKind::Borrow { expr } | NodeKind::Ann { expr, .. } => vec![expr],
+            NodeKind::If { cond, conseq, alt } => vec![cond, conseq, alt],
+            NodeKind::MultiIf { arms, otherwise } => arms
+                .iter()
+                .flat_map(|(cond, body)| [cond, body])
+                .chain(otherwise)
+                .collect(),
+            NodeKind::Let { bindings, body } => bindings
+                .iter()
+                .flat_map(|(name, value)| [name, value])
+                .chain([body])
+                .collect(),
+            NodeKind::Seq { first, second } => vec![first, second],
+            NodeKind::List { elems } => elems.iter().collect(),
+            NodeKind::Comprehension { body, generators, guards } => [body]
+                .into_iter()
+                .chain(generators.iter().flat_map(|(name, list)| [name, list]))
+                .chain(guards)
+                .collect(),
+        }
+    }
+
+    /// Return a node kind of the same shape, with every child node replaced
+    /// by the result of calling `f` on it.
+    pub fn map_children<F>(&self, mut f: F) -> NodeKind<'src, Anno>
+    where
+        F: FnMut(&Rc<Node<'src, Anno>>) -> Rc<Node<'src, Anno>>,
+    {
+        match self {
+            NodeKind::Name { name, id } => NodeKind::Name { name: name.clone(), id: *id },
+            NodeKind::App { fun, arg } => NodeKind::App {
+                fun: f(fun),
+                arg: f(arg),
+            },
+            NodeKind::Abs { param, body } => NodeKind::Abs {
+                param: f(param),
+                body: f(body),
+            },
+            NodeKind::Lit { lit } => NodeKind::Lit { lit: lit.clone() },
+            NodeKind::BinOp { op, op_span, lhs, rhs } => NodeKind::BinOp {
+                op: *op,
+                op_span: *op_span,
+                lhs: f(lhs),
+                rhs: f(rhs),
+            },
+            NodeKind::Borrow { expr } => NodeKind::Borrow { expr: f(expr) },
+            NodeKind::Ann { expr, ty } => NodeKind::Ann {
+                expr: f(expr),
+                ty: ty.clone(),
+            },
+            NodeKind::If { cond, conseq, alt } => NodeKind::If {
+                cond: f(cond),
+                conseq: f(conseq),
+                alt: f(alt),
+            },
+            NodeKind::MultiIf { arms, otherwise } => NodeKind::MultiIf {
+                arms: arms.iter().map(|(cond, body)| (f(cond), f(body))).collect(),
+                otherwise: otherwise.as_ref().map(&mut f),
+            },
+            NodeKind::Let { bindings, body } => NodeKind::Let {
+                bindings: bindings
+                    .iter()
+                    .map(|(name, value)| (f(name), f(value)))
+                    .collect(),
+                body: f(body),
+            },
+            NodeKind::Seq { first, second } => NodeKind::Seq {
+                first: f(first),
+                second: f(second),
+            },
+            NodeKind::BoundVar { index } => NodeKind::BoundVar { index: *index },
+            NodeKind::List { elems } => NodeKind::List {
+                elems: elems.iter().map(&mut f).collect(),
+            },
+            NodeKind::Comprehension { body, generators, guards } => NodeKind::Comprehension {
+                body: f(body),
+                generators: generators
+                    .iter()
+                    .map(|(name, list)| (f(name), f(list)))
+                    .collect(),
+                guards: guards.iter().map(&mut f).collect(),
+            },
+        }
+    }
+}
+
+/// A whole program, as returned by `Parser::parse_program`.
+pub type Program<'src> = [Declaration<'src>];
+
+/// Name resolution on programs. When a name is declared more than once,
+/// the first declaration wins.
+#[allow(dead_code)]
+pub trait ProgramExt<'src> {
+    /// Return the declaration of `name`, if any.
+    fn lookup(&self, name: &str) -> Option<&Declaration<'src>>;
+
+    /// Build a map from names to declarations, for callers that resolve
+    /// many names.
+    fn index(&self) -> HashMap<&str, &Declaration<'src>>;
+}
+
+impl<'src> ProgramExt<'src> for Program<'src> {
+    fn lookup(&self, name: &str) -> Option<&Declaration<'src>> {
+        self.iter().find(|decl| decl.name() == name)
+    }
+
+    fn index(&self) -> HashMap<&str, &Declaration<'src>> {
+        let mut index = HashMap::new();
+        for decl in self {
+            index.entry(decl.name()).or_insert(decl);
+        }
+        index
+    }
+}
+
+/// Value of an attribute entry `name = value`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum AttributeValue<'src> {
+    Int(i64),
+    String(Cow<'src, str>),
+}
+
+/// Entry of an attribute: a bare name, or a name with a value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum AttributeEntry<'src> {
+    Flag(Cow<'src, str>),
+    Value(Cow<'src, str>, AttributeValue<'src>),
+}
+
+/// An attribute `@name` or `@[entry, ...]` attached to a declaration. The
+/// short form `@name` has the single entry `name`.
+#[derive(Debug, Clone)]
+pub struct Attribute<'src> {
+    pub(crate) entries: Vec<AttributeEntry<'src>>,
+    pub(crate) span: Span,
+}
+
+#[allow(dead_code)]
+impl<'src> Attribute<'src> {
+    pub fn entries(&self) -> &[AttributeEntry<'src>] {
+        &self.entries
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Return whether the attribute has a bare entry `name`.
+    pub fn has_flag(&self, name: &str) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| matches!(entry, AttributeEntry::Flag(n) if n == name))
+    }
+
+    /// Return the value of the first entry `name = value`, if any.
+    pub fn get(&self, name: &str) -> Option<&AttributeValue<'src>> {
+        self.entries.iter().find_map(|entry| match entry {
+            AttributeEntry::Value(n, value) if n == name => Some(value),
+            _ => None,
+        })
+    }
+}
+
+/// A top-level definition `name param... = body;`, optionally preceded by
+/// attributes and a type signature `name :: Type;`.
+#[derive(Debug, Clone)]
+pub struct Declaration<'src> {
+    pub(crate) attributes: Vec<Attribute<'src>>,
+    pub(crate) name: Cow<'src, str>,
+    pub(crate) ty: Option<Qualified>,
+    /// The parameters written before the `=`, as `Name` nodes.
+    pub(crate) params: Vec<Rc<Node<'src, ()>>>,
+    /// The guarded equations `| guard = body` as written, empty if the
+    /// definition has no guards. `body` then holds them desugared into
+    /// conditionals, without the equations after `otherwise`.
+    pub(crate) guards: Vec<Guard<'src>>,
+    pub(crate) body: Rc<Node<'src, ()>>,
+    pub(crate) span: Span,
+}
+
+/// A guarded equation `| guard = body` of a definition.
+pub type Guard<'src> = (Rc<Node<'src, ()>>, Rc<Node<'src, ()>>);
+
+#[allow(dead_code)]
+impl<'src> Declaration<'src> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn attributes(&self) -> &[Attribute<'src>] {
+        &self.attributes
+    }
+
+    /// The type from the signature, if any.
+    pub fn ty(&self) -> Option<&Qualified> {
+        self.ty.as_ref()
+    }
+
+    pub fn params(&self) -> &[Rc<Node<'src, ()>>] {
+        &self.params
+    }
+
+    /// The guarded equations, in the order they are written.
+    pub fn guards(&self) -> &[Guard<'src>] {
+        &self.guards
+    }
+
+    /// The guarded equations after the first one with the guard
+    /// `otherwise`, which can never be selected.
+    pub fn unreachable_guards(&self) -> &[Guard<'src>] {
+        match self.guards.iter().position(|(guard, _)| guard.as_name() == Some("otherwise")) {
+            Some(otherwise) => &self.guards[otherwise + 1..],
+            None => &[],
+        }
+    }
+
+    /// The body as written, without the parameters; see `desugar`. The
+    /// guards of a guarded definition are desugared into conditionals.
+    pub fn body(&self) -> &Rc<Node<'src, ()>> {
+        &self.body
+    }
+
+    /// The value of the declaration: the body wrapped in one abstraction
+    /// per parameter, so that `f x y = b;` gives `\ x. \ y. b`. Without
+    /// parameters, this is the body itself.
+    pub fn desugar(&self) -> Rc<Node<'src, ()>> {
+        self.params.iter().rev().fold(self.body.clone(), |body, param| {
+            Rc::new(Node::new(
+                param.span().merge(body.span()),
+                (),
+                NodeKind::Abs {
+                    param: param.clone(),
+                    body,
+                },
+            ))
+        })
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn start(&self) -> usize {
+        self.span.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.span.end
+    }
+
+    /// Return a copy of the declaration with all positions moved `delta`
+    /// bytes to the right.
+    pub fn shifted(&self, delta: usize) -> Declaration<'src> {
+        Declaration {
+            attributes: self
+                .attributes
+                .iter()
+                .map(|attr| Attribute {
+                    entries: attr.entries.clone(),
+                    span: Span::new(attr.span.start + delta, attr.span.end + delta),
+                })
+                .collect(),
+            name: self.name.clone(),
+            ty: self.ty.clone(),
+            params: self.params.iter().map(|param| param.shifted(delta)).collect(),
+            guards: self
+                .guards
+                .iter()
+                .map(|(guard, body)| (guard.shifted(delta), body.shifted(delta)))
+                .collect(),
+            body: self.body.shifted(delta),
+            span: Span::new(self.span.start + delta, self.span.end + delta),
+        }
+    }
+}
+
+impl Display for AttributeValue<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AttributeValue::Int(i) => i.fmt(f),
+            AttributeValue::String(s) => write!(f, "{s:?}"),
+        }
+    }
+}
+
+/// Prints the attribute in the list form `@[entry, ...]`.
+impl Display for Attribute<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        "@[".fmt(f)?;
+        for (i, entry) in self.entries.iter().enumerate() {
+            if i > 0 {
+                ", ".fmt(f)?;
+            }
+            match entry {
+                AttributeEntry::Flag(name) => name.fmt(f)?,
+                AttributeEntry::Value(name, value) => write!(f, "{name} = {value}")?,
+            }
+        }
+        "]".fmt(f)
+    }
+}
+
+/// Prints the declaration as source, with each attribute and the signature
+/// on a line of their own.
+impl Display for Declaration<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for attr in &self.attributes {
+            writeln!(f, "{attr}")?;
+        }
+        if let Some(ty) = &self.ty {
+            writeln!(f, "{} :: {ty};", self.name)?;
+        }
+        self.name.fmt(f)?;
+        for param in &self.params {
+            write!(f, " {param}")?;
+        }
+        if self.guards.is_empty() {
+            return write!(f, " = {};", self.body);
+        }
+        for (guard, body) in &self.guards {
+            write!(f, " | {guard} = {body}")?;
+        }
+        ";".fmt(f)
+    }
+}
+
+/// State passed down while printing a tree. `prio` is the minimum priority
+/// a node must have to be printed without parentheses in the current
+/// context; see `NodeKind::prio`. `level` is the indentation level of the
+/// current line and `width` the line width to stay within, if any. Only
+/// `pretty` breaks lines, `Display` always prints on a single line.
 #[derive(Default, Clone, Copy)]
+#[allow(dead_code)]
 pub struct ShowState {
     prio: usize,
+    level: usize,
+    width: Option<usize>,
 }
 
+/// Priority of applications: function application binds tighter than any
+/// binary operator.
+const PRIO_APP: usize = 10;
+
+/// Priority of atoms, which never need parentheses.
+const PRIO_ATOM: usize = 11;
+
+#[allow(dead_code)]
 pub trait Show {
     fn show(&self, st: &mut ShowState, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result;
 }
@@ -38,21 +977,1404 @@ impl<'src, Anno> Show for Node<'src, Anno> {
     }
 }
 
+impl<'src, Anno> Display for Node<'src, Anno> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.show(&mut ShowState::default(), f)
+    }
+}
+
+impl<'src, Anno> NodeKind<'src, Anno> {
+    /// Priority of the node for printing: abstractions extend as far to the
+    /// right as possible and have the lowest priority, followed by binary
+    /// operators according to their precedence, applications and atoms.
+    fn prio(&self) -> usize {
+        match self {
+            NodeKind::Abs { .. } => 0,
+            NodeKind::BinOp { op, .. } => 1 + op.precedence() as usize,
+            NodeKind::App { .. } => PRIO_APP,
+            // `f (-1)` needs the parentheses, `-1 * 2` does not.
+            NodeKind::Lit { lit: Literal::Int(i) } if *i < 0 => PRIO_APP - 1,
+            NodeKind::Name { .. }
+            | NodeKind::Lit { .. }
+            | NodeKind::Borrow { .. }
+            | NodeKind::Ann { .. }
+            | NodeKind::If { .. }
+            | NodeKind::MultiIf { .. }
+            | NodeKind::Let { .. }
+            | NodeKind::Seq { .. }
+            | NodeKind::BoundVar { .. }
+            | NodeKind::List { .. }
+            | NodeKind::Comprehension { .. } => PRIO_ATOM,
+        }
+    }
+}
+
+impl<'src, Anno> NodeKind<'src, Anno> {
+    /// Print the `;`-separated items of a block, without the braces.
+    fn show_block_items(&self, st: &ShowState, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NodeKind::Let { bindings, body } => {
+                "let ".fmt(f)?;
+                for (i, (name, value)) in bindings.iter().enumerate() {
+                    if i > 0 {
+                        "; ".fmt(f)?;
+                    }
+                    name.show(&mut ShowState { prio: PRIO_ATOM, ..*st }, f)?;
+                    " = ".fmt(f)?;
+                    value.show(&mut ShowState { prio: 0, ..*st }, f)?;
+                }
+                // Bindings separated by `;` in a block are sequential, so
+                // simultaneous ones need `in`.
+                if bindings.len() > 1 {
+                    " in ".fmt(f)?;
+                    return body.show(&mut ShowState { prio: 0, ..*st }, f);
+                }
+                "; ".fmt(f)?;
+                body.kind.show_block_items(st, f)
+            }
+            NodeKind::Seq { first, second } => {
+                first.show(&mut ShowState { prio: 0, ..*st }, f)?;
+                "; ".fmt(f)?;
+                second.kind.show_block_items(st, f)
+            }
+            _ => self.show(&mut ShowState { prio: 0, ..*st }, f),
+        }
+    }
+}
+
 impl<'src, Anno> Show for NodeKind<'src, Anno> {
     fn show(&self, st: &mut ShowState, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let parens = self.prio() < st.prio;
+        if parens {
+            "(".fmt(f)?;
+        }
         match self {
-            NodeKind::Name { name } => name.as_ref().fmt(f),
+            NodeKind::Name { name, .. } => name.as_ref().fmt(f)?,
             NodeKind::App { fun, arg } => {
-                fun.show(&mut ShowState{prio: st.prio + 1, ..*st}, f)?;
+                fun.show(&mut ShowState { prio: PRIO_APP, ..*st }, f)?;
                 " ".fmt(f)?;
-                arg.show(st, f)
+                arg.show(&mut ShowState { prio: PRIO_ATOM, ..*st }, f)?;
             }
             NodeKind::Abs { param, body } => {
                 "\\ ".fmt(f)?;
-                param.show(st, f)?;
+                param.show(&mut ShowState { prio: PRIO_ATOM, ..*st }, f)?;
                 ". ".fmt(f)?;
-                body.show(st, f)
+                body.show(&mut ShowState { prio: 0, ..*st }, f)?;
+            }
+            NodeKind::Lit { lit } => lit.fmt(f)?,
+            NodeKind::BoundVar { index } => index.fmt(f)?,
+            NodeKind::BinOp { op, lhs, rhs, .. } => {
+                let prio = self.prio();
+                lhs.show(&mut ShowState { prio, ..*st }, f)?;
+                write!(f, " {op} ")?;
+                rhs.show(&mut ShowState { prio: prio + 1, ..*st }, f)?;
+            }
+            NodeKind::Borrow { expr } => {
+                "&".fmt(f)?;
+                expr.show(&mut ShowState { prio: PRIO_ATOM, ..*st }, f)?;
+            }
+            NodeKind::Ann { expr, ty } => {
+                "(".fmt(f)?;
+                expr.show(&mut ShowState { prio: 0, ..*st }, f)?;
+                write!(f, " : {ty})")?;
+            }
+            NodeKind::If { cond, conseq, alt } => {
+                "if ".fmt(f)?;
+                cond.show(&mut ShowState { prio: 0, ..*st }, f)?;
+                " then ".fmt(f)?;
+                conseq.show(&mut ShowState { prio: 0, ..*st }, f)?;
+                " else ".fmt(f)?;
+                alt.show(&mut ShowState { prio: 0, ..*st }, f)?;
+                " end".fmt(f)?;
+            }
+            NodeKind::MultiIf { arms, otherwise } => {
+                "if".fmt(f)?;
+                for (cond, body) in arms {
+                    " | ".fmt(f)?;
+                    cond.show(&mut ShowState { prio: 0, ..*st }, f)?;
+                    " => ".fmt(f)?;
+                    body.show(&mut ShowState { prio: 0, ..*st }, f)?;
+                }
+                if let Some(otherwise) = otherwise {
+                    " | otherwise => ".fmt(f)?;
+                    otherwise.show(&mut ShowState { prio: 0, ..*st }, f)?;
+                }
+                " end".fmt(f)?;
+            }
+            NodeKind::Let { .. } | NodeKind::Seq { .. } => {
+                "{ ".fmt(f)?;
+                self.show_block_items(st, f)?;
+                " }".fmt(f)?;
+            }
+            NodeKind::List { elems } => {
+                "[".fmt(f)?;
+                for (i, elem) in elems.iter().enumerate() {
+                    if i > 0 {
+                        ", ".fmt(f)?;
+                    }
+                    elem.show(&mut ShowState { prio: 0, ..*st }, f)?;
+                }
+                "]".fmt(f)?;
+            }
+            NodeKind::Comprehension { body, generators, guards } => {
+                "[".fmt(f)?;
+                body.show(&mut ShowState { prio: 0, ..*st }, f)?;
+                " | ".fmt(f)?;
+                for (i, (name, list)) in generators.iter().enumerate() {
+                    if i > 0 {
+                        ", ".fmt(f)?;
+                    }
+                    name.show(&mut ShowState { prio: PRIO_ATOM, ..*st }, f)?;
+                    " <- ".fmt(f)?;
+                    list.show(&mut ShowState { prio: 0, ..*st }, f)?;
+                }
+                for guard in guards {
+                    ", ".fmt(f)?;
+                    guard.show(&mut ShowState { prio: 0, ..*st }, f)?;
+                }
+                "]".fmt(f)?;
             }
         }
+        if parens {
+            ")".fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Return the names occurring free in the tree, that is, outside of any
+/// abstraction or `let` binding them.
+#[allow(dead_code)]
+pub fn free_vars<'src, A>(node: &Node<'src, A>) -> BTreeSet<Cow<'src, str>> {
+    match &node.kind {
+        NodeKind::Name { name, .. } => BTreeSet::from([name.clone()]),
+        NodeKind::Abs { param, body } => {
+            let mut vars = free_vars(body);
+            vars.remove(param.binder_name());
+            vars
+        }
+        NodeKind::Let { bindings, body } => {
+            let mut vars = free_vars(body);
+            for (name, _) in bindings {
+                vars.remove(name.binder_name());
+            }
+            for (_, value) in bindings {
+                vars.extend(free_vars(value));
+            }
+            vars
+        }
+        NodeKind::Comprehension { body, generators, guards } => {
+            let mut vars = guards.iter().chain([body]).flat_map(|child| free_vars(child)).collect::<BTreeSet<_>>();
+            for (name, list) in generators.iter().rev() {
+                vars.remove(name.binder_name());
+                vars.extend(free_vars(list));
+            }
+            vars
+        }
+        kind => kind
+            .children()
+            .into_iter()
+            .flat_map(|child| free_vars(child))
+            .collect(),
+    }
+}
+
+/// The free names of a node, shared between a `FreeVarCache` and its users.
+pub type FreeVars<'src> = Rc<BTreeSet<Cow<'src, str>>>;
+
+/// Cache for the results of `free_vars` on the nodes of trees, so that
+/// repeated queries on the same nodes take constant time. Nodes are
+/// identified by their address. The cache holds only weak references to
+/// the nodes, so that it does not keep trees alive, but keeps the
+/// addresses of dropped nodes from being reused by other nodes. Entries of
+/// dropped nodes are evicted as the cache grows.
+pub struct FreeVarCache<'src, A> {
+    entries: HashMap<*const Node<'src, A>, (Weak<Node<'src, A>>, FreeVars<'src>)>,
+    /// Number of entries at which the entries of dropped nodes are evicted
+    /// next.
+    evict_at: usize,
+}
+
+/// Minimum number of entries of a `FreeVarCache` before it evicts any.
+const MIN_EVICT_AT: usize = 1024;
+
+#[allow(dead_code)]
+impl<'src, A> FreeVarCache<'src, A> {
+    pub fn new() -> Self {
+        FreeVarCache {
+            entries: HashMap::new(),
+            evict_at: MIN_EVICT_AT,
+        }
+    }
+
+    /// Return the free names of the node like `free_vars`. The results for
+    /// the node and all nodes below it are cached.
+    pub fn free_vars(&mut self, node: &Rc<Node<'src, A>>) -> FreeVars<'src> {
+        if let Some((_, vars)) = self.entries.get(&Rc::as_ptr(node)) {
+            return vars.clone();
+        }
+        let vars = match &node.kind {
+            NodeKind::Name { name, .. } => BTreeSet::from([name.clone()]),
+            NodeKind::Abs { param, body } => {
+                let mut vars = BTreeSet::clone(&self.free_vars(body));
+                vars.remove(param.binder_name());
+                vars
+            }
+            NodeKind::Let { bindings, body } => {
+                let mut vars = BTreeSet::clone(&self.free_vars(body));
+                for (name, _) in bindings {
+                    vars.remove(name.binder_name());
+                }
+                for (_, value) in bindings {
+                    vars.extend(self.free_vars(value).iter().cloned());
+                }
+                vars
+            }
+            NodeKind::Comprehension { body, generators, guards } => {
+                let mut vars = guards
+                    .iter()
+                    .chain([body])
+                    .flat_map(|child| BTreeSet::clone(&self.free_vars(child)))
+                    .collect::<BTreeSet<_>>();
+                for (name, list) in generators.iter().rev() {
+                    vars.remove(name.binder_name());
+                    vars.extend(self.free_vars(list).iter().cloned());
+                }
+                vars
+            }
+            kind => kind
+                .children()
+                .into_iter()
+                .flat_map(|child| BTreeSet::clone(&self.free_vars(child)))
+                .collect(),
+        };
+        if self.entries.len() >= self.evict_at {
+            self.entries.retain(|_, (node, _)| node.strong_count() > 0);
+            self.evict_at = MIN_EVICT_AT.max(2 * self.entries.len());
+        }
+        let vars = Rc::new(vars);
+        self.entries.insert(Rc::as_ptr(node), (Rc::downgrade(node), vars.clone()));
+        vars
+    }
+
+    /// Return whether `name` occurs free in the node, like
+    /// `Node::contains_free`, using the cached free names.
+    pub fn contains_free(&mut self, node: &Rc<Node<'src, A>>, name: &str) -> bool {
+        self.free_vars(node).contains(name)
+    }
+
+    /// Number of nodes with cached results, including dropped nodes that
+    /// have not been evicted yet.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<'src, A> Default for FreeVarCache<'src, A> {
+    fn default() -> Self {
+        FreeVarCache::new()
+    }
+}
+
+/// Convert a tree to one that does not borrow from the source, so that it
+/// can outlive it. Names that are already owned, as with
+/// `Parser::with_owned_names`, are moved instead of copied, as long as
+/// the nodes are not shared.
+#[allow(dead_code)]
+pub fn into_owned<A: Clone>(node: Rc<Node<'_, A>>) -> Rc<Node<'static, A>> {
+    let Node { span, anno, kind } = Rc::try_unwrap(node).unwrap_or_else(|node| Node {
+        span: node.span,
+        anno: node.anno.clone(),
+        kind: node.kind.map_children(Rc::clone),
+    });
+    let owned = |name: Cow<'_, str>| Cow::Owned(name.into_owned());
+    let pairs = |pairs: Vec<Binding<'_, A>>| {
+        pairs.into_iter().map(|(a, b)| (into_owned(a), into_owned(b))).collect()
+    };
+    let kind = match kind {
+        NodeKind::Name { name, id } => NodeKind::Name { name: owned(name), id },
+        NodeKind::App { fun, arg } => NodeKind::App {
+            fun: into_owned(fun),
+            arg: into_owned(arg),
+        },
+        NodeKind::Abs { param, body } => NodeKind::Abs {
+            param: into_owned(param),
+            body: into_owned(body),
+        },
+        NodeKind::Lit { lit } => NodeKind::Lit { lit },
+        NodeKind::BinOp { op, op_span, lhs, rhs } => NodeKind::BinOp {
+            op,
+            op_span,
+            lhs: into_owned(lhs),
+            rhs: into_owned(rhs),
+        },
+        NodeKind::Borrow { expr } => NodeKind::Borrow { expr: into_owned(expr) },
+        NodeKind::Ann { expr, ty } => NodeKind::Ann {
+            expr: into_owned(expr),
+            ty,
+        },
+        NodeKind::If { cond, conseq, alt } => NodeKind::If {
+            cond: into_owned(cond),
+            conseq: into_owned(conseq),
+            alt: into_owned(alt),
+        },
+        NodeKind::MultiIf { arms, otherwise } => NodeKind::MultiIf {
+            arms: pairs(arms),
+            otherwise: otherwise.map(into_owned),
+        },
+        NodeKind::Let { bindings, body } => NodeKind::Let {
+            bindings: pairs(bindings),
+            body: into_owned(body),
+        },
+        NodeKind::Seq { first, second } => NodeKind::Seq {
+            first: into_owned(first),
+            second: into_owned(second),
+        },
+        NodeKind::BoundVar { index } => NodeKind::BoundVar { index },
+        NodeKind::List { elems } => NodeKind::List {
+            elems: elems.into_iter().map(into_owned).collect(),
+        },
+        NodeKind::Comprehension { body, generators, guards } => NodeKind::Comprehension {
+            body: into_owned(body),
+            generators: pairs(generators),
+            guards: guards.into_iter().map(into_owned).collect(),
+        },
+    };
+    Rc::new(Node::new(span, anno, kind))
+}
+
+/// Convert a tree to nameless representation: every name bound by an
+/// abstraction or `let` is replaced by a `BoundVar` whose index is the
+/// number of binders between the occurrence and its binder, and the names
+/// of the binders are erased. Free names are kept as they are.
+#[allow(dead_code)]
+pub fn to_debruijn<'src, A: Clone>(node: &Node<'src, A>) -> Rc<Node<'src, A>> {
+    to_debruijn_in(node, &mut Vec::new())
+}
+
+/// Implementation of `to_debruijn`. `context` holds the names of the
+/// enclosing binders, innermost last.
+fn to_debruijn_in<'src, A: Clone>(node: &Node<'src, A>, context: &mut Vec<String>) -> Rc<Node<'src, A>> {
+    let erased = |param: &Node<'src, A>| param.with_kind(NodeKind::Name { name: "".into(), id: None });
+    let kind = match &node.kind {
+        NodeKind::Name { name, id } => match context.iter().rev().position(|n| n == name) {
+            Some(index) => NodeKind::BoundVar { index },
+            None => NodeKind::Name { name: name.clone(), id: *id },
+        },
+        NodeKind::Abs { param, body } => {
+            context.push(param.binder_name().to_string());
+            let body = to_debruijn_in(body, context);
+            context.pop();
+            NodeKind::Abs {
+                param: erased(param),
+                body,
+            }
+        }
+        NodeKind::Let { bindings, body } => {
+            let values = bindings
+                .iter()
+                .map(|(_, value)| to_debruijn_in(value, context))
+                .collect::<Vec<_>>();
+            let depth = context.len();
+            context.extend(bindings.iter().map(|(name, _)| name.binder_name().to_string()));
+            let body = to_debruijn_in(body, context);
+            context.truncate(depth);
+            NodeKind::Let {
+                bindings: bindings.iter().map(|(name, _)| erased(name)).zip(values).collect(),
+                body,
+            }
+        }
+        NodeKind::Comprehension { body, generators, guards } => {
+            let depth = context.len();
+            let generators = generators
+                .iter()
+                .map(|(name, list)| {
+                    let list = to_debruijn_in(list, context);
+                    context.push(name.binder_name().to_string());
+                    (erased(name), list)
+                })
+                .collect();
+            let guards = guards.iter().map(|guard| to_debruijn_in(guard, context)).collect();
+            let body = to_debruijn_in(body, context);
+            context.truncate(depth);
+            NodeKind::Comprehension { body, generators, guards }
+        }
+        kind => kind.map_children(|child| to_debruijn_in(child, context)),
+    };
+    node.with_kind(kind)
+}
+
+/// Return a hash of the structure of a tree. Trees that are equal according
+/// to `alpha_eq` have the same hash, so the hash can be used to deduplicate
+/// terms or as a key for memoizing results. Positions and annotations are
+/// ignored.
+#[allow(dead_code)]
+pub fn structural_hash<A>(node: &Node<'_, A>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hash_structure(node, &mut Vec::new(), &mut hasher);
+    hasher.finish()
+}
+
+/// Implementation of `structural_hash`. `context` holds the names of the
+/// enclosing binders, innermost last; bound names are hashed by their
+/// distance to the binder, like in `to_debruijn`.
+fn hash_structure<'a, A>(node: &'a Node<'_, A>, context: &mut Vec<&'a str>, state: &mut DefaultHasher) {
+    std::mem::discriminant(&node.kind).hash(state);
+    match &node.kind {
+        NodeKind::Name { name, .. } => match context.iter().rev().position(|n| n == name) {
+            Some(index) => (true, index).hash(state),
+            None => (false, name).hash(state),
+        },
+        NodeKind::Abs { param, body } => {
+            context.push(param.binder_name());
+            hash_structure(body, context, state);
+            context.pop();
+        }
+        NodeKind::Let { bindings, body } => {
+            bindings.len().hash(state);
+            for (_, value) in bindings {
+                hash_structure(value, context, state);
+            }
+            let depth = context.len();
+            context.extend(bindings.iter().map(|(name, _)| name.binder_name()));
+            hash_structure(body, context, state);
+            context.truncate(depth);
+        }
+        NodeKind::Comprehension { body, generators, guards } => {
+            (generators.len(), guards.len()).hash(state);
+            let depth = context.len();
+            for (name, list) in generators {
+                hash_structure(list, context, state);
+                context.push(name.binder_name());
+            }
+            for child in guards.iter().chain([body]) {
+                hash_structure(child, context, state);
+            }
+            context.truncate(depth);
+        }
+        NodeKind::Lit { lit } => lit.hash(state),
+        NodeKind::BoundVar { index } => index.hash(state),
+        kind => {
+            match kind {
+                NodeKind::BinOp { op, .. } => op.hash(state),
+                NodeKind::Ann { ty, .. } => ty.hash(state),
+                NodeKind::List { elems } => elems.len().hash(state),
+                NodeKind::MultiIf { arms, otherwise } => (arms.len(), otherwise.is_some()).hash(state),
+                _ => {}
+            }
+            for child in kind.children() {
+                hash_structure(child, context, state);
+            }
+        }
+    }
+}
+
+/// Return whether two trees are equal up to the names of bound variables.
+/// Free names must be equal, and the trees must have the same shape.
+/// Positions and annotations are ignored.
+#[allow(dead_code)]
+pub fn alpha_eq<A, B>(a: &Node<'_, A>, b: &Node<'_, B>) -> bool {
+    alpha_eq_in(a, b, &mut Vec::new(), &mut Vec::new())
+}
+
+/// Implementation of `alpha_eq`. The contexts hold the names of the
+/// binders enclosing `a` and `b`, innermost last; they always have the
+/// same length.
+fn alpha_eq_in<'a, 'b, A, B>(
+    a: &'a Node<'_, A>,
+    b: &'b Node<'_, B>,
+    ctx_a: &mut Vec<&'a str>,
+    ctx_b: &mut Vec<&'b str>,
+) -> bool {
+    match (&a.kind, &b.kind) {
+        (NodeKind::Name { name: x, .. }, NodeKind::Name { name: y, .. }) => {
+            let i = ctx_a.iter().rev().position(|n| n == x);
+            let j = ctx_b.iter().rev().position(|n| n == y);
+            match (i, j) {
+                (None, None) => x == y,
+                (i, j) => i == j,
+            }
+        }
+        (NodeKind::Abs { param: pa, body: ba }, NodeKind::Abs { param: pb, body: bb }) => {
+            ctx_a.push(pa.binder_name());
+            ctx_b.push(pb.binder_name());
+            let result = alpha_eq_in(ba, bb, ctx_a, ctx_b);
+            ctx_a.pop();
+            ctx_b.pop();
+            result
+        }
+        (
+            NodeKind::Let { bindings: xs, body: ba },
+            NodeKind::Let { bindings: ys, body: bb },
+        ) => {
+            if xs.len() != ys.len()
+                || !xs
+                    .iter()
+                    .zip(ys)
+                    .all(|((_, x), (_, y))| alpha_eq_in(x, y, ctx_a, ctx_b))
+            {
+                return false;
+            }
+            let depth = ctx_a.len();
+            ctx_a.extend(xs.iter().map(|(name, _)| name.binder_name()));
+            ctx_b.extend(ys.iter().map(|(name, _)| name.binder_name()));
+            let result = alpha_eq_in(ba, bb, ctx_a, ctx_b);
+            ctx_a.truncate(depth);
+            ctx_b.truncate(depth);
+            result
+        }
+        (
+            NodeKind::Comprehension { body: ba, generators: xs, guards: gas },
+            NodeKind::Comprehension { body: bb, generators: ys, guards: gbs },
+        ) => {
+            if xs.len() != ys.len() || gas.len() != gbs.len() {
+                return false;
+            }
+            let depth = ctx_a.len();
+            let mut result = true;
+            for ((x, la), (y, lb)) in xs.iter().zip(ys) {
+                if !alpha_eq_in(la, lb, ctx_a, ctx_b) {
+                    result = false;
+                    break;
+                }
+                ctx_a.push(x.binder_name());
+                ctx_b.push(y.binder_name());
+            }
+            let result = result
+                && gas
+                    .iter()
+                    .chain([ba])
+                    .zip(gbs.iter().chain([bb]))
+                    .all(|(x, y)| alpha_eq_in(x, y, ctx_a, ctx_b));
+            ctx_a.truncate(depth);
+            ctx_b.truncate(depth);
+            result
+        }
+        (NodeKind::Lit { lit: x }, NodeKind::Lit { lit: y }) => x == y,
+        (NodeKind::BoundVar { index: i }, NodeKind::BoundVar { index: j }) => i == j,
+        (NodeKind::BinOp { op: x, .. }, NodeKind::BinOp { op: y, .. }) if x != y => false,
+        (NodeKind::List { elems: xs }, NodeKind::List { elems: ys }) if xs.len() != ys.len() => false,
+        (NodeKind::Ann { ty: x, .. }, NodeKind::Ann { ty: y, .. }) if x != y => false,
+        (
+            NodeKind::MultiIf { arms: xs, otherwise: x },
+            NodeKind::MultiIf { arms: ys, otherwise: y },
+        ) if xs.len() != ys.len() || x.is_some() != y.is_some() => false,
+        (NodeKind::App { .. }, NodeKind::App { .. })
+        | (NodeKind::List { .. }, NodeKind::List { .. })
+        | (NodeKind::BinOp { .. }, NodeKind::BinOp { .. })
+        | (NodeKind::Borrow { .. }, NodeKind::Borrow { .. })
+        | (NodeKind::Ann { .. }, NodeKind::Ann { .. })
+        | (NodeKind::If { .. }, NodeKind::If { .. })
+        | (NodeKind::MultiIf { .. }, NodeKind::MultiIf { .. })
+        | (NodeKind::Seq { .. }, NodeKind::Seq { .. }) => a
+            .kind
+            .children()
+            .into_iter()
+            .zip(b.kind.children())
+            .all(|(x, y)| alpha_eq_in(x, y, ctx_a, ctx_b)),
+        _ => false,
+    }
+}
+
+/// Return the spans in `a` and in `b` of the largest subterm, by number of
+/// nodes, that occurs in both trees up to `alpha_eq`. Subterms are
+/// compared on their own, so names bound outside of them count as free.
+/// Binders are not subterms. Of several equally large subterms, the one
+/// that ends first in `a` is returned.
+pub fn largest_common_subterm<A, B>(a: &Node<'_, A>, b: &Node<'_, B>) -> Option<(Span, Span)> {
+    let mut subterms_b = Vec::new();
+    collect_subterms(b, &mut subterms_b);
+    let mut index = HashMap::<u64, Vec<&Node<'_, B>>>::new();
+    for (_, y) in subterms_b {
+        index.entry(structural_hash(y)).or_default().push(y);
+    }
+    let mut subterms_a = Vec::new();
+    collect_subterms(a, &mut subterms_a);
+    subterms_a.sort_by_key(|(size, _)| std::cmp::Reverse(*size));
+    subterms_a.into_iter().find_map(|(_, x)| {
+        let candidates = index.get(&structural_hash(x))?;
+        let y = candidates.iter().find(|y| alpha_eq(x, y))?;
+        Some((x.span(), y.span()))
+    })
+}
+
+/// Append the subterms of `node` with their sizes to `subterms` in
+/// post-order, and return the size of `node`.
+fn collect_subterms<'a, 'src, A>(node: &'a Node<'src, A>, subterms: &mut Vec<(usize, &'a Node<'src, A>)>) -> usize {
+    let binders = match &node.kind {
+        NodeKind::Abs { param, .. } => vec![param],
+        NodeKind::Let { bindings, .. } => bindings.iter().map(|(name, _)| name).collect(),
+        NodeKind::Comprehension { generators, .. } => generators.iter().map(|(name, _)| name).collect(),
+        _ => vec![],
+    };
+    let size = 1 + node
+        .kind
+        .children()
+        .into_iter()
+        .map(|child| {
+            if binders.iter().any(|binder| Rc::ptr_eq(binder, child)) {
+                1
+            } else {
+                collect_subterms(child, subterms)
+            }
+        })
+        .sum::<usize>();
+    subterms.push((size, node));
+    size
+}
+
+/// Compare two trees and describe the first difference in pre-order, as
+/// the path to the differing node followed by the expected node from `a`
+/// and the node found in `b`. Returns `None` if the trees have the same
+/// shape, names and literals. Positions and annotations are ignored.
+#[allow(dead_code)]
+pub fn tree_diff<A, B>(a: &Node<'_, A>, b: &Node<'_, B>) -> Option<String> {
+    tree_diff_at(a, b, &mut "root".to_string())
+}
+
+/// Implementation of `tree_diff` for nodes at the given path.
+fn tree_diff_at<A, B>(a: &Node<'_, A>, b: &Node<'_, B>, path: &mut String) -> Option<String> {
+    let (expected, found) = (a.kind.describe(), b.kind.describe());
+    if expected != found {
+        return Some(format!("at {path}: expected {expected}, found {found}"));
+    }
+    let labels = a.kind.child_labels();
+    for ((x, y), label) in a.kind.children().into_iter().zip(b.kind.children()).zip(labels) {
+        let len = path.len();
+        path.push('.');
+        path.push_str(&label);
+        let diff = tree_diff_at(x, y, path);
+        path.truncate(len);
+        if diff.is_some() {
+            return diff;
+        }
+    }
+    None
+}
+
+impl<'src, Anno> NodeKind<'src, Anno> {
+    /// Describe the node without its children, for `tree_diff`. Nodes with
+    /// the same description have the same number of children.
+    fn describe(&self) -> String {
+        match self {
+            NodeKind::Name { name, .. } => format!("name `{name}`"),
+            NodeKind::App { .. } => "application".into(),
+            NodeKind::Abs { .. } => "abstraction".into(),
+            NodeKind::Lit { lit } => format!("literal `{lit}`"),
+            NodeKind::BinOp { op, .. } => format!("operator `{op}`"),
+            NodeKind::Borrow { .. } => "borrow".into(),
+            NodeKind::Ann { ty, .. } => format!("annotation with type `{ty}`"),
+            NodeKind::If { .. } => "conditional".into(),
+            NodeKind::MultiIf { arms, otherwise: None } => {
+                format!("multi-way conditional with {} arms", arms.len())
+            }
+            NodeKind::MultiIf { arms, otherwise: Some(_) } => {
+                format!("multi-way conditional with {} arms and otherwise", arms.len())
+            }
+            NodeKind::Let { bindings, .. } => format!("let with {} bindings", bindings.len()),
+            NodeKind::Seq { .. } => "sequence".into(),
+            NodeKind::BoundVar { index } => format!("bound variable {index}"),
+            NodeKind::List { elems } => format!("list of {} elements", elems.len()),
+            NodeKind::Comprehension { generators, guards, .. } => format!(
+                "comprehension with {} generators and {} guards",
+                generators.len(),
+                guards.len()
+            ),
+        }
+    }
+
+    /// Names of the children, in the order of `children`.
+    fn child_labels(&self) -> Vec<String> {
+        let labels: &[&str] = match self {
+            NodeKind::Name { .. } | NodeKind::Lit { .. } | NodeKind::BoundVar { .. } => &[],
+            NodeKind::App { .. } => &["fun", "arg"],
+            NodeKind::Abs { .. } => &["param", "body"],
+            NodeKind::BinOp { .. } => &["lhs", "rhs"],
+            NodeKind::Borrow { .. } | NodeKind::Ann { .. } => &["expr"],
+            NodeKind::If { .. } => &["cond", "conseq", "alt"],
+            NodeKind::MultiIf { arms, otherwise } => {
+                return (0..arms.len())
+                    .flat_map(|i| [format!("arms[{i}].cond"), format!("arms[{i}].body")])
+                    .chain(otherwise.as_ref().map(|_| "otherwise".to_string()))
+                    .collect()
+            }
+            NodeKind::Let { bindings, .. } => {
+                return (0..bindings.len())
+                    .flat_map(|i| [format!("bindings[{i}].name"), format!("bindings[{i}].value")])
+                    .chain(["body".to_string()])
+                    .collect()
+            }
+            NodeKind::Seq { .. } => &["first", "second"],
+            NodeKind::List { elems } => {
+                return (0..elems.len()).map(|i| format!("elems[{i}]")).collect()
+            }
+            NodeKind::Comprehension { generators, guards, .. } => {
+                return ["body".to_string()]
+                    .into_iter()
+                    .chain((0..generators.len()).flat_map(|i| {
+                        [format!("generators[{i}].name"), format!("generators[{i}].list")]
+                    }))
+                    .chain((0..guards.len()).map(|i| format!("guards[{i}]")))
+                    .collect()
+            }
+        };
+        labels.iter().map(|label| label.to_string()).collect()
+    }
+}
+
+/// Assert that two trees are equal according to `tree_diff`, and show the
+/// first difference otherwise.
+#[cfg(test)]
+macro_rules! assert_tree_eq {
+    ($expected:expr, $found:expr) => {
+        if let Some(diff) = $crate::ast::tree_diff(&$expected, &$found) {
+            panic!("trees differ {diff}\nexpected: {}\n   found: {}", $expected, $found);
+        }
+    };
+}
+
+#[cfg(test)]
+pub(crate) use assert_tree_eq;
+
+/// Options for `pretty`.
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct PrettyOptions {
+    /// Target line width. Terms that do not fit are broken over several
+    /// lines.
+    pub width: usize,
+    /// Text inserted once per nesting level at the start of a broken line,
+    /// for example two spaces or a tab.
+    pub indent: String,
+}
+
+impl Default for PrettyOptions {
+    fn default() -> Self {
+        PrettyOptions {
+            width: 80,
+            indent: "  ".into(),
+        }
+    }
+}
+
+/// Print a node like `Display`, but break abstractions, applications and
+/// blocks that do not fit into the line width: the body of an abstraction,
+/// each argument of an application and each item of a block go on their
+/// own line, indented one level deeper. Terms that fit are printed exactly
+/// as by `Display`, and the output can always be parsed again. The
+/// indentation counts towards the width with the number of characters in
+/// the indent unit.
+#[allow(dead_code)]
+pub fn pretty<Anno>(node: &Node<'_, Anno>, options: &PrettyOptions) -> String {
+    let st = ShowState {
+        prio: 0,
+        level: 0,
+        width: Some(options.width),
+    };
+    let mut out = String::new();
+    pretty_into(node, st, &options.indent, &mut out);
+    out
+}
+
+#[allow(dead_code)]
+impl<'src, Anno> Node<'src, Anno> {
+    /// Print the node with `pretty`, indenting by two spaces per level.
+    pub fn show_pretty(&self, width: usize) -> String {
+        let options = PrettyOptions {
+            width,
+            ..PrettyOptions::default()
+        };
+        pretty(self, &options)
+    }
+}
+
+/// Adapter to print a node on one line in the given state.
+struct Compact<'a, 'src, Anno>(&'a Node<'src, Anno>, ShowState);
+
+impl<'a, 'src, Anno> Display for Compact<'a, 'src, Anno> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut st = self.1;
+        self.0.show(&mut st, f)
+    }
+}
+
+/// Return whether `text` fits into the line width when printed at the
+/// indentation level of `st`.
+fn fits(st: ShowState, indent: &str, text: &str) -> bool {
+    let column = st.level * indent.chars().count();
+    st.width.is_none_or(|width| column + text.chars().count() <= width)
+}
+
+/// Start a new line indented to the given level.
+fn push_newline(out: &mut String, indent: &str, level: usize) {
+    out.push('\n');
+    for _ in 0..level {
+        out.push_str(indent);
+    }
+}
+
+/// Implementation of `pretty` for a node starting a line at the
+/// indentation level of `st`.
+fn pretty_into<Anno>(node: &Node<'_, Anno>, st: ShowState, indent: &str, out: &mut String) {
+    let compact = Compact(node, st).to_string();
+    if fits(st, indent, &compact)
+        || !matches!(
+            node.kind,
+            NodeKind::App { .. } | NodeKind::Abs { .. } | NodeKind::Let { .. } | NodeKind::Seq { .. }
+        )
+    {
+        out.push_str(&compact);
+        return;
+    }
+    let inner = ShowState {
+        level: st.level + 1,
+        ..st
+    };
+    let parens = node.kind.prio() < st.prio;
+    if parens {
+        out.push('(');
+    }
+    match &node.kind {
+        NodeKind::Abs { param, body } => {
+            out.push_str(&format!("\\ {}.", Compact(param, ShowState { prio: PRIO_ATOM, ..st })));
+            push_newline(out, indent, inner.level);
+            pretty_into(body, ShowState { prio: 0, ..inner }, indent, out);
+        }
+        NodeKind::Let { .. } | NodeKind::Seq { .. } => {
+            out.push('{');
+            pretty_block_items(node, ShowState { prio: 0, ..inner }, indent, out);
+            push_newline(out, indent, st.level);
+            out.push('}');
+        }
+        _ => {
+            let (head, args) = node.app_spine();
+            pretty_into(head, ShowState { prio: PRIO_APP, ..st }, indent, out);
+            for arg in args {
+                push_newline(out, indent, inner.level);
+                pretty_into(arg, ShowState { prio: PRIO_ATOM, ..inner }, indent, out);
+            }
+        }
+    }
+    if parens {
+        out.push(')');
+    }
+}
+
+/// Print the items of a block like `NodeKind::show_block_items`, each on
+/// its own line at the indentation level of `st`.
+fn pretty_block_items<Anno>(node: &Node<'_, Anno>, st: ShowState, indent: &str, out: &mut String) {
+    match &node.kind {
+        NodeKind::Let { bindings, body } => {
+            for (i, (name, value)) in bindings.iter().enumerate() {
+                let keyword = if i == 0 { "let " } else { "" };
+                let suffix = if bindings.len() > 1 && i == bindings.len() - 1 { " in" } else { ";" };
+                let name = Compact(name, ShowState { prio: PRIO_ATOM, ..st });
+                pretty_item(&format!("{keyword}{name} = "), value, suffix, st, indent, out);
+            }
+            if bindings.len() > 1 {
+                pretty_item("", body, "", st, indent, out);
+            } else {
+                pretty_block_items(body, st, indent, out);
+            }
+        }
+        NodeKind::Seq { first, second } => {
+            pretty_item("", first, ";", st, indent, out);
+            pretty_block_items(second, st, indent, out);
+        }
+        _ => pretty_item("", node, "", st, indent, out),
+    }
+}
+
+/// Print `prefix`, the node and `suffix` on a new line. If they do not fit,
+/// the node starts another line one level deeper.
+fn pretty_item<Anno>(
+    prefix: &str,
+    node: &Node<'_, Anno>,
+    suffix: &str,
+    st: ShowState,
+    indent: &str,
+    out: &mut String,
+) {
+    push_newline(out, indent, st.level);
+    let line = format!("{prefix}{}{suffix}", Compact(node, st));
+    if fits(st, indent, &line) {
+        out.push_str(&line);
+        return;
+    }
+    out.push_str(prefix.trim_end());
+    let st = if prefix.is_empty() {
+        st
+    } else {
+        let st = ShowState {
+            level: st.level + 1,
+            ..st
+        };
+        push_newline(out, indent, st.level);
+        st
+    };
+    pretty_into(node, st, indent, out);
+    out.push_str(suffix);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn node(kind: NodeKind<'static, ()>) -> Rc<Node<'static, ()>> {
+        Rc::new(Node::new(Span::default(), (), kind))
+    }
+
+    fn name(name: &'static str) -> Rc<Node<'static, ()>> {
+        node(NodeKind::Name { name: name.into(), id: None })
+    }
+
+    fn app(fun: Rc<Node<'static, ()>>, arg: Rc<Node<'static, ()>>) -> Rc<Node<'static, ()>> {
+        node(NodeKind::App { fun, arg })
+    }
+
+    fn abs(param: &'static str, body: Rc<Node<'static, ()>>) -> Rc<Node<'static, ()>> {
+        node(NodeKind::Abs {
+            param: name(param),
+            body,
+        })
+    }
+
+    fn binop(op: BinOp, lhs: Rc<Node<'static, ()>>, rhs: Rc<Node<'static, ()>>) -> Rc<Node<'static, ()>> {
+        let op_span = Span::default();
+        node(NodeKind::BinOp { op, op_span, lhs, rhs })
+    }
+
+    fn int(i: i64) -> Rc<Node<'static, ()>> {
+        node(NodeKind::Lit { lit: Literal::Int(i) })
+    }
+
+    fn show(node: &Node<'static, ()>) -> String {
+        node.to_string()
+    }
+
+    #[test]
+    fn display() {
+        let term = app(abs("x", app(name("f"), name("x"))), name("y"));
+        assert_eq!(term.to_string(), "(\\ x. f x) y");
+        assert_eq!(format!("{term}!"), "(\\ x. f x) y!");
+    }
+
+    #[test]
+    fn show_parentheses() {
+        assert_eq!(show(&app(app(name("f"), name("x")), name("y"))), "f x y");
+        assert_eq!(show(&app(name("f"), app(name("x"), name("y")))), "f (x y)");
+        assert_eq!(show(&app(abs("p", name("p")), name("x"))), "(\\ p. p) x");
+        assert_eq!(show(&app(name("f"), abs("p", name("p")))), "f (\\ p. p)");
+        assert_eq!(
+            show(&abs("x", abs("y", app(name("x"), name("y"))))),
+            "\\ x. \\ y. x y"
+        );
+        assert_eq!(
+            show(&app(abs("x", abs("y", name("x"))), app(name("g"), name("z")))),
+            "(\\ x. \\ y. x) (g z)"
+        );
+    }
+
+    #[test]
+    fn app_spine() {
+        let term = app(app(app(name("f"), name("a")), name("b")), app(name("g"), name("c")));
+        let (head, args) = term.app_spine();
+        assert_eq!(head.to_string(), "f");
+        assert_eq!(
+            args.iter().map(|a| a.to_string()).collect::<Vec<_>>(),
+            vec!["a", "b", "g c"]
+        );
+
+        let term = abs("x", app(name("f"), name("x")));
+        let (head, args) = term.app_spine();
+        assert!(std::ptr::eq(head, term.as_ref()));
+        assert!(args.is_empty());
+    }
+
+    #[test]
+    fn contains_free() {
+        // \\x. x y
+        let term = abs("x", app(name("x"), name("y")));
+        assert!(term.contains_free("y"));
+        assert!(!term.contains_free("x"));
+        assert!(!term.contains_free("z"));
+
+        // x (\\x. x)
+        let term = app(name("x"), abs("x", name("x")));
+        assert!(term.contains_free("x"));
+
+        // { let x = x; x }: the bound value is outside the binding.
+        let term = node(NodeKind::Let {
+            bindings: vec![(name("x"), name("x"))],
+            body: name("x"),
+        });
+        assert!(term.contains_free("x"));
+        let term = node(NodeKind::Let {
+            bindings: vec![(name("x"), int(1))],
+            body: name("x"),
+        });
+        assert!(!term.contains_free("x"));
+
+        // The search stops at the first free occurrence: searching all of
+        // `big`, which shares its subtrees to describe a tree of 2^64
+        // nodes, would not finish.
+        let mut big = name("z");
+        for _ in 0..64 {
+            big = app(big.clone(), big);
+        }
+        let term = binop(BinOp::Add, name("y"), big);
+        assert!(term.contains_free("y"));
+    }
+
+    #[test]
+    fn show_borrow() {
+        let borrow = node(NodeKind::Borrow { expr: name("x") });
+        assert_eq!(show(&borrow), "&x");
+        assert_eq!(show(&app(name("f"), borrow)), "f &x");
+        let borrow = node(NodeKind::Borrow {
+            expr: app(name("f"), name("x")),
+        });
+        assert_eq!(show(&borrow), "&(f x)");
+    }
+
+    #[test]
+    fn show_operator_parentheses() {
+        let sum = binop(BinOp::Add, int(1), int(2));
+        assert_eq!(show(&binop(BinOp::Mul, sum.clone(), int(3))), "(1 + 2) * 3");
+        assert_eq!(show(&binop(BinOp::Add, int(3), binop(BinOp::Mul, int(1), int(2)))), "3 + 1 * 2");
+        assert_eq!(show(&binop(BinOp::Sub, sum.clone(), int(3))), "1 + 2 - 3");
+        assert_eq!(show(&binop(BinOp::Sub, int(3), sum.clone())), "3 - (1 + 2)");
+        assert_eq!(show(&app(name("f"), sum.clone())), "f (1 + 2)");
+        assert_eq!(show(&binop(BinOp::Add, app(name("f"), int(1)), int(2))), "f 1 + 2");
+        assert_eq!(show(&abs("x", binop(BinOp::Add, name("x"), int(1)))), "\\ x. x + 1");
+        assert_eq!(
+            show(&binop(BinOp::Add, abs("x", name("x")), int(1))),
+            "(\\ x. x) + 1"
+        );
+    }
+
+    #[test]
+    fn pretty_indent() {
+        // f (\ x. g x x) aaaa
+        let term = app(
+            app(name("f"), abs("x", app(app(name("g"), name("x")), name("x")))),
+            name("aaaa"),
+        );
+        let mut options = PrettyOptions::default();
+        assert_eq!(pretty(&term, &options), "f (\\ x. g x x) aaaa");
+        options.width = 12;
+        // The body of the abstraction fits with two spaces, but not with four.
+        assert_eq!(pretty(&term, &options), "f\n  (\\ x.\n    g x x)\n  aaaa");
+        options.indent = "    ".into();
+        assert_eq!(pretty(&term, &options), "f\n    (\\ x.\n        g\n            x\n            x)\n    aaaa");
+        options.indent = "\t".into();
+        assert_eq!(pretty(&term, &options), "f\n\t(\\ x.\n\t\tg x x)\n\taaaa");
+    }
+
+    #[test]
+    fn show_pretty() {
+        let source = "{ let twice = \\f x. f (f x); inc = \\n. n + 1; print (twice inc 0); twice twice inc 0 }";
+        let mut parser = Parser::new(source).expect("scanning example input");
+        let term = parser.parse_script().expect("parsing example input");
+        let compact = term.to_string();
+        assert_eq!(term.show_pretty(compact.len()), compact);
+        let wrapped = term.show_pretty(24);
+        assert_eq!(
+            wrapped,
+            "{\n  let twice =\n    \\ f. \\ x. f (f x);\n  let inc = \\ n. n + 1;\n  print (twice inc 0);\n  twice twice inc 0\n}"
+        );
+        // The wrapped output is still valid syntax for the same term.
+        for width in [24, 12, 1] {
+            let wrapped = term.show_pretty(width);
+            let mut parser = Parser::new(&wrapped).expect("scanning pretty output");
+            let reparsed = parser.parse_script().expect("parsing pretty output");
+            assert_eq!(reparsed.to_string(), compact);
+        }
+    }
+
+    #[test]
+    fn rename_free() {
+        let mut parser = Parser::new("x (\\x. x)").expect("scanning example input");
+        let term = parser.parse_script().expect("parsing example input");
+        let renamed = term.rename_free("x", "y");
+        assert_eq!(renamed.to_string(), "y (\\ x. x)");
+        let (NodeKind::App { fun, arg }, NodeKind::App { arg: old_arg, .. }) = (renamed.kind(), term.kind()) else {
+            panic!("expected application, got {renamed:?}");
+        };
+        assert_eq!(fun.span(), Span::new(0, 1));
+        // The abstraction binds `x`, so it is untouched.
+        assert!(Rc::ptr_eq(arg, old_arg));
+
+        // A binder of the new name is renamed instead of capturing.
+        assert_eq!(show(&abs("y", app(name("x"), name("y"))).rename_free("x", "y")), "\\ y1. y y1");
+        assert_eq!(show(&name("z").rename_free("x", "y")), "z");
+    }
+
+    #[test]
+    fn substitution() {
+        let term = abs("y", app(name("x"), name("y")));
+        assert_eq!(show(&substitute(&term, "x", &name("z"))), "\\ y. z y");
+        // The free `y` of the replacement must not be captured.
+        assert_eq!(show(&substitute(&term, "x", &name("y"))), "\\ y1. y y1");
+        // `x` is shadowed by the parameter.
+        let shadowed = abs("x", app(name("x"), name("x")));
+        assert!(Rc::ptr_eq(&substitute(&shadowed, "x", &name("z")), &shadowed));
+
+        // Untouched subtrees are shared.
+        let left = app(name("f"), int(1));
+        let term = app(left.clone(), name("x"));
+        let result = substitute(&term, "x", &int(2));
+        assert_eq!(show(&result), "f 1 2");
+        let NodeKind::App { fun, .. } = result.kind() else {
+            panic!("expected application");
+        };
+        assert!(Rc::ptr_eq(fun, &left));
+    }
+
+    #[test]
+    fn debruijn() {
+        // \ x . \ y . x y
+        let term = abs("x", abs("y", app(name("x"), name("y"))));
+        let nameless = to_debruijn(&term);
+        let NodeKind::Abs { body, .. } = nameless.kind() else {
+            panic!("expected abstraction");
+        };
+        let NodeKind::Abs { body, .. } = body.kind() else {
+            panic!("expected abstraction");
+        };
+        let NodeKind::App { fun, arg } = body.kind() else {
+            panic!("expected application");
+        };
+        assert!(matches!(fun.kind(), NodeKind::BoundVar { index: 1 }));
+        assert!(matches!(arg.kind(), NodeKind::BoundVar { index: 0 }));
+        assert_eq!(show(&nameless), "\\ . \\ . 1 0");
+
+        // Free names are kept, and shadowing refers to the innermost binder.
+        let term = abs("x", app(abs("x", app(name("x"), name("z"))), name("x")));
+        assert_eq!(show(&to_debruijn(&term)), "\\ . (\\ . 0 z) 0");
+    }
+
+    #[test]
+    fn program_lookup() {
+        let mut parser = Parser::new("id x = x; main = id 1; id = 2;").expect("scanning example input");
+        let prog = parser.parse_program().expect("parsing example input");
+        let id = prog.lookup("id").expect("id is declared");
+        assert_eq!(id.params().len(), 1);
+        assert_eq!(prog.lookup("main").map(|decl| decl.start()), Some(10));
+        assert!(prog.lookup("missing").is_none());
+
+        let index = prog.index();
+        assert_eq!(index.len(), 2);
+        assert!(std::ptr::eq(index["id"], id));
+        assert!(!index.contains_key("missing"));
+    }
+
+    #[test]
+    fn free_variables() {
+        let vars = |node: &Node<'static, ()>| free_vars(node).into_iter().collect::<Vec<_>>();
+        assert_eq!(vars(&abs("x", app(name("x"), name("y")))), ["y"]);
+        assert_eq!(vars(&app(name("f"), abs("x", name("x")))), ["f"]);
+        // The inner x is bound by the inner abstraction, the outer one is
+        // free again after leaving the outer abstraction.
+        let shadowed = app(
+            abs("x", app(abs("x", app(name("x"), name("z"))), name("x"))),
+            app(name("x"), name("a")),
+        );
+        assert_eq!(vars(&shadowed), ["a", "x", "z"]);
+        assert_eq!(vars(&binop(BinOp::Add, int(1), int(2))), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn tree_differences() {
+        // f (\ x. x y)
+        let a = app(name("f"), abs("x", app(name("x"), name("y"))));
+        let b = app(name("f"), abs("x", app(name("x"), name("z"))));
+        assert_eq!(tree_diff(&a, &a), None);
+        assert_eq!(
+            tree_diff(&a, &b).as_deref(),
+            Some("at root.arg.body.arg: expected name `y`, found name `z`")
+        );
+        let c = app(name("f"), abs("x", binop(BinOp::Add, name("x"), name("y"))));
+        assert_eq!(
+            tree_diff(&a, &c).as_deref(),
+            Some("at root.arg.body: expected application, found operator `+`")
+        );
+        assert_tree_eq!(a, app(name("f"), abs("x", app(name("x"), name("y")))));
+    }
+
+    #[test]
+    #[should_panic(expected = "trees differ at root.fun")]
+    fn assert_tree_eq_fails() {
+        assert_tree_eq!(app(name("f"), name("x")), app(name("g"), name("x")));
+    }
+
+    #[test]
+    fn free_variable_cache() {
+        // (\ x. x y) (f (\ z. z w))
+        let term = app(
+            abs("x", app(name("x"), name("y"))),
+            app(name("f"), abs("z", app(name("z"), name("w")))),
+        );
+        let mut cache = FreeVarCache::new();
+        let vars = cache.free_vars(&term);
+        assert_eq!(*vars, free_vars(&term));
+        assert_eq!(vars.iter().collect::<Vec<_>>(), ["f", "w", "y"]);
+        // All 11 nodes were visited once, and are not visited again.
+        assert_eq!(cache.len(), 11);
+        assert!(Rc::ptr_eq(&cache.free_vars(&term), &vars));
+        assert_eq!(cache.len(), 11);
+        let NodeKind::App { fun, arg } = term.kind() else {
+            panic!("expected application");
+        };
+        assert!(cache.contains_free(fun, "y"));
+        assert!(!cache.contains_free(fun, "x"));
+        assert_eq!(cache.contains_free(arg, "w"), arg.contains_free("w"));
+        assert_eq!(cache.len(), 11);
+    }
+
+    #[test]
+    fn free_variable_cache_does_not_keep_trees_alive() {
+        let mut cache = FreeVarCache::new();
+        let term = app(name("f"), name("x"));
+        let weak = Rc::downgrade(&term);
+        assert!(cache.contains_free(&term, "x"));
+        drop(term);
+        assert!(weak.upgrade().is_none());
+        // Entries of dropped trees are evicted as new ones are added.
+        for _ in 0..10_000 {
+            let term = app(name("f"), name("x"));
+            assert!(cache.contains_free(&term, "f"));
+        }
+        assert!(cache.len() < 4 * MIN_EVICT_AT);
+    }
+
+    /// Compare repeated free name queries on a deep tree with and without
+    /// the cache. Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn free_variable_cache_benchmark() {
+        let term = (0..1000).fold(name("x"), |term, i| binop(BinOp::Add, term, int(i)));
+        let queries = 1000;
+        let start = std::time::Instant::now();
+        for _ in 0..queries {
+            assert!(!term.contains_free("y"));
+        }
+        let uncached = start.elapsed();
+        let mut cache = FreeVarCache::new();
+        let start = std::time::Instant::now();
+        for _ in 0..queries {
+            assert!(!cache.contains_free(&term, "y"));
+        }
+        let cached = start.elapsed();
+        println!("{queries} queries: {uncached:?} uncached, {cached:?} cached");
+        assert!(cached < uncached);
+    }
+
+    #[test]
+    fn structural_hashes() {
+        let hash = |input: &str| {
+            let mut parser = Parser::new(input).expect("scanning example input");
+            structural_hash(&parser.parse_script().expect("parsing example input"))
+        };
+        assert_eq!(hash("\\x. x"), hash("\\y. y"));
+        assert_ne!(hash("\\x. \\y. x"), hash("\\x. \\y. y"));
+        assert_eq!(hash("\\x. \\y. x"), hash("\\a. \\b. a"));
+        // Free names are kept, and bound and free names differ.
+        assert_ne!(hash("\\x. y"), hash("\\x. z"));
+        assert_ne!(hash("\\x. y"), hash("\\y. y"));
+        assert_eq!(hash("let x = y in f x"), hash("let z = y in f z"));
+        assert_ne!(hash("1 + 2"), hash("1 - 2"));
+        assert_ne!(hash("f x"), hash("x f"));
+        // Positions are ignored.
+        assert_eq!(hash("f   (x)"), hash("f x"));
+    }
+
+    #[test]
+    fn common_subterms() {
+        let common = |a: &str, b: &str| {
+            let mut parser = Parser::new(a).expect("scanning example input");
+            let a = parser.parse_script().expect("parsing example input");
+            let mut parser = Parser::new(b).expect("scanning example input");
+            let b = parser.parse_script().expect("parsing example input");
+            largest_common_subterm(&a, &b)
+        };
+        assert_eq!(common("f (g x) 1", "h 2 + g x"), Some((Span::new(2, 7), Span::new(6, 9))));
+        // Up to the names of bound variables.
+        assert_eq!(
+            common("k (\\y. y + 1)", "[\\z. z + 1, 2]"),
+            Some((Span::new(2, 13), Span::new(1, 10)))
+        );
+        // Binders are not subterms.
+        assert_eq!(common("\\x. 1", "x"), None);
+        assert_eq!(common("f 1", "g 2"), None);
+    }
+
+    #[test]
+    fn alpha_equivalence() {
+        assert!(alpha_eq(&abs("x", name("x")), &abs("y", name("y"))));
+        assert!(!alpha_eq(&abs("x", name("y")), &abs("x", name("z"))));
+        // Bound and free occurrences differ even if the names are equal.
+        assert!(!alpha_eq(&abs("x", name("y")), &abs("y", name("y"))));
+        // \ x y. x vs \ a b. b
+        assert!(alpha_eq(&abs("x", abs("y", name("x"))), &abs("a", abs("b", name("a")))));
+        assert!(!alpha_eq(&abs("x", abs("y", name("x"))), &abs("a", abs("b", name("b")))));
+        // Structural mismatches.
+        assert!(!alpha_eq(&abs("x", name("x")), &name("x")));
+        assert!(!alpha_eq(&app(name("f"), name("x")), &abs("f", name("x"))));
+        assert!(!alpha_eq(&binop(BinOp::Add, int(1), int(2)), &binop(BinOp::Sub, int(1), int(2))));
+
+        let let_term = |x: &'static str, y: &'static str| {
+            node(NodeKind::Let {
+                bindings: vec![(name(x), name("v")), (name(y), int(1))],
+                body: app(name(x), name(y)),
+            })
+        };
+        assert!(alpha_eq(&let_term("a", "b"), &let_term("c", "d")));
+        // The values are outside the scope of the bindings.
+        let let_self = |x: &'static str, v: &'static str| {
+            node(NodeKind::Let {
+                bindings: vec![(name(x), name(v))],
+                body: name(x),
+            })
+        };
+        assert!(alpha_eq(&let_self("x", "x"), &let_self("y", "x")));
+        assert!(!alpha_eq(&let_self("x", "x"), &let_self("y", "y")));
+        // The trees may have different annotations.
+        let annotated = Rc::new(Node::new(Span::default(), 1u8, NodeKind::Name { name: "x".into(), id: None }));
+        assert!(alpha_eq(&annotated, &name("x")));
+    }
+
+    #[test]
+    fn debug_without_anno() {
+        let annotated = |anno, kind| Rc::new(Node::new(Span::new(0, 3), anno, kind));
+        let f = annotated(7u32, NodeKind::Name { name: "f".into(), id: None });
+        let one = annotated(8u32, NodeKind::Lit { lit: Literal::Int(1) });
+        let term = annotated(9u32, NodeKind::App { fun: f, arg: one });
+        let with = format!("{term:?}");
+        let without = format!("{:?}", term.debug_without_anno());
+        assert!(with.contains("anno: 7"));
+        assert!(!without.contains("anno"));
+        assert_eq!(
+            without,
+            "Node { span: Span { start: 0, end: 3 }, kind: App { \
+             fun: Node { span: Span { start: 0, end: 3 }, kind: Name { name: \"f\", id: None } }, \
+             arg: Node { span: Span { start: 0, end: 3 }, kind: Lit { lit: Int(1) } } } }"
+        );
+        let mut stripped = with;
+        for anno in ["7", "8", "9"] {
+            stripped = stripped.replace(&format!(", anno: {anno}"), "");
+        }
+        assert_eq!(stripped, without);
+
+        // Pretty-printed output works, too.
+        assert_eq!(
+            format!("{:#?}", term.debug_without_anno()),
+            format!("{term:#?}").lines().filter(|l| !l.contains("anno:")).collect::<Vec<_>>().join("\n")
+        );
     }
 }
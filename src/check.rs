@@ -0,0 +1,328 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    ast::{Declaration, Node, NodeKind, Program, ProgramExt},
+    diagnostic::Diagnostic,
+    eval::io_builtins,
+    span::{Span, Spanned},
+};
+
+/// Report declarations whose value refers back to themselves outside of
+/// any abstraction, directly as in `x = x;` or through other declarations
+/// as in `a = b; b = a;`. Evaluating such a declaration diverges
+/// immediately. A reference under an abstraction, as in `f = \n. f n;`, is
+/// fine.
+///
+/// Each cycle is reported once, as an error at its first declaration, with
+/// a hint at every other declaration that is part of it.
+#[allow(dead_code)]
+pub fn detect_immediate_cycles(prog: &Program<'_>) -> Vec<Diagnostic> {
+    let index = prog.index();
+    let deps = index
+        .iter()
+        .map(|(name, decl)| (*name, immediate_references(decl, &index)))
+        .collect::<HashMap<_, _>>();
+    let mut reported = HashSet::new();
+    let mut diagnostics = Vec::new();
+    for decl in prog {
+        let name = decl.name();
+        // Later declarations of the same name are never referred to.
+        if reported.contains(name) || !std::ptr::eq(index[name], decl) {
+            continue;
+        }
+        let mut cycle = vec![name];
+        if !path_to(name, name, &deps, &mut HashSet::new(), &mut cycle) {
+            continue;
+        }
+        let path = cycle.iter().chain([&name]).copied().collect::<Vec<_>>().join(" -> ");
+        diagnostics.push(Diagnostic::error(
+            decl.span(),
+            format!("{name} refers to itself outside of any abstraction: {path}"),
+        ));
+        for other in &cycle[1..] {
+            diagnostics.push(Diagnostic::hint(index[other].span(), format!("{other} is part of the cycle")));
+        }
+        reported.extend(cycle);
+    }
+    diagnostics
+}
+
+/// Return the references in the declarations of `prog` to names that are
+/// bound neither by an enclosing abstraction, `let` or parameter, nor by a
+/// declaration or a builtin, in source order.
+#[allow(dead_code)]
+pub fn unbound_names(prog: &Program<'_>) -> Vec<Spanned<String>> {
+    let builtins = io_builtins();
+    let mut bound = prog
+        .iter()
+        .map(|decl| decl.name())
+        .chain(builtins.keys().copied())
+        .collect::<Vec<_>>();
+    let mut unbound = Vec::new();
+    for decl in prog {
+        let len = bound.len();
+        bound.extend(decl.params().iter().map(|param| param.binder_name()));
+        collect_unbound(decl.body(), &mut bound, &mut unbound);
+        bound.truncate(len);
+    }
+    unbound
+}
+
+fn collect_unbound<'a, A>(node: &'a Node<'_, A>, bound: &mut Vec<&'a str>, unbound: &mut Vec<Spanned<String>>) {
+    match node.kind() {
+        NodeKind::Name { name, .. } => {
+            if !bound.contains(&name.as_ref()) {
+                unbound.push(Spanned::new(name.to_string(), node.span()));
+            }
+        }
+        NodeKind::Abs { param, body } => {
+            bound.push(param.binder_name());
+            collect_unbound(body, bound, unbound);
+            bound.pop();
+        }
+        NodeKind::Let { bindings, body } => {
+            for (_, value) in bindings {
+                collect_unbound(value, bound, unbound);
+            }
+            let len = bound.len();
+            bound.extend(bindings.iter().map(|(param, _)| param.binder_name()));
+            collect_unbound(body, bound, unbound);
+            bound.truncate(len);
+        }
+        NodeKind::Comprehension { body, generators, guards } => {
+            let len = bound.len();
+            for (param, list) in generators {
+                collect_unbound(list, bound, unbound);
+                bound.push(param.binder_name());
+            }
+            for child in guards.iter().chain([body]) {
+                collect_unbound(child, bound, unbound);
+            }
+            bound.truncate(len);
+        }
+        kind => {
+            for child in kind.children() {
+                collect_unbound(child, bound, unbound);
+            }
+        }
+    }
+}
+
+/// Return the spans of the binders in `node` that bind a name that is
+/// already bound by an enclosing abstraction or `let`, as the inner `x` in
+/// `\x. \x. x`. Rewriters that substitute without renaming can get such
+/// terms wrong, so they can use this as a precondition. Unlike a shadowing
+/// warning, this only looks at the term itself, not at declarations.
+#[allow(dead_code)]
+pub fn has_capture_risk<A>(node: &Node<'_, A>) -> Vec<Span> {
+    let mut spans = Vec::new();
+    collect_shadowing_binders(node, &mut Vec::new(), &mut spans);
+    spans
+}
+
+fn collect_shadowing_binders<'a, A>(node: &'a Node<'_, A>, bound: &mut Vec<&'a str>, spans: &mut Vec<Span>) {
+    match node.kind() {
+        NodeKind::Abs { param, body } => {
+            let name = param.binder_name();
+            if bound.contains(&name) {
+                spans.push(param.span());
+            }
+            bound.push(name);
+            collect_shadowing_binders(body, bound, spans);
+            bound.pop();
+        }
+        NodeKind::Let { bindings, body } => {
+            for (_, value) in bindings {
+                collect_shadowing_binders(value, bound, spans);
+            }
+            let len = bound.len();
+            for (param, _) in bindings {
+                let name = param.binder_name();
+                // Also catches a name bound twice by the same `let`.
+                if bound.contains(&name) {
+                    spans.push(param.span());
+                }
+                bound.push(name);
+            }
+            collect_shadowing_binders(body, bound, spans);
+            bound.truncate(len);
+        }
+        NodeKind::Comprehension { body, generators, guards } => {
+            let len = bound.len();
+            for (param, list) in generators {
+                collect_shadowing_binders(list, bound, spans);
+                let name = param.binder_name();
+                if bound.contains(&name) {
+                    spans.push(param.span());
+                }
+                bound.push(name);
+            }
+            for child in guards.iter().chain([body]) {
+                collect_shadowing_binders(child, bound, spans);
+            }
+            bound.truncate(len);
+        }
+        kind => {
+            for child in kind.children() {
+                collect_shadowing_binders(child, bound, spans);
+            }
+        }
+    }
+}
+
+/// Return the declarations that the value of `decl` refers to outside of
+/// any abstraction, in order of first reference. Declarations with
+/// parameters are abstractions and refer to nothing.
+fn immediate_references<'a>(decl: &'a Declaration<'_>, index: &HashMap<&str, &Declaration<'_>>) -> Vec<&'a str> {
+    let mut refs = Vec::new();
+    if decl.params().is_empty() {
+        collect_references(decl.body(), &mut Vec::new(), index, &mut refs);
+    }
+    refs
+}
+
+fn collect_references<'a, A>(
+    node: &'a Node<'_, A>,
+    bound: &mut Vec<&'a str>,
+    index: &HashMap<&str, &Declaration<'_>>,
+    refs: &mut Vec<&'a str>,
+) {
+    match node.kind() {
+        NodeKind::Name { name, .. } => {
+            let name = name.as_ref();
+            if !bound.contains(&name) && index.contains_key(name) && !refs.contains(&name) {
+                refs.push(name);
+            }
+        }
+        NodeKind::Abs { .. } => {}
+        NodeKind::Let { bindings, body } => {
+            // The bindings are not recursive, so only the body is in their
+            // scope.
+            for (_, value) in bindings {
+                collect_references(value, bound, index, refs);
+            }
+            let len = bound.len();
+            bound.extend(bindings.iter().map(|(param, _)| param.binder_name()));
+            collect_references(body, bound, index, refs);
+            bound.truncate(len);
+        }
+        NodeKind::Comprehension { body, generators, guards } => {
+            let len = bound.len();
+            for (param, list) in generators {
+                collect_references(list, bound, index, refs);
+                bound.push(param.binder_name());
+            }
+            for child in guards.iter().chain([body]) {
+                collect_references(child, bound, index, refs);
+            }
+            bound.truncate(len);
+        }
+        kind => {
+            for child in kind.children() {
+                collect_references(child, bound, index, refs);
+            }
+        }
+    }
+}
+
+/// Search for a path of references from `from` to `target`, appending the
+/// declarations along the way, not including `target`, to `path`.
+fn path_to<'a>(
+    from: &'a str,
+    target: &str,
+    deps: &HashMap<&'a str, Vec<&'a str>>,
+    visited: &mut HashSet<&'a str>,
+    path: &mut Vec<&'a str>,
+) -> bool {
+    for &next in &deps[from] {
+        if next == target {
+            return true;
+        }
+        if visited.insert(next) {
+            path.push(next);
+            if path_to(next, target, deps, visited, path) {
+                return true;
+            }
+            path.pop();
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{diagnostic::Severity, parser::Parser};
+
+    fn check(input: &str) -> Vec<(Severity, Span, String)> {
+        let mut parser = Parser::new(input).expect("scanning example input");
+        let prog = parser.parse_program().expect("parsing example input");
+        detect_immediate_cycles(&prog)
+            .into_iter()
+            .map(|d| (d.severity, d.span, d.message))
+            .collect()
+    }
+
+    #[test]
+    fn capture_risk() {
+        let check = |input: &str| {
+            let mut parser = Parser::new(input).expect("scanning example input");
+            has_capture_risk(&parser.parse_script().expect("parsing example input"))
+        };
+        assert_eq!(check("\\x. \\x. x"), [Span::new(5, 6)]);
+        assert_eq!(check("\\x. (\\y. y) (\\y. x)"), []);
+        assert_eq!(check("\\x. x (\\y. \\z. z y)"), []);
+        assert_eq!(check("let x = 1; y = \\y. y in \\x. x"), [Span::new(25, 26)]);
+        assert_eq!(check("let x = 1; x = 2 in x"), [Span::new(11, 12)]);
+        // Free names are not binders.
+        assert_eq!(check("\\x. f (\\f. f)"), []);
+    }
+
+    #[test]
+    fn unbound() {
+        let check = |input: &str| {
+            let mut parser = Parser::new(input).expect("scanning example input");
+            unbound_names(&parser.parse_program().expect("parsing example input"))
+        };
+        assert_eq!(
+            check("main = print (f 1);\nf x = foo x + g;\ng = 2;"),
+            [Spanned::new("foo".to_string(), Span::new(26, 29))]
+        );
+        assert_eq!(check("f = \\x. let y = x in [z + y | z <- [y]];"), []);
+        // `let` is not recursive.
+        assert_eq!(
+            check("f = let x = x in x;"),
+            [Spanned::new("x".to_string(), Span::new(12, 13))]
+        );
+    }
+
+    #[test]
+    fn immediate_cycles() {
+        assert_eq!(
+            check("x = x;"),
+            [(
+                Severity::Error,
+                Span::new(0, 6),
+                "x refers to itself outside of any abstraction: x -> x".into()
+            )]
+        );
+        assert_eq!(
+            check("main = a; a = b + 1;\nb = a;"),
+            [
+                (
+                    Severity::Error,
+                    Span::new(10, 20),
+                    "a refers to itself outside of any abstraction: a -> b -> a".into()
+                ),
+                (Severity::Hint, Span::new(21, 27), "b is part of the cycle".into()),
+            ]
+        );
+
+        // Guarded by an abstraction, explicitly or through parameters.
+        assert!(check("f = \\n. f n; main = f 1;").is_empty());
+        assert!(check("g n = g n; h = \\x. k; k = h 1;").is_empty());
+        // Local names shadow declarations.
+        assert!(check("x = let x = 1 in x; y = (\\y. y) 2;").is_empty());
+        assert_eq!(check("x = let y = x in y;").len(), 1);
+    }
+}
@@ -0,0 +1,174 @@
+use crate::{
+    parser::ParseError,
+    scanner::ScanError,
+    span::{line_col, render_snippet, Span},
+};
+
+/// How serious a problem reported by a `Diagnostic` is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum Severity {
+    /// The input is invalid.
+    Error,
+    /// The input is valid, but probably not what was intended.
+    Warning,
+    /// Additional information about another diagnostic.
+    Hint,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => "error".fmt(f),
+            Severity::Warning => "warning".fmt(f),
+            Severity::Hint => "hint".fmt(f),
+        }
+    }
+}
+
+/// A problem found in the input, as reported by the APIs that collect
+/// several problems instead of stopping at the first one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(dead_code)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub message: String,
+    pub severity: Severity,
+}
+
+#[allow(dead_code)]
+impl Diagnostic {
+    pub fn error(span: Span, message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            span,
+            message: message.into(),
+            severity: Severity::Error,
+        }
+    }
+
+    pub fn warning(span: Span, message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            span,
+            message: message.into(),
+            severity: Severity::Warning,
+        }
+    }
+
+    pub fn hint(span: Span, message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            span,
+            message: message.into(),
+            severity: Severity::Hint,
+        }
+    }
+
+    /// Show the line of `source` where the problem starts, with a caret
+    /// under it. `source` must be the input the diagnostic refers to.
+    pub fn render(&self, source: &str) -> String {
+        render_snippet(source, self.span.start)
+    }
+}
+
+/// Prints the severity followed by the message, e.g. `error: ...`.
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+/// Format diagnostics the way GCC and Clang do, one line per diagnostic in
+/// the form `file:line:col: error: message`, so that editors can jump to
+/// them. Lines and columns are 1-based, and hints are reported as `note`.
+/// `source` must be the contents of `file`.
+#[allow(dead_code)]
+pub fn format_gnu(file: &str, source: &str, diags: &[Diagnostic]) -> String {
+    let mut output = String::new();
+    for diag in diags {
+        let (line, column) = line_col(source, diag.span.start);
+        let severity = match diag.severity {
+            Severity::Hint => "note",
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        output.push_str(&format!("{file}:{line}:{column}: {severity}: {}\n", diag.message));
+    }
+    output
+}
+
+/// Scan errors are always errors. Errors that only know their offset get
+/// an empty span at that offset.
+impl From<&ScanError> for Diagnostic {
+    fn from(e: &ScanError) -> Diagnostic {
+        let span = match e {
+            ScanError::ExpectedEndOfInput { span, .. } => *span,
+            _ => Span::new(e.offset(), e.offset()),
+        };
+        Diagnostic::error(span, e.to_string())
+    }
+}
+
+/// Parse errors are always errors, with spans like scan errors.
+impl From<&ParseError> for Diagnostic {
+    fn from(e: &ParseError) -> Diagnostic {
+        let span = match e {
+            ParseError::ScanError(e) => return e.into(),
+            ParseError::UnexpectedKeywordInExpression { span, .. } => *span,
+            _ => Span::new(e.offset(), e.offset()),
+        };
+        Diagnostic::error(span, e.to_string())
+    }
+}
+
+impl From<ParseError> for Diagnostic {
+    fn from(e: ParseError) -> Diagnostic {
+        Diagnostic::from(&e)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn gnu_format() {
+        let source = "id x = x;\nmain = id (;\nid = 1;";
+        let diags = Parser::new(source)
+            .expect("scanning example input")
+            .parse_program_recovering();
+        assert_eq!(
+            format_gnu("prog.lc", source, &diags),
+            "prog.lc:2:12: error: expected expression at offset 21, found ';' instead\n\
+             prog.lc:3:1: warning: id is defined more than once\n\
+             prog.lc:1:1: note: previous definition is here\n"
+        );
+        assert_eq!(format_gnu("prog.lc", source, &[]), "");
+    }
+
+    #[test]
+    fn from_errors() {
+        let scan_error = ScanError::UnexpectedCharacter { offset: 2, unexpected: '$' };
+        assert_eq!(
+            Diagnostic::from(&scan_error),
+            Diagnostic::error(Span::new(2, 2), "unexpected character '$' at offset 2")
+        );
+        let end = ScanError::ExpectedEndOfInput {
+            span: Span::new(4, 6),
+            found: crate::token::TokenKind::Identifier,
+        };
+        assert_eq!(Diagnostic::from(&end).span, Span::new(4, 6));
+
+        let parse = |input| Parser::new(input).and_then(|mut p| p.parse_script()).map(|_| ()).unwrap_err();
+        assert_eq!(
+            Diagnostic::from(&parse("f (")),
+            Diagnostic::error(Span::new(3, 3), "expected expression at offset 3, found end of input instead")
+        );
+        let keyword = Diagnostic::from(&parse("1 + end"));
+        assert_eq!(keyword.span, Span::new(4, 7));
+        assert_eq!(keyword.severity, Severity::Error);
+        // Scan errors found while parsing keep their spans.
+        let error = parse("f \"abc");
+        assert_eq!(Diagnostic::from(&error).span, Span::new(6, 6));
+        assert_eq!(Diagnostic::from(&error).message, error.to_string());
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{parser::ParseError, scanner::ScanError};
+use crate::{eval::EvalError, parser::ParseError, scanner::ScanError};
 
 #[derive(Debug)]
 #[allow(dead_code)]
@@ -6,9 +6,22 @@ pub enum Error {
     Io(std::io::Error),
     Scan(ScanError),
     Parse(ParseError),
+    Eval(EvalError),
     Other(String),
 }
 
+impl Error {
+    /// Describe the error. For errors with a position in `source`, this is
+    /// followed by the offending line with a caret under the position.
+    pub fn render(&self, source: &str) -> String {
+        match self {
+            Error::Scan(e) => format!("{e}\n{}", e.render(source)),
+            Error::Parse(e) => format!("{e}\n{}", e.render(source)),
+            _ => self.to_string(),
+        }
+    }
+}
+
 impl std::error::Error for Error {}
 
 impl std::fmt::Display for Error {
@@ -17,6 +30,7 @@ impl std::fmt::Display for Error {
             Error::Io(e) => e.fmt(f),
             Error::Scan(e) => e.fmt(f),
             Error::Parse(e) => e.fmt(f),
+            Error::Eval(e) => e.fmt(f),
             Error::Other(s) => s.fmt(f),
         }
     }
@@ -40,6 +54,12 @@ impl From<ParseError> for Error {
     }
 }
 
+impl From<EvalError> for Error {
+    fn from(e: EvalError) -> Error {
+        Error::Eval(e)
+    }
+}
+
 impl From<String> for Error {
     fn from(e: String) -> Error {
         Error::Other(e)
@@ -0,0 +1,1579 @@
+use std::{collections::HashMap, io::Write, rc::Rc};
+
+use crate::{
+    ast::{
+        substitute, substitute_cached, Arm, BinOp, Binding, FreeVarCache, Literal, Node, NodeKind, Program,
+        ProgramExt,
+    },
+    error::Error,
+    intern::{Interner, NameId},
+    parser::Parser,
+    span::Span,
+};
+
+/// Result of evaluating an expression.
+#[derive(Debug, Clone)]
+pub enum Value<'src, Anno> {
+    Int(i64),
+    String(String),
+    Unit,
+    /// An abstraction in normal form.
+    Closure(Rc<Node<'src, Anno>>),
+    List(Vec<Value<'src, Anno>>),
+}
+
+impl<'src, Anno> PartialEq for Value<'src, Anno> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Int(l), Value::Int(r)) => l == r,
+            (Value::String(l), Value::String(r)) => l == r,
+            (Value::Unit, Value::Unit) => true,
+            (Value::Closure(l), Value::Closure(r)) => Rc::ptr_eq(l, r),
+            (Value::List(l), Value::List(r)) => l == r,
+            _ => false,
+        }
+    }
+}
+
+impl<'src, Anno> std::fmt::Display for Value<'src, Anno> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Int(i) => i.fmt(f),
+            Value::String(s) => write!(f, "{s:?}"),
+            Value::Unit => "()".fmt(f),
+            Value::Closure(node) => node.fmt(f),
+            Value::List(values) => {
+                "[".fmt(f)?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        ", ".fmt(f)?;
+                    }
+                    value.fmt(f)?;
+                }
+                "]".fmt(f)
+            }
+        }
+    }
+}
+
+/// Reduction strategy, determining which terms count as values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum Strategy {
+    /// Arguments are reduced before they are substituted, and bodies of
+    /// abstractions are not reduced.
+    CallByValue,
+    /// The leftmost, outermost redex is reduced first, everywhere in the
+    /// term, including under abstractions.
+    NormalOrder,
+}
+
+#[allow(dead_code)]
+impl<'src, Anno> Node<'src, Anno> {
+    /// Return whether the term cannot be reduced any further with the given
+    /// strategy. For call-by-value, that is a literal, an abstraction, or a
+    /// free variable applied to values. For normal order, it is a term in
+    /// normal form.
+    pub fn is_value(&self, strategy: Strategy) -> bool {
+        match strategy {
+            Strategy::CallByValue => match self.kind() {
+                NodeKind::Lit { .. } | NodeKind::Abs { .. } => true,
+                NodeKind::List { elems } => elems.iter().all(|elem| elem.is_value(strategy)),
+                _ => {
+                    let (head, args) = self.app_spine();
+                    matches!(head.kind(), NodeKind::Name { .. } | NodeKind::BoundVar { .. })
+                        && args.iter().all(|arg| arg.is_value(strategy))
+                }
+            },
+            Strategy::NormalOrder => match self.kind() {
+                NodeKind::Name { .. } | NodeKind::Lit { .. } | NodeKind::BoundVar { .. } => true,
+                NodeKind::Abs { body, .. } => body.is_value(strategy),
+                NodeKind::App { fun, arg } => {
+                    !matches!(fun.kind(), NodeKind::Abs { .. })
+                        && fun.is_value(strategy)
+                        && arg.is_value(strategy)
+                }
+                // Arithmetic is stuck if an operand is not a literal.
+                NodeKind::BinOp { lhs, rhs, .. } => {
+                    !(matches!(lhs.kind(), NodeKind::Lit { .. })
+                        && matches!(rhs.kind(), NodeKind::Lit { .. }))
+                        && lhs.is_value(strategy)
+                        && rhs.is_value(strategy)
+                }
+                // A conditional is stuck if the condition is not a literal.
+                NodeKind::If { cond, conseq, alt } => {
+                    !matches!(cond.kind(), NodeKind::Lit { .. })
+                        && cond.is_value(strategy)
+                        && conseq.is_value(strategy)
+                        && alt.is_value(strategy)
+                }
+                // So is a multi-way conditional whose first condition is
+                // not a literal.
+                NodeKind::MultiIf { arms, .. } => {
+                    arms.first().is_some_and(|(cond, _)| !matches!(cond.kind(), NodeKind::Lit { .. }))
+                        && self.kind().children().iter().all(|child| child.is_value(strategy))
+                }
+                NodeKind::List { elems } => elems.iter().all(|elem| elem.is_value(strategy)),
+                NodeKind::Borrow { .. }
+                | NodeKind::Ann { .. }
+                | NodeKind::Let { .. }
+                | NodeKind::Seq { .. }
+                | NodeKind::Comprehension { .. } => false,
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+#[allow(dead_code)]
+pub enum EvalError {
+    UnboundVariable { name: String },
+    DivisionByZero { offset: usize },
+    Overflow { offset: usize },
+    Unsupported { offset: usize },
+    NoRedexAt { span: Span },
+    OutOfFuel,
+    NotALiteral { offset: usize },
+    InvalidArgument { builtin: String, offset: usize },
+    InvalidCondition { offset: usize },
+    NotAList { offset: usize },
+    NoMatchingArm { offset: usize },
+    Output(std::io::Error),
+}
+
+impl std::error::Error for EvalError {}
+
+impl std::fmt::Display for EvalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EvalError::UnboundVariable { name } => write!(f, "unbound variable {name}"),
+            EvalError::DivisionByZero { offset } => {
+                write!(f, "division by zero at offset {offset}")
+            }
+            EvalError::Overflow { offset } => {
+                write!(f, "arithmetic overflow at offset {offset}")
+            }
+            EvalError::Unsupported { offset } => {
+                write!(f, "cannot evaluate expression at offset {offset}")
+            }
+            EvalError::NoRedexAt { span } => write!(f, "no reducible expression at {span}"),
+            EvalError::OutOfFuel => "evaluation did not finish within its fuel budget".fmt(f),
+            EvalError::NotALiteral { offset } => {
+                write!(f, "result at offset {offset} is not a literal")
+            }
+            EvalError::InvalidArgument { builtin, offset } => {
+                write!(f, "invalid argument to builtin {builtin} at offset {offset}")
+            }
+            EvalError::Output(e) => write!(f, "cannot write output: {e}"),
+            EvalError::InvalidCondition { offset } => {
+                write!(f, "condition at offset {offset} is not an integer")
+            }
+            EvalError::NotAList { offset } => {
+                write!(f, "generator at offset {offset} does not range over a list")
+            }
+            EvalError::NoMatchingArm { offset } => {
+                write!(f, "no arm of the conditional at offset {offset} matches")
+            }
+        }
+    }
+}
+
+/// Evaluate an expression by reducing it to normal form. The result must be
+/// a literal or an abstraction, and the expression must not contain free
+/// variables.
+pub fn eval<'src, Anno: Clone>(node: &Rc<Node<'src, Anno>>) -> Result<Value<'src, Anno>, EvalError> {
+    if let Some(name) = free_name(node, &mut Vec::new()) {
+        return Err(EvalError::UnboundVariable { name });
+    }
+    to_value(normalize(node)?.term)
+}
+
+/// A builtin function. Once it is applied to `arity` arguments, these are
+/// reduced to literals and passed to `fun`, which may write to the output
+/// sink. `fun` returns `None` if it cannot handle the arguments.
+#[derive(Debug, Clone, Copy)]
+#[allow(dead_code)]
+pub struct Builtin {
+    pub arity: usize,
+    pub fun: fn(&[Literal], &mut dyn Write) -> std::io::Result<Option<Literal>>,
+}
+
+/// Table of builtins by name. Builtins are shadowed by local variables and
+/// by declarations of the same name.
+pub type Builtins = HashMap<&'static str, Builtin>;
+
+/// The builtins for output: `print` writes any literal and `putStrLn` a
+/// string, followed by a newline. Both return `()`.
+#[allow(dead_code)]
+pub fn io_builtins() -> Builtins {
+    fn print(args: &[Literal], out: &mut dyn Write) -> std::io::Result<Option<Literal>> {
+        match &args[0] {
+            Literal::String(s) => writeln!(out, "{s}")?,
+            lit => writeln!(out, "{lit}")?,
+        }
+        Ok(Some(Literal::Unit))
+    }
+    fn put_str_ln(args: &[Literal], out: &mut dyn Write) -> std::io::Result<Option<Literal>> {
+        let Literal::String(s) = &args[0] else {
+            return Ok(None);
+        };
+        writeln!(out, "{s}")?;
+        Ok(Some(Literal::Unit))
+    }
+    HashMap::from([
+        ("print", Builtin { arity: 1, fun: print }),
+        ("putStrLn", Builtin { arity: 1, fun: put_str_ln }),
+    ])
+}
+
+/// Evaluate an expression like `eval`, with the given builtins available.
+/// Everything the builtins write goes to `out`, so that the output can be
+/// captured.
+#[allow(dead_code)]
+pub fn eval_with_output<'src, Anno: Clone>(
+    node: &Rc<Node<'src, Anno>>,
+    builtins: &Builtins,
+    out: &mut dyn Write,
+) -> Result<Value<'src, Anno>, EvalError> {
+    let mut bound = builtins.keys().map(|name| name.to_string()).collect();
+    if let Some(name) = free_name(node, &mut bound) {
+        return Err(EvalError::UnboundVariable { name });
+    }
+    let mut interner = Interner::new();
+    let node = intern_names(node, &mut interner);
+    let mut machine = Machine::new(None, &mut interner, HashMap::new());
+    machine.builtins = Some((builtins, out));
+    to_value(machine.normalize(&node)?)
+}
+
+/// Convert a term in normal form to the value it denotes.
+fn to_value<'src, Anno>(node: Rc<Node<'src, Anno>>) -> Result<Value<'src, Anno>, EvalError> {
+    match node.kind() {
+        NodeKind::Lit { lit: Literal::Int(i) } => Ok(Value::Int(*i)),
+        NodeKind::Lit { lit: Literal::String(s) } => Ok(Value::String(s.clone())),
+        NodeKind::Lit { lit: Literal::Unit } => Ok(Value::Unit),
+        NodeKind::Abs { .. } => Ok(Value::Closure(node)),
+        NodeKind::List { elems } => Ok(Value::List(
+            elems.iter().map(|elem| to_value(elem.clone())).collect::<Result<_, _>>()?,
+        )),
+        _ => Err(EvalError::Unsupported {
+            offset: node.start(),
+        }),
+    }
+}
+
+/// Result of `normalize`: the term that reduction ended with, and whether
+/// it is a proper normal form.
+#[derive(Debug)]
+#[allow(dead_code)]
+pub struct NormResult<'src, Anno> {
+    pub term: Rc<Node<'src, Anno>>,
+    pub status: NormStatus,
+}
+
+/// How reduction ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum NormStatus {
+    /// The term is a normal form that cannot get stuck.
+    Normal,
+    /// The term cannot be reduced any further, but it still contains an
+    /// expression that cannot be evaluated, at `span`.
+    Stuck { span: Span, reason: StuckReason },
+    /// Reduction ran out of steps before reaching a normal form; the term is
+    /// the original one.
+    StepLimit,
+}
+
+/// Why a term is stuck.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum StuckReason {
+    /// A variable that is not bound anywhere.
+    Unbound { name: String },
+    /// A literal applied to arguments.
+    NotAFunction,
+    /// An operand or condition that is a value other than an integer.
+    NotAnInteger,
+}
+
+impl std::fmt::Display for StuckReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StuckReason::Unbound { name } => write!(f, "unbound variable {name}"),
+            StuckReason::NotAFunction => "literal applied to arguments".fmt(f),
+            StuckReason::NotAnInteger => "value is not an integer".fmt(f),
+        }
+    }
+}
+
+impl<'src, Anno> NormResult<'src, Anno> {
+    /// Classify a term that cannot be reduced any further.
+    fn new(term: Rc<Node<'src, Anno>>) -> Self {
+        let status = match stuck_at(&term, &mut Vec::new()) {
+            Some((span, reason)) => NormStatus::Stuck { span, reason },
+            None => NormStatus::Normal,
+        };
+        NormResult { term, status }
+    }
+}
+
+/// Reduce an expression to normal form, using normal-order reduction: the
+/// leftmost, outermost redex is always reduced first, so that arguments
+/// are only reduced when they are needed. Does not terminate for
+/// expressions without a normal form. The result tells whether the term
+/// got stuck, for example on a free variable.
+pub fn normalize<'src, Anno: Clone>(
+    node: &Rc<Node<'src, Anno>>,
+) -> Result<NormResult<'src, Anno>, EvalError> {
+    let mut interner = Interner::new();
+    let node = intern_names(node, &mut interner);
+    Ok(NormResult::new(Machine::new(None, &mut interner, HashMap::new()).normalize(&node)?))
+}
+
+/// Reduce an expression like `normalize`, but with at most `fuel`
+/// reductions. If that is not enough, the status is
+/// `NormStatus::StepLimit`.
+#[allow(dead_code)]
+pub fn normalize_fueled<'src, Anno: Clone>(
+    node: &Rc<Node<'src, Anno>>,
+    fuel: u64,
+) -> Result<NormResult<'src, Anno>, EvalError> {
+    let mut interner = Interner::new();
+    let interned = intern_names(node, &mut interner);
+    match Machine::new(Some(fuel), &mut interner, HashMap::new()).normalize(&interned) {
+        Ok(term) => Ok(NormResult::new(term)),
+        Err(EvalError::OutOfFuel) => Ok(NormResult {
+            term: node.clone(),
+            status: NormStatus::StepLimit,
+        }),
+        Err(e) => Err(e),
+    }
+}
+
+/// Return the position of the leftmost expression in a term in normal form
+/// that cannot be evaluated, and the reason why. `bound` holds the names
+/// bound by the enclosing abstractions.
+fn stuck_at<'a, Anno>(node: &'a Node<'_, Anno>, bound: &mut Vec<&'a str>) -> Option<(Span, StuckReason)> {
+    let not_integer = |operand: &Node<'_, Anno>| {
+        let value = match operand.kind() {
+            NodeKind::Lit { lit } => !matches!(lit, Literal::Int(_)),
+            kind => matches!(kind, NodeKind::Abs { .. } | NodeKind::List { .. }),
+        };
+        value.then(|| (operand.span(), StuckReason::NotAnInteger))
+    };
+    match node.kind() {
+        NodeKind::Name { name, .. } if !bound.contains(&name.as_ref()) => Some((
+            node.span(),
+            StuckReason::Unbound {
+                name: name.to_string(),
+            },
+        )),
+        NodeKind::Abs { param, body } => {
+            bound.push(param.binder_name());
+            let result = stuck_at(body, bound);
+            bound.pop();
+            result
+        }
+        NodeKind::App { .. } if matches!(node.app_spine().0.kind(), NodeKind::Lit { .. }) => {
+            Some((node.span(), StuckReason::NotAFunction))
+        }
+        NodeKind::BinOp { lhs, rhs, .. } => stuck_at(lhs, bound)
+            .or_else(|| stuck_at(rhs, bound))
+            .or_else(|| not_integer(lhs))
+            .or_else(|| not_integer(rhs)),
+        NodeKind::If { cond, conseq, alt } => stuck_at(cond, bound)
+            .or_else(|| not_integer(cond))
+            .or_else(|| stuck_at(conseq, bound))
+            .or_else(|| stuck_at(alt, bound)),
+        NodeKind::MultiIf { arms, otherwise } => arms
+            .iter()
+            .find_map(|(cond, body)| {
+                stuck_at(cond, bound)
+                    .or_else(|| not_integer(cond))
+                    .or_else(|| stuck_at(body, bound))
+            })
+            .or_else(|| otherwise.as_ref().and_then(|otherwise| stuck_at(otherwise, bound))),
+        NodeKind::Comprehension { body, generators, guards } => {
+            let depth = bound.len();
+            let mut result = None;
+            for (name, list) in generators {
+                result = result.or_else(|| stuck_at(list, bound));
+                bound.push(name.binder_name());
+            }
+            let result = result
+                .or_else(|| guards.iter().find_map(|guard| stuck_at(guard, bound).or_else(|| not_integer(guard))))
+                .or_else(|| stuck_at(body, bound));
+            bound.truncate(depth);
+            result
+        }
+        kind => kind.children().into_iter().find_map(|child| stuck_at(child, bound)),
+    }
+}
+
+/// Reduce `main` of a program to normal form like `normalize`, unfolding
+/// the other declarations of the program when they are needed. Each
+/// reduction and each unfolding of a declaration uses one unit of `fuel`,
+/// and evaluation fails with `EvalError::OutOfFuel` once it is used up, so
+/// that evaluation terminates even for programs without a normal form.
+/// Names declared more than once refer to their first declaration.
+#[allow(dead_code)]
+pub fn eval_program_fueled<'src>(
+    prog: &Program<'src>,
+    fuel: u64,
+) -> Result<Rc<Node<'src, ()>>, EvalError> {
+    let mut interner = Interner::new();
+    let globals = prog
+        .index()
+        .into_iter()
+        .map(|(name, decl)| (interner.intern(name), intern_names(&decl.desugar(), &mut interner)))
+        .collect();
+    eval_globals(prog, &mut interner, globals, fuel)
+}
+
+/// Evaluate a program like `eval_program_fueled`, identifying its names by
+/// their ids in `interner`. The interner can be the one that parsed the
+/// program, see `Parser::into_interner`, so that the names are not interned
+/// again.
+#[allow(dead_code)]
+pub fn eval_program_interned<'src>(
+    prog: &Program<'src>,
+    interner: &mut Interner,
+    fuel: u64,
+) -> Result<Rc<Node<'src, ()>>, EvalError> {
+    let globals = prog
+        .index()
+        .into_iter()
+        .map(|(name, decl)| (interner.intern(name), decl.desugar()))
+        .collect();
+    eval_globals(prog, interner, globals, fuel)
+}
+
+/// Implementation of `eval_program_fueled` and `eval_program_interned`,
+/// with the declarations of the program by the ids of their names.
+fn eval_globals<'src>(
+    prog: &Program<'src>,
+    interner: &mut Interner,
+    globals: HashMap<NameId, Rc<Node<'src, ()>>>,
+    fuel: u64,
+) -> Result<Rc<Node<'src, ()>>, EvalError> {
+    let mut bound = prog.iter().map(|decl| decl.name().to_string()).collect();
+    for decl in prog {
+        if let Some(name) = free_name(&globals[&interner.intern(decl.name())], &mut bound) {
+            return Err(EvalError::UnboundVariable { name });
+        }
+    }
+    let main = globals.get(&interner.intern("main")).cloned().ok_or(EvalError::UnboundVariable {
+        name: "main".into(),
+    })?;
+    Machine::new(Some(fuel), interner, globals).normalize(&main)
+}
+
+/// Return a copy of the tree in which every name has its id in
+/// `interner`, for trees whose ids, if any, are from another interner.
+fn intern_names<'src, Anno: Clone>(node: &Rc<Node<'src, Anno>>, interner: &mut Interner) -> Rc<Node<'src, Anno>> {
+    let kind = match node.kind() {
+        NodeKind::Name { name, .. } => NodeKind::Name {
+            name: name.clone(),
+            id: Some(interner.intern(name)),
+        },
+        kind => kind.map_children(|child| intern_names(child, interner)),
+    };
+    node.with_kind(kind)
+}
+
+/// Parse a program and evaluate its `main` with `eval_program_fueled`. The
+/// normal form of `main` must be a literal.
+#[allow(dead_code)]
+pub fn run_main(input: &str, fuel: u64) -> Result<Literal, Error> {
+    let prog = Parser::new(input)?.parse_program()?;
+    let result = eval_program_fueled(&prog, fuel)?;
+    match result.kind() {
+        NodeKind::Lit { lit } => Ok(lit.clone()),
+        _ => Err(EvalError::NotALiteral {
+            offset: result.start(),
+        }
+        .into()),
+    }
+}
+
+/// State of a reduction to normal form.
+struct Machine<'p, 'src, Anno> {
+    /// Number of reductions left, unlimited if `None`.
+    fuel: Option<u64>,
+    /// Ids of the names of declarations and bound variables.
+    interner: &'p mut Interner,
+    /// Declarations that names refer to unless they are bound locally.
+    globals: HashMap<NameId, Rc<Node<'src, Anno>>>,
+    /// Names bound by the abstractions whose bodies are being reduced.
+    bound: Vec<NameId>,
+    /// Fresh names that refer to declarations, by the name of the
+    /// declaration. An unfolded declaration refers to the others by these
+    /// names where the enclosing abstractions bind their own names.
+    aliases: HashMap<NameId, NameId>,
+    /// Builtins that free names refer to, and where they write their
+    /// output.
+    builtins: Option<(&'p Builtins, &'p mut dyn Write)>,
+    /// The values of the `let` bindings reduced so far, by address, with
+    /// their weak head normal form once it is known. Every reduction of a
+    /// `let` copies its values, and substitution shares the copy between
+    /// all uses of the binding, so that it is reduced at most once per
+    /// reduction of the `let`. The entries keep the copies alive, so that
+    /// their addresses cannot be reused by other nodes, and are removed
+    /// when the term whose normalization added them is normalized.
+    shared: HashMap<*const Node<'src, Anno>, SharedValue<'src, Anno>>,
+    /// The addresses of the entries of `shared`, in the order they were
+    /// added.
+    shared_order: Vec<*const Node<'src, Anno>>,
+    /// Free names of the terms that are substituted into.
+    free_vars: FreeVarCache<'src, Anno>,
+}
+
+/// A `let`-bound value and its weak head normal form, if already reduced.
+type SharedValue<'src, Anno> = (Rc<Node<'src, Anno>>, Option<Rc<Node<'src, Anno>>>);
+
+impl<'p, 'src, Anno: Clone> Machine<'p, 'src, Anno> {
+    fn new(fuel: Option<u64>, interner: &'p mut Interner, globals: HashMap<NameId, Rc<Node<'src, Anno>>>) -> Self {
+        Machine {
+            fuel,
+            interner,
+            globals,
+            bound: Vec::new(),
+            aliases: HashMap::new(),
+            builtins: None,
+            shared: HashMap::new(),
+            shared_order: Vec::new(),
+            free_vars: FreeVarCache::new(),
+        }
+    }
+
+    /// Use up one unit of fuel for a reduction.
+    fn step(&mut self) -> Result<(), EvalError> {
+        match &mut self.fuel {
+            Some(0) => Err(EvalError::OutOfFuel),
+            Some(fuel) => {
+                *fuel -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Implementation of `normalize`. The values of the `let`s reduced on
+    /// the way are released at the end, when all their uses are reduced.
+    fn normalize(&mut self, node: &Rc<Node<'src, Anno>>) -> Result<Rc<Node<'src, Anno>>, EvalError> {
+        let mark = self.shared_order.len();
+        let result = self.normalize_shared(node);
+        for ptr in self.shared_order.drain(mark..) {
+            self.shared.remove(&ptr);
+        }
+        result
+    }
+
+    /// Implementation of `normalize`, keeping the shared values.
+    fn normalize_shared(&mut self, node: &Rc<Node<'src, Anno>>) -> Result<Rc<Node<'src, Anno>>, EvalError> {
+        let node = self.whnf(node)?;
+        match node.kind() {
+            NodeKind::Abs { param, body } => {
+                let id = self.name_id(param);
+                self.bound.push(id);
+                let body = self.normalize(body);
+                self.bound.pop();
+                Ok(node.with_kind(NodeKind::Abs { param: param.clone(), body: body? }))
+            }
+            NodeKind::App { fun, arg } => {
+                let fun = self.normalize(fun)?;
+                let arg = self.normalize(arg)?;
+                Ok(node.with_kind(NodeKind::App { fun, arg }))
+            }
+            NodeKind::If { cond, conseq, alt } => {
+                let conseq = self.normalize(conseq)?;
+                let alt = self.normalize(alt)?;
+                Ok(node.with_kind(NodeKind::If { cond: cond.clone(), conseq, alt }))
+            }
+            NodeKind::MultiIf { arms, otherwise } => {
+                // The first condition is already normalized.
+                let arms = arms
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (cond, body))| {
+                        let cond = if i == 0 { cond.clone() } else { self.normalize(cond)? };
+                        Ok((cond, self.normalize(body)?))
+                    })
+                    .collect::<Result<_, _>>()?;
+                let otherwise = otherwise.as_ref().map(|otherwise| self.normalize(otherwise)).transpose()?;
+                Ok(node.with_kind(NodeKind::MultiIf { arms, otherwise }))
+            }
+            NodeKind::List { elems } => {
+                let elems = elems.iter().map(|elem| self.normalize(elem)).collect::<Result<_, _>>()?;
+                Ok(node.with_kind(NodeKind::List { elems }))
+            }
+            _ => Ok(node),
+        }
+    }
+
+    /// Reduce an expression until it is no longer a redex at the top: an
+    /// abstraction, a literal, a list, or an application whose head is not
+    /// an abstraction.
+    fn whnf(&mut self, node: &Rc<Node<'src, Anno>>) -> Result<Rc<Node<'src, Anno>>, EvalError> {
+        if self.shared.contains_key(&Rc::as_ptr(node)) {
+            return self.whnf_shared(node);
+        }
+        match node.kind() {
+            NodeKind::App { fun, arg } => {
+                let fun = self.whnf(fun)?;
+                match fun.kind() {
+                    NodeKind::Abs { param, body } => {
+                        self.step()?;
+                        let body = substitute_cached(body, param.binder_name(), arg, &mut self.free_vars);
+                        self.whnf(&body)
+                    }
+                    _ => self.apply_builtin(node.with_kind(NodeKind::App { fun, arg: arg.clone() })),
+                }
+            }
+            NodeKind::Let { bindings, body } => self.whnf_let(node, bindings, body),
+            NodeKind::Borrow { expr } | NodeKind::Ann { expr, .. } => self.whnf(expr),
+            NodeKind::Seq { first, second } => {
+                self.normalize(first)?;
+                self.whnf(second)
+            }
+            NodeKind::BinOp { op, op_span, lhs, rhs } => {
+                let lhs = self.normalize(lhs)?;
+                let rhs = self.normalize(rhs)?;
+                let (NodeKind::Lit { lit: Literal::Int(l) }, NodeKind::Lit { lit: Literal::Int(r) }) =
+                    (lhs.kind(), rhs.kind())
+                else {
+                    let op_span = *op_span;
+                    return Ok(node.with_kind(NodeKind::BinOp { op: *op, op_span, lhs, rhs }));
+                };
+                self.step()?;
+                let i = arithmetic(*op, *l, *r, node.start())?;
+                Ok(node.with_kind(NodeKind::Lit { lit: Literal::Int(i) }))
+            }
+            NodeKind::If { cond, conseq, alt } => self.whnf_if(node, cond, conseq, alt),
+            NodeKind::MultiIf { arms, otherwise } => self.whnf_multi_if(node, arms, otherwise),
+            NodeKind::Comprehension { .. } => self.whnf_comprehension(node),
+            NodeKind::Name { .. } => self.whnf_name(node),
+            NodeKind::Abs { .. }
+            | NodeKind::Lit { .. }
+            | NodeKind::BoundVar { .. }
+            | NodeKind::List { .. } => Ok(node.clone()),
+        }
+    }
+
+    /// Implementation of `whnf` for a `Name` node: unfold the declaration
+    /// it refers to, unless it is bound locally. Declarations that are
+    /// names themselves are unfolded in a loop, so that chains of them do
+    /// not use up the stack.
+    fn whnf_name(&mut self, node: &Rc<Node<'src, Anno>>) -> Result<Rc<Node<'src, Anno>>, EvalError> {
+        let mut node = node.clone();
+        loop {
+            let id = self.name_id(&node);
+            match self.global(id) {
+                Some(body) if !self.is_bound(id) => {
+                    self.step()?;
+                    let body = self.unfold(body);
+                    if !matches!(body.kind(), NodeKind::Name { .. }) {
+                        return self.whnf(&body);
+                    }
+                    node = body;
+                }
+                _ => return Ok(node),
+            }
+        }
+    }
+
+    /// Return the id of the name of a `Name` node. Names without one, like
+    /// the fresh names of substitution, are interned.
+    fn name_id(&mut self, node: &Node<'src, Anno>) -> NameId {
+        node.name_id().unwrap_or_else(|| self.interner.intern(node.binder_name()))
+    }
+
+    /// Return whether the name is bound by an enclosing abstraction.
+    fn is_bound(&self, id: NameId) -> bool {
+        self.bound.contains(&id)
+    }
+
+    /// Return the body of the declaration that a free name refers to,
+    /// directly or by an alias.
+    fn global(&self, id: NameId) -> Option<Rc<Node<'src, Anno>>> {
+        let id = self.aliases.get(&id).copied().unwrap_or(id);
+        self.globals.get(&id).cloned()
+    }
+
+    /// Prepare the body of a declaration for use under the enclosing
+    /// abstractions: the names of other declarations that the abstractions
+    /// bind, too, are replaced by aliases, so that they are not captured.
+    fn unfold(&mut self, body: Rc<Node<'src, Anno>>) -> Rc<Node<'src, Anno>> {
+        let mut body = body;
+        for i in 0..self.bound.len() {
+            let global = self.bound[i];
+            if self.bound[..i].contains(&global) || !self.globals.contains_key(&global) {
+                continue;
+            }
+            let name = self.interner.resolve(global);
+            if !self.free_vars.contains_free(&body, name) {
+                continue;
+            }
+            // `#` cannot occur in names in the source.
+            let alias = format!("{name}#{}", self.aliases.len());
+            body = body.rename_free(name, &alias);
+            let alias = self.interner.intern(&alias);
+            self.aliases.insert(alias, global);
+        }
+        body
+    }
+
+    /// Implementation of `whnf` for a comprehension `node`: collect the
+    /// body for every combination of generator elements that passes the
+    /// guards into a list. The elements are not reduced. If a list or a
+    /// guard is stuck, the comprehension is returned unchanged.
+    fn whnf_comprehension(&mut self, node: &Rc<Node<'src, Anno>>) -> Result<Rc<Node<'src, Anno>>, EvalError> {
+        let mut elems = Vec::new();
+        if !self.expand_comprehension(node, &mut elems)? {
+            return Ok(node.clone());
+        }
+        Ok(node.with_kind(NodeKind::List { elems }))
+    }
+
+    /// Reduce the list of the first generator of the comprehension `node`
+    /// and expand the rest of the comprehension once for each element,
+    /// substituted for the generator's name. Without generators, push the
+    /// body to `elems` if all guards are true. Returns `false` if a list or
+    /// a guard is stuck.
+    fn expand_comprehension(
+        &mut self,
+        node: &Rc<Node<'src, Anno>>,
+        elems: &mut Vec<Rc<Node<'src, Anno>>>,
+    ) -> Result<bool, EvalError> {
+        let NodeKind::Comprehension { body, generators, guards } = node.kind() else {
+            unreachable!("not a comprehension");
+        };
+        let Some(((param, list), generators)) = generators.split_first() else {
+            for guard in guards {
+                let cond = self.normalize(guard)?;
+                self.step()?;
+                match select_branch(&cond, &true, &false)? {
+                    Some(true) => {}
+                    Some(false) => return Ok(true),
+                    None => return Ok(false),
+                }
+            }
+            elems.push(body.clone());
+            return Ok(true);
+        };
+        let list = self.whnf(list)?;
+        let items = match list.kind() {
+            NodeKind::List { elems: items } => items,
+            NodeKind::Lit { .. } | NodeKind::Abs { .. } => {
+                return Err(EvalError::NotAList { offset: list.start() })
+            }
+            _ => return Ok(false),
+        };
+        let rest = node.with_kind(NodeKind::Comprehension {
+            body: body.clone(),
+            generators: generators.to_vec(),
+            guards: guards.clone(),
+        });
+        for item in items {
+            self.step()?;
+            let rest = substitute_cached(&rest, param.binder_name(), item, &mut self.free_vars);
+            if !self.expand_comprehension(&rest, elems)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Implementation of `whnf` for a `let` node. Simultaneous bindings
+    /// behave like an abstraction over all names, applied to all values.
+    fn whnf_let(
+        &mut self,
+        node: &Rc<Node<'src, Anno>>,
+        bindings: &[Binding<'src, Anno>],
+        body: &Rc<Node<'src, Anno>>,
+    ) -> Result<Rc<Node<'src, Anno>>, EvalError> {
+        // A `let` in the body of an abstraction is reduced once per
+        // application, with values that are not substituted into unchanged.
+        let values = bindings
+            .iter()
+            .map(|(_, value)| {
+                let copy = value.with_kind(value.kind().map_children(Rc::clone));
+                self.shared.insert(Rc::as_ptr(&copy), (copy.clone(), None));
+                self.shared_order.push(Rc::as_ptr(&copy));
+                copy
+            })
+            .collect::<Vec<_>>();
+        let fun = bindings.iter().rev().fold(body.clone(), |body, (param, _)| {
+            node.with_kind(NodeKind::Abs {
+                param: param.clone(),
+                body,
+            })
+        });
+        let app = values.into_iter().fold(fun, |fun, arg| node.with_kind(NodeKind::App { fun, arg }));
+        self.whnf(&app)
+    }
+
+    /// Implementation of `whnf` for the value of a `let` binding: reduce it
+    /// on first use and return the remembered result on later uses.
+    fn whnf_shared(&mut self, node: &Rc<Node<'src, Anno>>) -> Result<Rc<Node<'src, Anno>>, EvalError> {
+        let ptr = Rc::as_ptr(node);
+        if let Some((_, Some(result))) = self.shared.get(&ptr) {
+            return Ok(result.clone());
+        }
+        // Without the entry, `whnf` reduces the value itself.
+        let (value, _) = self.shared.remove(&ptr).expect("value is shared");
+        let result = self.whnf(node)?;
+        self.shared.insert(ptr, (value, Some(result.clone())));
+        Ok(result)
+    }
+
+    /// Implementation of `whnf` for a conditional `node`: reduce the
+    /// condition and then the branch it selects.
+    fn whnf_if(
+        &mut self,
+        node: &Rc<Node<'src, Anno>>,
+        cond: &Rc<Node<'src, Anno>>,
+        conseq: &Rc<Node<'src, Anno>>,
+        alt: &Rc<Node<'src, Anno>>,
+    ) -> Result<Rc<Node<'src, Anno>>, EvalError> {
+        let cond = self.normalize(cond)?;
+        match select_branch(&cond, conseq, alt)? {
+            Some(branch) => {
+                self.step()?;
+                self.whnf(branch)
+            }
+            None => Ok(node.with_kind(NodeKind::If {
+                cond,
+                conseq: conseq.clone(),
+                alt: alt.clone(),
+            })),
+        }
+    }
+
+    /// Implementation of `whnf` for a multi-way conditional `node`: reduce
+    /// the conditions in order until one is true, and then its body. If a
+    /// condition is stuck, return the conditional from that arm on.
+    fn whnf_multi_if(
+        &mut self,
+        node: &Rc<Node<'src, Anno>>,
+        arms: &[Arm<'src, Anno>],
+        otherwise: &Option<Rc<Node<'src, Anno>>>,
+    ) -> Result<Rc<Node<'src, Anno>>, EvalError> {
+        for (i, (cond, body)) in arms.iter().enumerate() {
+            let cond = self.normalize(cond)?;
+            match select_branch(&cond, &true, &false)? {
+                Some(true) => {
+                    self.step()?;
+                    return self.whnf(body);
+                }
+                Some(false) => self.step()?,
+                None => {
+                    let arms = [(cond, body.clone())].into_iter().chain(arms[i + 1..].iter().cloned()).collect();
+                    return Ok(node.with_kind(NodeKind::MultiIf {
+                        arms,
+                        otherwise: otherwise.clone(),
+                    }));
+                }
+            }
+        }
+        match otherwise {
+            Some(otherwise) => self.whnf(otherwise),
+            None => Err(EvalError::NoMatchingArm { offset: node.start() }),
+        }
+    }
+
+    /// Run a builtin if `node` applies one to as many arguments as it
+    /// takes, and return its result. Any other application is returned
+    /// unchanged.
+    fn apply_builtin(&mut self, node: Rc<Node<'src, Anno>>) -> Result<Rc<Node<'src, Anno>>, EvalError> {
+        let mut head = &node;
+        let mut args = Vec::new();
+        while let NodeKind::App { fun, arg } = head.kind() {
+            args.push(arg.clone());
+            head = fun;
+        }
+        args.reverse();
+        let NodeKind::Name { name, .. } = head.kind() else {
+            return Ok(node);
+        };
+        let id = self.name_id(head);
+        let builtin = match &self.builtins {
+            Some((builtins, _)) if !self.is_bound(id) => builtins.get(name.as_ref()).copied(),
+            _ => None,
+        };
+        let Some(builtin) = builtin.filter(|builtin| builtin.arity == args.len()) else {
+            return Ok(node);
+        };
+        let mut lits = Vec::with_capacity(args.len());
+        for arg in &args {
+            let arg = self.normalize(arg)?;
+            let NodeKind::Lit { lit } = arg.kind() else {
+                return Err(EvalError::InvalidArgument {
+                    builtin: name.to_string(),
+                    offset: arg.start(),
+                });
+            };
+            lits.push(lit.clone());
+        }
+        self.step()?;
+        let (_, out) = self.builtins.as_mut().expect("builtins are present");
+        match (builtin.fun)(&lits, *out).map_err(EvalError::Output)? {
+            Some(lit) => Ok(node.with_kind(NodeKind::Lit { lit })),
+            None => Err(EvalError::InvalidArgument {
+                builtin: name.to_string(),
+                offset: node.start(),
+            }),
+        }
+    }
+}
+
+/// Apply a binary operator to two integers. Comparisons give 1 if they
+/// hold and 0 otherwise. `offset` is the position of the operation, for
+/// error messages.
+fn arithmetic(op: BinOp, l: i64, r: i64, offset: usize) -> Result<i64, EvalError> {
+    let result = match op {
+        BinOp::Add => l.checked_add(r),
+        BinOp::Sub => l.checked_sub(r),
+        BinOp::Mul => l.checked_mul(r),
+        BinOp::Div if r == 0 => return Err(EvalError::DivisionByZero { offset }),
+        BinOp::Div => l.checked_div(r),
+        BinOp::Eq => Some(i64::from(l == r)),
+        BinOp::Lt => Some(i64::from(l < r)),
+        BinOp::Le => Some(i64::from(l <= r)),
+        BinOp::Gt => Some(i64::from(l > r)),
+        BinOp::Ge => Some(i64::from(l >= r)),
+    };
+    result.ok_or(EvalError::Overflow { offset })
+}
+
+/// Return the branch of a conditional that a literal condition selects: any
+/// integer but 0 selects `conseq`, and 0 selects `alt`. Returns `None` if the
+/// condition is not a literal.
+fn select_branch<'a, 'src, Anno, T>(
+    cond: &Node<'src, Anno>,
+    conseq: &'a T,
+    alt: &'a T,
+) -> Result<Option<&'a T>, EvalError> {
+    match cond.kind() {
+        NodeKind::Lit { lit: Literal::Int(0) } => Ok(Some(alt)),
+        NodeKind::Lit { lit: Literal::Int(_) } => Ok(Some(conseq)),
+        NodeKind::Lit { .. } => Err(EvalError::InvalidCondition {
+            offset: cond.start(),
+        }),
+        _ => Ok(None),
+    }
+}
+
+/// Reduce the redex with the given span, as returned by `redexes`, by one
+/// step, and leave the rest of the term alone. If several redexes have the
+/// span, the outermost and leftmost one is reduced.
+#[allow(dead_code)]
+pub fn reduce_at<'src, Anno: Clone>(
+    node: &Rc<Node<'src, Anno>>,
+    target: Span,
+) -> Result<Rc<Node<'src, Anno>>, EvalError> {
+    reduce_at_changed(node, target)?.ok_or(EvalError::NoRedexAt { span: target })
+}
+
+/// Implementation of `reduce_at`, returning `None` if no redex has the
+/// span.
+fn reduce_at_changed<'src, Anno: Clone>(
+    node: &Rc<Node<'src, Anno>>,
+    target: Span,
+) -> Result<Option<Rc<Node<'src, Anno>>>, EvalError> {
+    if node.span() == target {
+        match node.kind() {
+            NodeKind::App { fun, arg } => {
+                if let NodeKind::Abs { param, body } = fun.kind() {
+                    return Ok(Some(substitute(body, param.binder_name(), arg)));
+                }
+            }
+            NodeKind::BinOp { op, lhs, rhs, .. } => {
+                if let (NodeKind::Lit { lit: Literal::Int(l) }, NodeKind::Lit { lit: Literal::Int(r) }) =
+                    (lhs.kind(), rhs.kind())
+                {
+                    let i = arithmetic(*op, *l, *r, node.start())?;
+                    return Ok(Some(node.with_kind(NodeKind::Lit { lit: Literal::Int(i) })));
+                }
+            }
+            NodeKind::If { cond, conseq, alt } => {
+                if let Some(branch) = select_branch(cond, conseq, alt)? {
+                    return Ok(Some(branch.clone()));
+                }
+            }
+            NodeKind::MultiIf { arms, otherwise } => match arms.split_first() {
+                None => {
+                    let offset = node.start();
+                    return otherwise.clone().map(Some).ok_or(EvalError::NoMatchingArm { offset });
+                }
+                Some(((cond, body), arms)) => match select_branch(cond, &true, &false)? {
+                    Some(true) => return Ok(Some(body.clone())),
+                    Some(false) => {
+                        let otherwise = otherwise.clone();
+                        return Ok(Some(node.with_kind(NodeKind::MultiIf { arms: arms.to_vec(), otherwise })));
+                    }
+                    None => {}
+                },
+            },
+            _ => {}
+        }
+    }
+    let mut result = Ok(None);
+    let kind = node.kind().map_children(|child| {
+        if let Ok(None) = result {
+            match reduce_at_changed(child, target) {
+                Ok(Some(reduced)) => {
+                    result = Ok(Some(()));
+                    return reduced;
+                }
+                Ok(None) => {}
+                Err(e) => result = Err(e),
+            }
+        }
+        child.clone()
+    });
+    Ok(result?.map(|()| node.with_kind(kind)))
+}
+
+/// Return the spans of all redexes in the term, outermost and leftmost
+/// first: applications of abstractions, arithmetic operations on two
+/// integers, conditionals on a literal, and multi-way conditionals whose
+/// first condition is a literal or that have no arms left.
+#[allow(dead_code)]
+pub fn redexes<Anno>(node: &Rc<Node<'_, Anno>>) -> Vec<Span> {
+    let mut spans = Vec::new();
+    collect_redexes(node, &mut spans);
+    spans
+}
+
+/// Implementation of `redexes`.
+fn collect_redexes<Anno>(node: &Node<'_, Anno>, spans: &mut Vec<Span>) {
+    if is_redex(node) {
+        spans.push(node.span());
+    }
+    for child in node.kind().children() {
+        collect_redexes(child, spans);
+    }
+}
+
+/// Return whether the node can be reduced in one step by itself.
+fn is_redex<Anno>(node: &Node<'_, Anno>) -> bool {
+    match node.kind() {
+        NodeKind::App { fun, .. } => matches!(fun.kind(), NodeKind::Abs { .. }),
+        NodeKind::BinOp { lhs, rhs, .. } => {
+            matches!(lhs.kind(), NodeKind::Lit { lit: Literal::Int(_) })
+                && matches!(rhs.kind(), NodeKind::Lit { lit: Literal::Int(_) })
+        }
+        NodeKind::If { cond, .. } => matches!(cond.kind(), NodeKind::Lit { .. }),
+        NodeKind::MultiIf { arms, .. } => {
+            arms.first().is_none_or(|(cond, _)| matches!(cond.kind(), NodeKind::Lit { .. }))
+        }
+        _ => false,
+    }
+}
+
+/// Return the leftmost name in `node` that is not bound by an enclosing
+/// abstraction or `let`, or one of the names in `bound`.
+fn free_name<'src, Anno>(node: &Node<'src, Anno>, bound: &mut Vec<String>) -> Option<String> {
+    match node.kind() {
+        NodeKind::Name { name, .. } if bound.iter().any(|b| b == name) => None,
+        NodeKind::Name { name, .. } => Some(name.to_string()),
+        NodeKind::Abs { param, body } => {
+            bound.push(param.binder_name().to_string());
+            let result = free_name(body, bound);
+            bound.pop();
+            result
+        }
+        NodeKind::Let { bindings, body } => bindings
+            .iter()
+            .find_map(|(_, value)| free_name(value, bound))
+            .or_else(|| {
+                let depth = bound.len();
+                bound.extend(bindings.iter().map(|(name, _)| name.binder_name().to_string()));
+                let result = free_name(body, bound);
+                bound.truncate(depth);
+                result
+            }),
+        NodeKind::Comprehension { body, generators, guards } => {
+            let depth = bound.len();
+            let mut result = None;
+            for (name, list) in generators {
+                result = free_name(list, bound);
+                if result.is_some() {
+                    break;
+                }
+                bound.push(name.binder_name().to_string());
+            }
+            let result = result.or_else(|| guards.iter().chain([body]).find_map(|child| free_name(child, bound)));
+            bound.truncate(depth);
+            result
+        }
+        kind => kind.children().into_iter().find_map(|child| free_name(child, bound)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run(input: &str) -> Result<Value<'_, ()>, EvalError> {
+        let mut parser = Parser::new(input).expect("scanning example input");
+        let expr = parser.parse_script().expect("parsing example input");
+        eval(&expr)
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(run("1 + 2 * 3").expect("evaluating"), Value::Int(7));
+        assert_eq!(run("8 - 4 - 2").expect("evaluating"), Value::Int(2));
+        assert_eq!(run("7 / 2").expect("evaluating"), Value::Int(3));
+        assert!(matches!(run("1 / 0"), Err(EvalError::DivisionByZero { offset: 0 })));
+        assert!(matches!(
+            run("9223372036854775807 + 1"),
+            Err(EvalError::Overflow { offset: 0 })
+        ));
+        assert_eq!(run("&2 * 3").expect("evaluating"), Value::Int(6));
+        assert!(matches!(run("x + 1"), Err(EvalError::UnboundVariable { name }) if name == "x"));
+    }
+
+    fn reduce(input: &str) -> String {
+        let mut parser = Parser::new(input).expect("scanning example input");
+        let expr = parser.parse_script().expect("parsing example input");
+        normalize(&expr).expect("reducing").term.to_string()
+    }
+
+    #[test]
+    fn beta_reduction() {
+        assert_eq!(reduce("(\\ x . x) a"), "a");
+        assert_eq!(reduce("(\\ x y . x) a b"), "a");
+        assert_eq!(reduce("(\\ f . f (f a)) (\\ x . g x)"), "g (g a)");
+        assert_eq!(reduce("\\ y . (\\ x . x) y"), "\\ y. y");
+        // Normal order does not reduce arguments that are not needed.
+        assert_eq!(reduce("(\\ x . a) ((\\ x . x x) (\\ x . x x))"), "a");
+        assert!(matches!(run("(\\ x . x) a"), Err(EvalError::UnboundVariable { name }) if name == "a"));
+        assert!(matches!(run("\\ x . x"), Ok(Value::Closure(_))));
+        assert_eq!(run("(\\ x . x + 1) 2").expect("evaluating"), Value::Int(3));
+        assert_eq!(run("{ let x = 2; x * x }").expect("evaluating"), Value::Int(4));
+        assert_eq!(run("{ let x = 1; y = x; y }").expect("evaluating"), Value::Int(1));
+    }
+
+    #[test]
+    fn values() {
+        let is_value = |input: &str, strategy| {
+            let mut parser = Parser::new(input).expect("scanning example input");
+            parser.parse_script().expect("parsing example input").is_value(strategy)
+        };
+        for strategy in [Strategy::CallByValue, Strategy::NormalOrder] {
+            assert!(is_value("1", strategy));
+            assert!(is_value("\\ x . x", strategy));
+            assert!(is_value("f x (\\ y . y)", strategy));
+            assert!(!is_value("(\\ x . x) 1", strategy));
+            assert!(!is_value("f ((\\ x . x) 1)", strategy));
+            assert!(!is_value("1 + 2", strategy));
+        }
+        // Call-by-value does not look under abstractions.
+        assert!(is_value("\\ x . (\\ y . y) x", Strategy::CallByValue));
+        assert!(!is_value("\\ x . (\\ y . y) x", Strategy::NormalOrder));
+        assert!(is_value("\\ x . x + 1", Strategy::NormalOrder));
+    }
+
+    #[test]
+    fn redex_spans() {
+        let redexes_of = |input: &str| {
+            let mut parser = Parser::new(input).expect("scanning example input");
+            redexes(&parser.parse_script().expect("parsing example input"))
+        };
+        assert_eq!(redexes_of("(\\x.x) ((\\y.y) z)"), [Span::new(0, 17), Span::new(7, 17)]);
+        assert_eq!(redexes_of("\\ x . f x (1 + 2 * x)"), []);
+        // Only the multiplication can be reduced right away.
+        assert_eq!(redexes_of("f (1 + 2 * 3)"), [Span::new(7, 12)]);
+        // Arithmetic on strings cannot be reduced.
+        assert_eq!(redexes_of("\"a\" + \"b\""), []);
+    }
+
+    #[test]
+    fn reduce_chosen_redex() {
+        let mut parser = Parser::new("(\\x.x) ((\\y.y) z)").expect("scanning example input");
+        let term = parser.parse_script().expect("parsing example input");
+        let spans = redexes(&term);
+        let inner = reduce_at(&term, spans[1]).expect("reducing");
+        assert_eq!(inner.to_string(), "(\\ x. x) z");
+
+        let outer = reduce_at(&term, spans[0]).expect("reducing");
+        assert_eq!(outer.to_string(), "(\\ y. y) z");
+        let spans = redexes(&outer);
+        assert_eq!(spans, [Span::new(7, 17)]);
+        let done = reduce_at(&outer, spans[0]).expect("reducing");
+        assert_eq!(done.to_string(), "z");
+        assert!(matches!(
+            reduce_at(&done, spans[0]),
+            Err(EvalError::NoRedexAt { span }) if span == Span::new(7, 17)
+        ));
+
+        let mut parser = Parser::new("f (1 / 0) (2 * 3)").expect("scanning example input");
+        let term = parser.parse_script().expect("parsing example input");
+        let spans = redexes(&term);
+        assert_eq!(reduce_at(&term, spans[1]).expect("reducing").to_string(), "f (1 / 0) 6");
+        assert!(matches!(reduce_at(&term, spans[0]), Err(EvalError::DivisionByZero { offset: 2 })));
+        // The span of a term that is not a redex.
+        assert!(matches!(reduce_at(&term, term.span()), Err(EvalError::NoRedexAt { .. })));
+    }
+
+    fn run_program(input: &str, fuel: u64) -> Result<String, EvalError> {
+        let mut parser = Parser::new(input).expect("scanning example input");
+        let prog = parser.parse_program().expect("parsing example input");
+        eval_program_fueled(&prog, fuel).map(|result| result.to_string())
+    }
+
+    #[test]
+    fn shared_interner() {
+        let source = "double x = x + x; quad x = double (double x); main = quad (double (quad 1));";
+        let mut parser = Parser::new(source).expect("scanning example input");
+        let prog = parser.parse_program().expect("parsing example input");
+        let mut interner = parser.into_interner();
+        // The parser interns the names of `Name` nodes, and their ids refer
+        // to them.
+        fn check_ids(node: &Node<'_, ()>, interner: &Interner, seen: &mut Vec<String>) {
+            if let Some(name) = node.as_name() {
+                let id = node.name_id().expect("parsed names have ids");
+                assert_eq!(interner.resolve(id), name);
+                seen.push(name.to_string());
+            }
+            for child in node.kind().children() {
+                check_ids(child, interner, seen);
+            }
+        }
+        let mut seen = Vec::new();
+        for decl in &prog {
+            for param in decl.params() {
+                check_ids(param, &interner, &mut seen);
+            }
+            check_ids(decl.body(), &interner, &mut seen);
+        }
+        assert_eq!(seen, ["x", "x", "x", "x", "double", "double", "x", "quad", "double", "quad"]);
+        let names = ["x", "double", "quad"];
+        assert_eq!(interner.len(), names.len());
+        let result = eval_program_interned(&prog, &mut interner, 1000).expect("evaluating");
+        assert_eq!(result.to_string(), "32");
+        // Evaluation finds all other names in the table of the parser, and
+        // only adds the name of `main`.
+        assert_eq!(interner.len(), names.len() + 1);
+        for name in names.into_iter().chain(["main"]) {
+            let id = interner.get(name).expect("name is interned");
+            assert_eq!(interner.resolve(id), name);
+        }
+    }
+
+    #[test]
+    fn fuel() {
+        let source = "twice f x = f (f x); inc = \\n. n + 1; main = twice inc 0;";
+        assert_eq!(run_program(source, 100).expect("evaluating"), "2");
+        // Unfolding twice once and inc two times, two applications of
+        // twice, two of inc and two additions. Evaluation starts with the
+        // body of main, which is not unfolded.
+        assert_eq!(run_program(source, 9).expect("evaluating"), "2");
+        assert!(matches!(run_program(source, 8), Err(EvalError::OutOfFuel)));
+
+        // Of several declarations of a name, the first is used.
+        let source = "f = 1; f = 2; main = f;";
+        assert_eq!(run_program(source, 100).expect("evaluating"), "1");
+
+        // Local names shadow declarations.
+        let source = "x = 1; main = (\\x. \\y. x) 2;";
+        assert_eq!(run_program(source, 100).expect("evaluating"), "\\ y. 2");
+        let source = "x = 1; main = \\x. x;";
+        assert_eq!(run_program(source, 100).expect("evaluating"), "\\ x. x");
+        // But not the names in unfolded declarations.
+        let source = "x = 1; f = \\y. x; main = \\x. f;";
+        assert_eq!(run_program(source, 100).expect("evaluating"), "\\ x. \\ y. 1");
+        let source = "x = 1; g = \\z. x; f = \\y. g; main = \\x. f;";
+        assert_eq!(run_program(source, 100).expect("evaluating"), "\\ x. \\ y. \\ z. 1");
+
+        // Mutual recursion without a normal form.
+        for source in [
+            "ping = pong; pong = ping; main = ping;",
+            "f = \\x. g x; g = \\x. f x; main = f 1;",
+            "main = (\\x. x x) (\\x. x x);",
+        ] {
+            assert!(matches!(run_program(source, 1000), Err(EvalError::OutOfFuel)));
+        }
+
+        assert!(matches!(
+            run_program("main = f 1;", 100),
+            Err(EvalError::UnboundVariable { name }) if name == "f"
+        ));
+        assert!(matches!(
+            run_program("f = 1;", 100),
+            Err(EvalError::UnboundVariable { name }) if name == "main"
+        ));
+    }
+
+    #[test]
+    fn main_result() {
+        assert_eq!(run_main("main = 6 * 7;", 100).expect("evaluating"), Literal::Int(42));
+        let e = run_main("id x = x; main = id;", 100).expect_err("should fail");
+        // The result is the abstraction from the definition of id.
+        assert!(matches!(e, Error::Eval(EvalError::NotALiteral { offset: 3 })));
+        assert!(matches!(run_main("main = ;", 100), Err(Error::Parse(_))));
+        assert!(matches!(run_main("main = 1 / 0;", 100), Err(Error::Eval(EvalError::DivisionByZero { .. }))));
+    }
+
+    #[test]
+    fn conditionals() {
+        assert_eq!(run("1 + if 1 then 2 else 3 end").expect("evaluating"), Value::Int(3));
+        assert_eq!(run("1 + if 0 then 2 else 3 end").expect("evaluating"), Value::Int(4));
+        assert_eq!(run("if 2 - 2 then 1 else 5 end * 2").expect("evaluating"), Value::Int(10));
+        assert_eq!(
+            run("(\\c. 10 * if c then 1 else 2 end) 0").expect("evaluating"),
+            Value::Int(20)
+        );
+        // The branches need not have the same type.
+        assert_eq!(run("if 7 then \"yes\" else 0 end").expect("evaluating"), Value::String("yes".into()));
+        // Only the selected branch is evaluated.
+        assert_eq!(run("if 0 then 1 / 0 else 1 end").expect("evaluating"), Value::Int(1));
+        assert!(matches!(
+            run("if \"no\" then 1 else 2 end"),
+            Err(EvalError::InvalidCondition { offset: 3 })
+        ));
+
+        assert_eq!(reduce("\\x. if x then (\\y. y) 1 else 2 end"), "\\ x. if x then 1 else 2 end");
+        let mut parser = Parser::new("if 1 then 2 else 3 end + 4").expect("scanning example input");
+        let term = parser.parse_script().expect("parsing example input");
+        assert_eq!(redexes(&term), [Span::new(0, 22)]);
+        assert_eq!(reduce_at(&term, Span::new(0, 22)).expect("reducing").to_string(), "2 + 4");
+    }
+
+    #[test]
+    fn multi_way_conditionals() {
+        let choose = "\\x. if | x - 1 => 10 | x => 20 end";
+        assert_eq!(run(&format!("({choose}) 1")).expect("evaluating"), Value::Int(20));
+        assert_eq!(run(&format!("({choose}) 2")).expect("evaluating"), Value::Int(10));
+        assert!(matches!(run("1 + if | 0 => 1 | 2 - 2 => 3 end"), Err(EvalError::NoMatchingArm { offset: 4 })));
+        // Only the selected body is evaluated.
+        assert_eq!(run("if | 0 => 1 / 0 | otherwise => 2 end").expect("evaluating"), Value::Int(2));
+
+        assert_eq!(reduce(choose), "\\ x. if | x - 1 => 10 | x => 20 end");
+        let mut parser = Parser::new("if | 0 => 1 | 2 => 3 end").expect("scanning example input");
+        let term = parser.parse_script().expect("parsing example input");
+        assert_eq!(redexes(&term), [Span::new(0, 24)]);
+        let term = reduce_at(&term, Span::new(0, 24)).expect("reducing");
+        assert_eq!(term.to_string(), "if | 2 => 3 end");
+        assert_eq!(reduce_at(&term, Span::new(0, 24)).expect("reducing").to_string(), "3");
+    }
+
+    #[test]
+    fn normalization_status() {
+        let status = |input| {
+            let mut parser = Parser::new(input).expect("scanning example input");
+            let expr = parser.parse_script().expect("parsing example input");
+            normalize_fueled(&expr, 100).expect("reducing").status
+        };
+        assert_eq!(status("(\\x. x + 1) 2"), NormStatus::Normal);
+        assert_eq!(status("\\f. \\x. f (x * 2)"), NormStatus::Normal);
+        assert_eq!(
+            status("x y"),
+            NormStatus::Stuck {
+                span: Span::new(0, 1),
+                reason: StuckReason::Unbound { name: "x".into() }
+            }
+        );
+        assert_eq!(
+            status("1 2"),
+            NormStatus::Stuck {
+                span: Span::new(0, 3),
+                reason: StuckReason::NotAFunction
+            }
+        );
+        assert_eq!(
+            status("(\\y. 1 y) 2"),
+            NormStatus::Stuck {
+                span: Span::new(5, 8),
+                reason: StuckReason::NotAFunction
+            }
+        );
+        assert_eq!(
+            status("\\x. x + \"a\""),
+            NormStatus::Stuck {
+                span: Span::new(8, 11),
+                reason: StuckReason::NotAnInteger
+            }
+        );
+        assert_eq!(status("(\\x. x x) (\\x. x x)"), NormStatus::StepLimit);
+    }
+
+    #[test]
+    fn guarded_definitions() {
+        let f = "f x | x > 0 = 1 | otherwise = -1;";
+        assert_eq!(run_main(&format!("{f} main = f 5;"), 100).expect("evaluating"), Literal::Int(1));
+        assert_eq!(run_main(&format!("{f} main = f (-5);"), 100).expect("evaluating"), Literal::Int(-1));
+        let f = "f x | x < 0 = -1 | x == 0 = 0;";
+        assert_eq!(run_main(&format!("{f} main = f (-5);"), 100).expect("evaluating"), Literal::Int(-1));
+        assert!(matches!(
+            run_main(&format!("{f} main = f 5;"), 100),
+            Err(Error::Eval(EvalError::NoMatchingArm { .. }))
+        ));
+    }
+
+    #[test]
+    fn pipe_forward() {
+        let defs = "inc x = x + 1; double x = x * 2;";
+        assert_eq!(
+            run_main(&format!("{defs} main = 1 |> inc |> double;"), 100).expect("evaluating"),
+            Literal::Int(4)
+        );
+        assert_eq!(
+            run_main(&format!("{defs} main = 1 |> double |> inc;"), 100).expect("evaluating"),
+            Literal::Int(3)
+        );
+    }
+
+    #[test]
+    fn comprehensions() {
+        let list = |values: &[i64]| Value::List(values.iter().map(|i| Value::Int(*i)).collect());
+        assert_eq!(run("[x | x <- [1, 2, 3]]").expect("evaluating"), list(&[1, 2, 3]));
+        assert_eq!(run("[x + 1 | x <- [1, 2, 3]]").expect("evaluating"), list(&[2, 3, 4]));
+        // Only odd numbers pass the guard.
+        assert_eq!(run("[x | x <- [1, 2, 3, 4, 5], x - x / 2 * 2]").expect("evaluating"), list(&[1, 3, 5]));
+        // Later generators and the guards see the earlier generators.
+        assert_eq!(
+            run("[x * y | x <- [1, 2], y <- [x, 10], y - 1]").expect("evaluating"),
+            list(&[10, 4, 20])
+        );
+        assert_eq!(run("(\\n. [x + n | x <- [1, 2]]) 10").expect("evaluating"), list(&[11, 12]));
+        assert_eq!(run("[x | x <- []]").expect("evaluating"), list(&[]));
+        assert_eq!(run("[x | x <- [1, 2]]").expect("evaluating").to_string(), "[1, 2]");
+        // Elements that are not selected are never evaluated.
+        assert_eq!(run("[1 / x | x <- [0, 1], x]").expect("evaluating"), list(&[1]));
+        assert!(matches!(run("[x | x <- 1]"), Err(EvalError::NotAList { offset: 10 })));
+
+        // Comprehensions over unknown lists or with undecided guards are
+        // stuck, like conditionals.
+        let normalized = |input| {
+            let mut parser = Parser::new(input).expect("scanning example input");
+            let expr = parser.parse_script().expect("parsing example input");
+            let result = normalize_fueled(&expr, 100).expect("reducing");
+            (result.term.to_string(), result.status)
+        };
+        assert_eq!(normalized("\\xs. [x | x <- xs]"), ("\\ xs. [x | x <- xs]".into(), NormStatus::Normal));
+        assert_eq!(
+            normalized("\\p. [x | y <- [1, 2], x <- [y], p]"),
+            ("\\ p. [x | y <- [1, 2], x <- [y], p]".into(), NormStatus::Normal)
+        );
+        assert_eq!(
+            normalized("[x | x <- xs]"),
+            (
+                "[x | x <- xs]".into(),
+                NormStatus::Stuck {
+                    span: Span::new(10, 12),
+                    reason: StuckReason::Unbound { name: "xs".into() }
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn shared_let_values() {
+        // Returns its argument and writes a line for every call.
+        fn tick(args: &[Literal], out: &mut dyn Write) -> std::io::Result<Option<Literal>> {
+            writeln!(out, "tick")?;
+            Ok(Some(args[0].clone()))
+        }
+        let builtins = HashMap::from([("tick", Builtin { arity: 1, fun: tick })]);
+        let run = |input: &'static str| {
+            let mut parser = Parser::new(input).expect("scanning example input");
+            let expr = parser.parse_script().expect("parsing example input");
+            let mut out = Vec::new();
+            let result = eval_with_output(&expr, &builtins, &mut out).expect("evaluating");
+            (result, String::from_utf8(out).expect("output is UTF-8").lines().count())
+        };
+        assert_eq!(run("let x = tick 21 in x + x"), (Value::Int(42), 1));
+        assert_eq!(run("let x = tick 2 in let y = x * x in y * y + x"), (Value::Int(18), 1));
+        assert_eq!(run("let f = \\n. tick n * 2 in f 1 + f 1"), (Value::Int(4), 2));
+        // Every reduction of a `let` reduces its values again.
+        assert_eq!(run("let f = \\n. let c = tick 5 in c + n in f 1 + f 2"), (Value::Int(13), 2));
+        // Unused values are never reduced.
+        assert_eq!(run("let x = tick 1 in 2"), (Value::Int(2), 0));
+        // Uses that are reduced after the `let`, too.
+        assert_eq!(
+            run("let x = tick 1 in [x, x]"),
+            (Value::List(vec![Value::Int(1), Value::Int(1)]), 1)
+        );
+        let (result, ticks) = run("let x = tick 1 in \\y. x + x + y");
+        assert!(matches!(result, Value::Closure(body) if body.to_string() == "\\ y. 2 + y"));
+        assert_eq!(ticks, 1);
+        // Without `let`, every use is reduced.
+        assert_eq!(run("(\\x. x + x) (tick 21)"), (Value::Int(42), 2));
+    }
+
+    #[test]
+    fn shared_let_values_are_released() {
+        let mut parser = Parser::new("let f = \\n. let c = n * 2 in c + c in let x = f 1 in \\y. x + y")
+            .expect("scanning example input");
+        let expr = parser.parse_script().expect("parsing example input");
+        let mut interner = Interner::new();
+        let mut machine = Machine::new(Some(1000), &mut interner, HashMap::new());
+        let result = machine.normalize(&expr).expect("evaluating");
+        assert_eq!(result.to_string(), "\\ y. 4 + y");
+        assert!(machine.shared.is_empty());
+        assert!(machine.shared_order.is_empty());
+    }
+
+    #[test]
+    fn captured_output() {
+        fn run_io(input: &str) -> (Result<Value<'_, ()>, EvalError>, String) {
+            let mut parser = Parser::new(input).expect("scanning example input");
+            let expr = parser.parse_script().expect("parsing example input");
+            let mut out = Vec::new();
+            let result = eval_with_output(&expr, &io_builtins(), &mut out);
+            (result, String::from_utf8(out).expect("output is UTF-8"))
+        }
+
+        let (result, out) = run_io("print \"hi\"");
+        assert_eq!(result.expect("evaluating"), Value::Unit);
+        assert_eq!(out, "hi\n");
+
+        let (result, out) = run_io("{ putStrLn \"a\"; print (6 * 7); (\\x. x) \"done\" }");
+        assert_eq!(result.expect("evaluating"), Value::String("done".into()));
+        assert_eq!(out, "a\n42\n");
+
+        let (result, out) = run_io("(\\print. print 1) (\\x. x)");
+        assert_eq!(result.expect("evaluating"), Value::Int(1));
+        assert_eq!(out, "");
+
+        let (result, out) = run_io("putStrLn 1");
+        assert!(matches!(result, Err(EvalError::InvalidArgument { builtin, offset: 0 }) if builtin == "putStrLn"));
+        assert_eq!(out, "");
+
+        assert!(matches!(run("print 1"), Err(EvalError::UnboundVariable { name }) if name == "print"));
+    }
+
+    #[test]
+    fn simultaneous_let() {
+        assert_eq!(run("let x = 1; y = 2 in x + y").expect("evaluating"), Value::Int(3));
+        assert_eq!(run("let id = \\ x . x in id 1 + id 2").expect("evaluating"), Value::Int(3));
+        // A binding does not see its siblings, only the enclosing scope.
+        assert_eq!(run("let x = 1 in let x = 2; y = x in y").expect("evaluating"), Value::Int(1));
+        assert!(matches!(run("let x = 1; y = x in y"), Err(EvalError::UnboundVariable { name }) if name == "x"));
+        assert_eq!(run("(\\ y . let x = y; y = 1 in x - y) 5").expect("evaluating"), Value::Int(4));
+        assert_eq!(reduce("(\\ z . let x = z; y = 1 in x y) y"), "y 1");
+    }
+
+    #[test]
+    fn capture_avoidance() {
+        assert_eq!(reduce("(\\ x . \\ y . x) y"), "\\ y1. y");
+        assert_eq!(reduce("(\\ x . \\ y . x y) y"), "\\ y1. y y1");
+        // `y1` is taken, too.
+        assert_eq!(reduce("(\\ x . \\ y . x y1) y"), "\\ y2. y y1");
+        // Bound occurrences are not replaced.
+        assert_eq!(reduce("(\\ x . \\ x . x) y"), "\\ x. x");
+        let body = Parser::new("{ let y = x; x y }").and_then(|mut p| p.parse_script());
+        let value = Parser::new("y").and_then(|mut p| p.parse_script());
+        let result = substitute(&body.expect("parsing"), "x", &value.expect("parsing"));
+        assert_eq!(result.to_string(), "{ let y1 = y; y y1 }");
+        let body = Parser::new("[x y | y <- [x], x]").and_then(|mut p| p.parse_script());
+        let value = Parser::new("y").and_then(|mut p| p.parse_script());
+        let result = substitute(&body.expect("parsing"), "x", &value.expect("parsing"));
+        assert_eq!(result.to_string(), "[y y1 | y1 <- [y], y]");
+    }
+}
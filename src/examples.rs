@@ -0,0 +1,31 @@
+/// Example programs bundled with the binary, by name.
+pub const EXAMPLES: &[(&str, &str)] = &[
+    ("minimal", include_str!("../examples/minimal.lc")),
+    ("commented", include_str!("../examples/commented.lc")),
+];
+
+/// Return the source of the bundled example with the given name.
+pub fn find_example(name: &str) -> Option<&'static str> {
+    EXAMPLES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, source)| *source)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn examples_parse() {
+        for (name, _) in EXAMPLES {
+            let source = find_example(name).expect("loading example");
+            let mut parser = Parser::new(source).expect("scanning example");
+            parser
+                .parse_program()
+                .unwrap_or_else(|e| panic!("parsing example {name}: {e}"));
+        }
+        assert!(find_example("no-such-example").is_none());
+    }
+}
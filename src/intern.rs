@@ -0,0 +1,83 @@
+use std::{collections::HashMap, rc::Rc};
+
+/// Identifier of an interned name. Two ids compare equal exactly when the
+/// names they were created from are equal.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, PartialOrd, Ord)]
+pub struct NameId(u32);
+
+/// Table mapping names to small integer ids, so that names can be compared
+/// and hashed cheaply.
+#[derive(Debug, Default)]
+pub struct Interner {
+    names: Vec<Rc<str>>,
+    ids: HashMap<Rc<str>, NameId>,
+}
+
+#[allow(dead_code)]
+impl Interner {
+    pub fn new() -> Interner {
+        Interner::default()
+    }
+
+    /// Return the id for the given name, allocating a new one if the name
+    /// has not been seen before.
+    pub fn intern(&mut self, name: &str) -> NameId {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        let id = NameId(self.names.len() as u32);
+        let name: Rc<str> = name.into();
+        self.names.push(name.clone());
+        self.ids.insert(name, id);
+        id
+    }
+
+    /// Return the id for the given name if it has been interned before.
+    pub fn get(&self, name: &str) -> Option<NameId> {
+        self.ids.get(name).copied()
+    }
+
+    /// Return the name the given id was created from.
+    ///
+    /// # Panics
+    /// Panics if the id was created by a different interner.
+    pub fn resolve(&self, id: NameId) -> &str {
+        &self.names[id.0 as usize]
+    }
+
+    /// Number of distinct names in the table.
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut interner = Interner::new();
+        let input = "f x y f x f main x y";
+        let ids = input
+            .split_whitespace()
+            .map(|n| interner.intern(n))
+            .collect::<Vec<_>>();
+        assert_eq!(interner.len(), 4);
+        assert_eq!(ids[0], ids[3]);
+        assert_eq!(ids[1], ids[4]);
+        assert_ne!(ids[0], ids[1]);
+        let resolved = ids
+            .iter()
+            .map(|id| interner.resolve(*id))
+            .collect::<Vec<_>>()
+            .join(" ");
+        assert_eq!(resolved, input);
+        assert_eq!(interner.get("main"), Some(ids[6]));
+        assert_eq!(interner.get("g"), None);
+    }
+}
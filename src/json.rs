@@ -0,0 +1,364 @@
+use std::fmt::Display;
+
+use crate::{
+    ast::{Attribute, AttributeEntry, AttributeValue, Declaration, Literal, Node, NodeKind, Program},
+    eval::Value,
+    parser::ParseError,
+    span::{line_col, Span},
+    token::Token,
+};
+
+/// A JSON value, for output to tools written in other languages.
+#[derive(Debug, Clone, PartialEq)]
+#[allow(dead_code)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Int(i64),
+    String(String),
+    Array(Vec<Json>),
+    /// Object with its members in the order they are printed.
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn object(members: Vec<(&str, Json)>) -> Json {
+        Json::Object(
+            members
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    /// Print the value with each array element and object member on its
+    /// own line, indented by two spaces per level.
+    fn write_pretty(&self, level: usize, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let newline = |f: &mut std::fmt::Formatter<'_>, level: usize| {
+            writeln!(f)?;
+            (0..level).try_for_each(|_| "  ".fmt(f))
+        };
+        match self {
+            Json::Null => "null".fmt(f),
+            Json::Bool(b) => b.fmt(f),
+            Json::Int(i) => i.fmt(f),
+            Json::String(s) => write_string(s, f),
+            Json::Array(elems) if elems.is_empty() => "[]".fmt(f),
+            Json::Array(elems) => {
+                "[".fmt(f)?;
+                for (i, elem) in elems.iter().enumerate() {
+                    if i > 0 {
+                        ",".fmt(f)?;
+                    }
+                    newline(f, level + 1)?;
+                    elem.write_pretty(level + 1, f)?;
+                }
+                newline(f, level)?;
+                "]".fmt(f)
+            }
+            Json::Object(members) if members.is_empty() => "{}".fmt(f),
+            Json::Object(members) => {
+                "{".fmt(f)?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        ",".fmt(f)?;
+                    }
+                    newline(f, level + 1)?;
+                    write_string(key, f)?;
+                    ": ".fmt(f)?;
+                    value.write_pretty(level + 1, f)?;
+                }
+                newline(f, level)?;
+                "}".fmt(f)
+            }
+        }
+    }
+}
+
+/// Prints the value as pretty JSON.
+impl std::fmt::Display for Json {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write_pretty(0, f)
+    }
+}
+
+/// Print a string as a JSON string literal.
+fn write_string(s: &str, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    use std::fmt::Write;
+    f.write_char('"')?;
+    for ch in s.chars() {
+        match ch {
+            '"' => "\\\"".fmt(f)?,
+            '\\' => "\\\\".fmt(f)?,
+            '\n' => "\\n".fmt(f)?,
+            '\r' => "\\r".fmt(f)?,
+            '\t' => "\\t".fmt(f)?,
+            ch if (ch as u32) < 0x20 => write!(f, "\\u{:04x}", ch as u32)?,
+            ch => f.write_char(ch)?,
+        }
+    }
+    f.write_char('"')
+}
+
+/// Convert a span to a two-element array of byte offsets.
+fn span_to_json(span: Span) -> Json {
+    Json::Array(vec![Json::Int(span.start as i64), Json::Int(span.end as i64)])
+}
+
+/// Convert a program to JSON: an object with the member `declarations`,
+/// holding one object per declaration with its `attributes`, `name`,
+/// `type` (`null` without a signature), `params`, `guards` as written,
+/// `body` and `span`.
+#[allow(dead_code)]
+pub fn program_to_json(prog: &Program<'_>) -> Json {
+    Json::object(vec![(
+        "declarations",
+        Json::Array(prog.iter().map(declaration_to_json).collect()),
+    )])
+}
+
+fn declaration_to_json(decl: &Declaration<'_>) -> Json {
+    let guards = decl
+        .guards()
+        .iter()
+        .map(|(guard, body)| Json::object(vec![("guard", node_to_json(guard)), ("body", node_to_json(body))]))
+        .collect();
+    Json::object(vec![
+        ("attributes", Json::Array(decl.attributes().iter().map(attribute_to_json).collect())),
+        ("name", Json::String(decl.name().into())),
+        ("type", decl.ty().map_or(Json::Null, |ty| Json::String(ty.to_string()))),
+        ("params", Json::Array(decl.params().iter().map(|p| node_to_json(p)).collect())),
+        ("guards", Json::Array(guards)),
+        ("body", node_to_json(decl.body())),
+        ("span", span_to_json(decl.span())),
+    ])
+}
+
+/// Convert an attribute to an object with its `entries` and `span`. Each
+/// entry is an object with its `name` and, unless it is a flag, `value`.
+fn attribute_to_json(attr: &Attribute<'_>) -> Json {
+    let entries = attr
+        .entries()
+        .iter()
+        .map(|entry| match entry {
+            AttributeEntry::Flag(name) => Json::object(vec![("name", Json::String(name.to_string()))]),
+            AttributeEntry::Value(name, value) => {
+                let value = match value {
+                    AttributeValue::Int(i) => Json::Int(*i),
+                    AttributeValue::String(s) => Json::String(s.to_string()),
+                };
+                Json::object(vec![("name", Json::String(name.to_string())), ("value", value)])
+            }
+        })
+        .collect();
+    Json::object(vec![("entries", Json::Array(entries)), ("span", span_to_json(attr.span()))])
+}
+
+/// Convert a tree to JSON. Every node is an object with the name of its
+/// `kind` and its `span` as a two-element array of byte offsets, followed
+/// by the fields of the node kind, with child nodes as nested objects.
+#[allow(dead_code)]
+pub fn node_to_json<Anno>(node: &Node<'_, Anno>) -> Json {
+    let (kind, mut fields) = match node.kind() {
+        NodeKind::Name { name, .. } => ("Name", vec![("name", Json::String(name.to_string()))]),
+        NodeKind::App { fun, arg } => ("App", vec![("fun", node_to_json(fun)), ("arg", node_to_json(arg))]),
+        NodeKind::Abs { param, body } => {
+            ("Abs", vec![("param", node_to_json(param)), ("body", node_to_json(body))])
+        }
+        NodeKind::Lit { lit: Literal::Int(i) } => ("Lit", vec![("value", Json::Int(*i))]),
+        NodeKind::Lit { lit: Literal::String(s) } => ("Lit", vec![("value", Json::String(s.clone()))]),
+        NodeKind::Lit { lit: Literal::Unit } => ("Lit", vec![("value", Json::Null)]),
+        NodeKind::Lit { lit: Literal::BigInt(digits) } => ("Lit", vec![("digits", Json::String(digits.clone()))]),
+        NodeKind::BinOp { op, op_span, lhs, rhs } => (
+            "BinOp",
+            vec![
+                ("op", Json::String(op.to_string())),
+                ("op_span", span_to_json(*op_span)),
+                ("lhs", node_to_json(lhs)),
+                ("rhs", node_to_json(rhs)),
+            ],
+        ),
+        NodeKind::Borrow { expr } => ("Borrow", vec![("expr", node_to_json(expr))]),
+        NodeKind::Ann { expr, ty } => (
+            "Ann",
+            vec![("expr", node_to_json(expr)), ("type", Json::String(ty.to_string()))],
+        ),
+        NodeKind::If { cond, conseq, alt } => (
+            "If",
+            vec![
+                ("cond", node_to_json(cond)),
+                ("conseq", node_to_json(conseq)),
+                ("alt", node_to_json(alt)),
+            ],
+        ),
+        NodeKind::MultiIf { arms, otherwise } => {
+            let arms = arms
+                .iter()
+                .map(|(cond, body)| {
+                    Json::object(vec![("cond", node_to_json(cond)), ("body", node_to_json(body))])
+                })
+                .collect();
+            let otherwise = otherwise.as_ref().map_or(Json::Null, |otherwise| node_to_json(otherwise));
+            ("MultiIf", vec![("arms", Json::Array(arms)), ("otherwise", otherwise)])
+        }
+        NodeKind::Let { bindings, body } => {
+            let bindings = bindings
+                .iter()
+                .map(|(name, value)| {
+                    Json::object(vec![("name", node_to_json(name)), ("value", node_to_json(value))])
+                })
+                .collect();
+            ("Let", vec![("bindings", Json::Array(bindings)), ("body", node_to_json(body))])
+        }
+        NodeKind::Seq { first, second } => {
+            ("Seq", vec![("first", node_to_json(first)), ("second", node_to_json(second))])
+        }
+        NodeKind::BoundVar { index } => ("BoundVar", vec![("index", Json::Int(*index as i64))]),
+        NodeKind::List { elems } => {
+            ("List", vec![("elems", Json::Array(elems.iter().map(|elem| node_to_json(elem)).collect()))])
+        }
+        NodeKind::Comprehension { body, generators, guards } => {
+            let generators = generators
+                .iter()
+                .map(|(name, list)| {
+                    Json::object(vec![("name", node_to_json(name)), ("list", node_to_json(list))])
+                })
+                .collect();
+            (
+                "Comprehension",
+                vec![
+                    ("body", node_to_json(body)),
+                    ("generators", Json::Array(generators)),
+                    ("guards", Json::Array(guards.iter().map(|guard| node_to_json(guard)).collect())),
+                ],
+            )
+        }
+    };
+    let mut members = vec![("kind", Json::String(kind.into())), ("span", span_to_json(node.span()))];
+    members.append(&mut fields);
+    Json::object(members)
+}
+
+/// Convert a parse error to JSON: an object with the member `error`,
+/// holding the `message`, the byte `offset` and the 1-based `line` and
+/// `column` in `source`.
+#[allow(dead_code)]
+pub fn error_to_json(e: &ParseError, source: &str) -> Json {
+    message_to_json(&e.to_string(), Some(e.offset()), source)
+}
+
+/// Convert an error message to JSON like `error_to_json`. Without an
+/// offset, the object only has the `message`.
+pub fn message_to_json(message: &str, offset: Option<usize>, source: &str) -> Json {
+    let mut members = vec![("message", Json::String(message.into()))];
+    if let Some(offset) = offset {
+        let (line, column) = line_col(source, offset);
+        members.extend([
+            ("offset", Json::Int(offset as i64)),
+            ("line", Json::Int(line as i64)),
+            ("column", Json::Int(column as i64)),
+        ]);
+    }
+    Json::object(vec![("error", Json::object(members))])
+}
+
+/// Convert a token to JSON: an object with its `kind`, `span` and the
+/// `text` as written in the source.
+pub fn token_to_json(token: &Token<'_>) -> Json {
+    Json::object(vec![
+        ("kind", Json::String(format!("{:?}", token.kind()))),
+        ("span", span_to_json(token.span())),
+        ("text", Json::String(token.raw_text().into())),
+    ])
+}
+
+/// Convert the value of an expression to JSON: an object with the member
+/// `result`, holding the literal, `null` for `()`, the tree of an
+/// abstraction, or an array for a list.
+pub fn value_to_json<Anno>(value: &Value<'_, Anno>) -> Json {
+    Json::object(vec![("result", value_json(value))])
+}
+
+/// Convert a value to JSON, without the `result` wrapper.
+fn value_json<Anno>(value: &Value<'_, Anno>) -> Json {
+    match value {
+        Value::Int(i) => Json::Int(*i),
+        Value::String(s) => Json::String(s.clone()),
+        Value::Unit => Json::Null,
+        Value::Closure(node) => node_to_json(node),
+        Value::List(values) => Json::Array(values.iter().map(value_json).collect()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn program() {
+        let mut parser = Parser::new("id :: T; id x = x;").expect("scanning example input");
+        let prog = parser.parse_program().expect("parsing example input");
+        let Json::Object(members) = program_to_json(&prog) else {
+            panic!("expected object");
+        };
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].0, "declarations");
+        let Json::Array(decls) = &members[0].1 else {
+            panic!("expected array");
+        };
+        let Json::Object(decl) = &decls[0] else {
+            panic!("expected object");
+        };
+        let keys = decl.iter().map(|(key, _)| key.as_str()).collect::<Vec<_>>();
+        assert_eq!(keys, ["attributes", "name", "type", "params", "guards", "body", "span"]);
+        assert_eq!(decl[2].1, Json::String("T".into()));
+        assert_eq!(
+            decl[5].1.to_string(),
+            "{\n  \"kind\": \"Name\",\n  \"span\": [\n    16,\n    17\n  ],\n  \"name\": \"x\"\n}"
+        );
+        assert_eq!(program_to_json(&[]).to_string(), "{\n  \"declarations\": []\n}");
+
+        let mut parser = Parser::new("@[inline, since = 2] f x | x = 1 | otherwise = 2;").expect("scanning example input");
+        let prog = parser.parse_program().expect("parsing example input");
+        let decl = declaration_to_json(&prog[0]);
+        let Json::Object(members) = &decl else {
+            panic!("expected object");
+        };
+        assert_eq!(
+            members[0].1,
+            Json::Array(vec![Json::object(vec![
+                (
+                    "entries",
+                    Json::Array(vec![
+                        Json::object(vec![("name", Json::String("inline".into()))]),
+                        Json::object(vec![("name", Json::String("since".into())), ("value", Json::Int(2))]),
+                    ])
+                ),
+                ("span", span_to_json(Span::new(0, 20))),
+            ])])
+        );
+        let Json::Array(guards) = &members[4].1 else {
+            panic!("expected array");
+        };
+        assert_eq!(guards.len(), 2);
+        assert_eq!(
+            guards[1],
+            Json::object(vec![("guard", node_to_json(&prog[0].guards()[1].0)), ("body", node_to_json(&prog[0].guards()[1].1))])
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let source = "main =\n  f (;";
+        let e = Parser::new(source)
+            .and_then(|mut parser| parser.parse_program())
+            .expect_err("should fail");
+        let json = error_to_json(&e, source).to_string();
+        assert!(json.contains("\"line\": 2,\n    \"column\": 6"), "{json}");
+        assert_eq!(
+            Json::String("\"\\\n\u{1}".into()).to_string(),
+            "\"\\\"\\\\\\n\\u0001\""
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use crate::ast::Literal;
+
+/// Value of a number literal, as returned by a `LiteralParser`.
+pub type LitValue = Literal;
+
+/// Reasons why a `LiteralParser` rejects a literal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum LitError {
+    /// The value is outside of the range the parser supports.
+    Overflow,
+    /// The digits are not valid in the base.
+    InvalidDigits,
+}
+
+impl std::fmt::Display for LitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LitError::Overflow => "literal is too large".fmt(f),
+            LitError::InvalidDigits => "invalid digits in literal".fmt(f),
+        }
+    }
+}
+
+/// Conversion of number literals to values, so that dialects can decode
+/// numbers differently, for example with arbitrary precision. The parser
+/// uses `I64Literals` unless it is configured with another implementation.
+pub trait LiteralParser {
+    /// Convert the digits of an integer literal in the given base to its
+    /// value. The digits have no sign, type suffix or digit separators.
+    fn parse_int(&self, digits: &str, base: u32) -> Result<LitValue, LitError>;
+}
+
+/// The standard literal parser, which decodes integers as `i64`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct I64Literals;
+
+impl LiteralParser for I64Literals {
+    fn parse_int(&self, digits: &str, base: u32) -> Result<LitValue, LitError> {
+        if digits.is_empty() || !digits.chars().all(|c| c.is_digit(base)) {
+            return Err(LitError::InvalidDigits);
+        }
+        i64::from_str_radix(digits, base)
+            .map(Literal::Int)
+            .map_err(|_| LitError::Overflow)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ast::NodeKind, parser::{ParseError, Parser}};
+
+    /// Decimal literals of any size, with those that do not fit into an
+    /// `i64` kept as digits.
+    struct BigLiterals;
+
+    impl LiteralParser for BigLiterals {
+        fn parse_int(&self, digits: &str, base: u32) -> Result<LitValue, LitError> {
+            match I64Literals.parse_int(digits, base) {
+                Err(LitError::Overflow) if base == 10 => {
+                    Ok(Literal::BigInt(digits.trim_start_matches('0').to_string()))
+                }
+                result => result,
+            }
+        }
+    }
+
+    #[test]
+    fn literal_parsers() {
+        assert_eq!(I64Literals.parse_int("42", 10), Ok(Literal::Int(42)));
+        assert_eq!(I64Literals.parse_int("ff", 16), Ok(Literal::Int(255)));
+        assert_eq!(I64Literals.parse_int("12a", 10), Err(LitError::InvalidDigits));
+        assert_eq!(I64Literals.parse_int("+1", 10), Err(LitError::InvalidDigits));
+        assert_eq!(I64Literals.parse_int("9223372036854775808", 10), Err(LitError::Overflow));
+
+        let big = "123_456_789_012_345_678_901_234_567_890";
+        assert!(matches!(
+            Parser::new(big).and_then(|mut p| p.parse_script()),
+            Err(ParseError::IntegerOverflow { offset: 0 })
+        ));
+        let mut parser = Parser::with_literal_parser(big, BigLiterals).expect("scanning example input");
+        let expr = parser.parse_script().expect("parsing example input");
+        let NodeKind::Lit { lit: Literal::BigInt(digits) } = expr.kind() else {
+            panic!("expected big integer, got {expr:?}");
+        };
+        assert_eq!(digits, "123456789012345678901234567890");
+        assert_eq!(expr.to_string(), "123456789012345678901234567890");
+        // Small literals are still `i64`.
+        let mut parser = Parser::with_literal_parser("f 1", BigLiterals).expect("scanning example input");
+        assert_eq!(parser.parse_script().expect("parsing example input").to_string(), "f 1");
+    }
+}
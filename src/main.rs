@@ -1,16 +1,174 @@
+use std::{
+    io::{IsTerminal, Read},
+    process::ExitCode,
+    rc::Rc,
+};
+
+use ast::Node;
 use error::Error;
 use parser::Parser;
+use render::{JsonRenderer, PlainRenderer, Renderer};
+use scanner::Scanner;
 
+mod diagnostic;
 mod error;
+mod eval;
+mod examples;
 mod scanner;
 mod token;
 mod parser;
 mod ast;
+mod span;
+mod intern;
+mod json;
+mod repl;
+mod types;
+mod check;
+mod render;
+mod literal;
+mod stable;
+
+const USAGE: &str =
+    "usage: lcubed [FILE | - | --repl | --example NAME | --list-examples | --script FILE | --json-ast FILE]\n\
+     \x20      lcubed [--json] (--script FILE | --ast FILE | --tokens FILE | --common FILE FILE)\n\
+     Without arguments or with -, the program is read from standard input.\n\
+     With --ast or --tokens, the parsed program or its tokens are printed.\n\
+     With --common, the largest subterm two expressions share is printed.\n\
+     With --json, results and errors are printed as JSON; --json-ast is --json --ast.\n\
+     Without arguments on a terminal, or with --repl, an interactive session is started.";
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> Result<(), Error> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    let (renderer, args): (&dyn Renderer, _) = match args.as_slice() {
+        ["--json", rest @ ..] => (&JsonRenderer, rest),
+        rest => (&PlainRenderer, rest),
+    };
+    match args {
+        [] if std::io::stdin().is_terminal() => repl::run_repl(),
+        ["--repl"] => repl::run_repl(),
+        [] | ["-"] => {
+            let mut input = String::new();
+            std::io::stdin().read_to_string(&mut input)?;
+            process(&input)
+        }
+        ["--example", name] => {
+            let input = examples::find_example(name)
+                .ok_or_else(|| Error::Other(format!("unknown example {name:?}")))?;
+            process(input)
+        }
+        ["--list-examples"] => {
+            for (name, _) in examples::EXAMPLES {
+                println!("{name}");
+            }
+            Ok(())
+        }
+        ["--script", path] => {
+            let input = read_source(renderer, path)?;
+            let value = Parser::new(&input)
+                .and_then(|mut parser| parser.parse_script())
+                .map_err(Error::from)
+                .and_then(|expr| Ok(eval::eval(&expr)?))
+                .map_err(|e| rendered(renderer, e, &input))?;
+            print!("{}", renderer.render_result(&value));
+            Ok(())
+        }
+        ["--ast", path] => print_ast(renderer, path),
+        ["--json-ast", path] => print_ast(&JsonRenderer, path),
+        ["--common", path_a, path_b] => print_common(renderer, path_a, path_b),
+        ["--tokens", path] => {
+            let input = read_source(renderer, path)?;
+            let tokens = Scanner::new(&input)
+                .and_then(|scanner| scanner.collect::<Result<Vec<_>, _>>())
+                .map_err(|e| rendered(renderer, e.into(), &input))?;
+            print!("{}", renderer.render_tokens(&tokens));
+            Ok(())
+        }
+        [path] if !path.starts_with("--") => {
+            let input = std::fs::read_to_string(path)?;
+            process(&input)
+        }
+        _ => Err(Error::Other(USAGE.into())),
+    }
+}
 
-fn main() -> Result<(), Error> {
-    let input = "main :: Integer; main = 2;";
-    let mut parser = Parser::new(input)?;
-    let _ = parser.parse_program()?;
+/// Parse the program in the file at `path` and print it.
+fn print_ast(renderer: &dyn Renderer, path: &str) -> Result<(), Error> {
+    let input = read_source(renderer, path)?;
+    let prog = Parser::new(&input)
+        .and_then(|mut parser| parser.parse_program())
+        .map_err(|e| rendered(renderer, e.into(), &input))?;
+    print!("{}", renderer.render_ast(&prog));
+    Ok(())
+}
+
+/// Parse the expressions in the files at `path_a` and `path_b` and print
+/// where their largest common subterm is in each, and its text in the
+/// first.
+fn print_common(renderer: &dyn Renderer, path_a: &str, path_b: &str) -> Result<(), Error> {
+    let input_a = read_source(renderer, path_a)?;
+    let input_b = read_source(renderer, path_b)?;
+    fn parse<'src>(renderer: &dyn Renderer, input: &'src str) -> Result<Rc<Node<'src, ()>>, Error> {
+        Parser::new(input)
+            .and_then(|mut parser| parser.parse_script())
+            .map_err(|e| rendered(renderer, e.into(), input))
+    }
+    let (a, b) = (parse(renderer, &input_a)?, parse(renderer, &input_b)?);
+    match ast::largest_common_subterm(&a, &b) {
+        Some((span_a, span_b)) => {
+            println!("{path_a}:{span_a}: {}", &input_a[span_a.start..span_a.end]);
+            println!("{path_b}:{span_b}");
+        }
+        None => println!("no common subterm"),
+    }
+    Ok(())
+}
+
+/// Read the file at `path`, rendering the error if it cannot be read.
+fn read_source(renderer: &dyn Renderer, path: &str) -> Result<String, Error> {
+    std::fs::read_to_string(path).map_err(|e| rendered(renderer, e.into(), ""))
+}
+
+/// Render an error in `input` for printing by `main`.
+fn rendered(renderer: &dyn Renderer, e: Error, input: &str) -> Error {
+    Error::Other(renderer.render_error(&e, input).trim_end().into())
+}
+
+/// Parse a program and report whether it is well-formed. Errors include
+/// the offending line of the input.
+fn process(input: &str) -> Result<(), Error> {
+    Parser::new(input)
+        .and_then(|mut parser| parser.parse_program())
+        .map_err(|e| rendered(&PlainRenderer, e.into(), input))?;
     println!("Parse OK!");
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use json::Json;
+
+    #[test]
+    fn unreadable_files() {
+        let path = "/nonexistent/file.lc";
+        let message = std::fs::read_to_string(path).expect_err("should fail").to_string();
+        let e = read_source(&JsonRenderer, path).expect_err("should fail");
+        assert_eq!(
+            e.to_string(),
+            format!("{{\n  \"error\": {{\n    \"message\": {}\n  }}\n}}", Json::String(message.clone()))
+        );
+        let e = read_source(&PlainRenderer, path).expect_err("should fail");
+        assert_eq!(e.to_string(), message);
+    }
+}
@@ -1,10 +1,55 @@
-use crate::{scanner::{ScanError, Scanner}, token::{Symbol, TokenKind}};
+use std::{borrow::Cow, collections::HashMap, rc::Rc};
+
+use crate::{
+    ast::{
+        Attribute, AttributeEntry, AttributeValue, BinOp, Binding, Declaration, Guard, Literal,
+        Node, NodeKind,
+    },
+    diagnostic::Diagnostic,
+    intern::Interner,
+    literal::{I64Literals, LitError, LiteralParser},
+    scanner::{ScanError, ScanOptions, Scanner},
+    span::{render_snippet, Span},
+    token::{Keyword, Symbol, Token, TokenKind},
+    types::{ClassName, Qualified, Type, TypeVar},
+};
 
 #[derive(Debug)]
 #[allow(dead_code)]
 pub enum ParseError {
     ScanError(ScanError),
-    Unexpected{expected: TokenKind, found: TokenKind},
+    Unexpected { expected: TokenKind, found: TokenKind, offset: usize },
+    ExpectedExpression { offset: usize, found: TokenKind },
+    IntegerOverflow { offset: usize },
+    MissingDefinition { offset: usize, name: String },
+    UnexpectedKeywordInExpression { keyword: Keyword, span: Span },
+    NestingTooDeep { depth: usize, offset: usize },
+    ExpectedAttributeValue { offset: usize, found: TokenKind },
+    InvalidLiteral { offset: usize, error: LitError },
+}
+
+#[allow(dead_code)]
+impl ParseError {
+    /// Byte offset in the input at which the error was detected.
+    pub fn offset(&self) -> usize {
+        match self {
+            ParseError::ScanError(e) => e.offset(),
+            ParseError::Unexpected { offset, .. }
+            | ParseError::ExpectedExpression { offset, .. }
+            | ParseError::IntegerOverflow { offset }
+            | ParseError::MissingDefinition { offset, .. }
+            | ParseError::NestingTooDeep { offset, .. }
+            | ParseError::ExpectedAttributeValue { offset, .. }
+            | ParseError::InvalidLiteral { offset, .. } => *offset,
+            ParseError::UnexpectedKeywordInExpression { span, .. } => span.start,
+        }
+    }
+
+    /// Show the line of `source` where the error occurred, with a caret
+    /// under the offending token. `source` must be the parsed input.
+    pub fn render(&self, source: &str) -> String {
+        render_snippet(source, self.offset())
+    }
 }
 
 impl std::error::Error for ParseError {}
@@ -15,9 +60,28 @@ impl std::fmt::Display for ParseError {
             ParseError::ScanError(e) => {
                 e.fmt(f)
             }
-            ParseError::Unexpected { expected, found } => {
-                write!(f, "expected {expected:?}, found {found:?} instead")
+            ParseError::Unexpected { expected, found, .. } => {
+                write!(f, "expected {expected}, found {found} instead")
+            }
+            ParseError::ExpectedExpression { offset, found } => {
+                write!(f, "expected expression at offset {offset}, found {found} instead")
+            }
+            ParseError::IntegerOverflow { offset } => {
+                write!(f, "integer literal at offset {offset} is too large")
+            }
+            ParseError::UnexpectedKeywordInExpression { keyword, span } => {
+                write!(f, "unexpected `{keyword}` in expression at {span}")
             }
+            ParseError::MissingDefinition { offset, name } => {
+                write!(f, "type signature for {name} at offset {offset} is not followed by its definition")
+            }
+            ParseError::NestingTooDeep { depth, offset } => {
+                write!(f, "expression at offset {offset} is nested more than {depth} levels deep")
+            }
+            ParseError::ExpectedAttributeValue { offset, found } => {
+                write!(f, "expected number or string as attribute value at offset {offset}, found {found} instead")
+            }
+            ParseError::InvalidLiteral { offset, error } => write!(f, "{error} at offset {offset}"),
         }
     }
 }
@@ -28,34 +92,2074 @@ impl From<ScanError> for ParseError {
     }
 }
 
+/// Result of an incremental reparse.
+#[derive(Debug)]
+#[allow(dead_code)]
+pub struct Reparse<'src> {
+    /// The declarations of the edited source.
+    pub declarations: Vec<Declaration<'src>>,
+    /// Indices into `declarations` of the declarations that had to be
+    /// parsed again. All others were reused.
+    pub reparsed: Vec<usize>,
+}
+
+/// Associativity of a binary operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum Assoc {
+    Left,
+    Right,
+}
+
+impl std::fmt::Display for Assoc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Assoc::Left => "left".fmt(f),
+            Assoc::Right => "right".fmt(f),
+        }
+    }
+}
+
+/// What a binary operator of the precedence table parses to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    /// A `BinOp` node.
+    BinOp(BinOp),
+    /// Pipe-forward `x |> f`, which is desugared to the application `f x`.
+    Pipe,
+}
+
+impl Operator {
+    /// Binding strength of the operator; higher binds tighter. The pipe
+    /// binds weaker than all operations.
+    fn precedence(self) -> u8 {
+        match self {
+            Operator::BinOp(op) => op.precedence(),
+            Operator::Pipe => 0,
+        }
+    }
+}
+
+impl std::fmt::Display for Operator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operator::BinOp(op) => op.fmt(f),
+            Operator::Pipe => "|>".fmt(f),
+        }
+    }
+}
+
+/// Precedence and associativity of the binary operators known to the
+/// parser. Operators with higher precedence bind tighter.
+#[derive(Debug, Clone)]
+pub struct PrecedenceTable {
+    entries: Vec<(Symbol, Operator, Assoc)>,
+}
+
+impl Default for PrecedenceTable {
+    fn default() -> Self {
+        PrecedenceTable {
+            entries: vec![
+                (Symbol::PipeRight, Operator::Pipe, Assoc::Left),
+                (Symbol::Plus, Operator::BinOp(BinOp::Add), Assoc::Left),
+                (Symbol::Minus, Operator::BinOp(BinOp::Sub), Assoc::Left),
+                (Symbol::Star, Operator::BinOp(BinOp::Mul), Assoc::Left),
+                (Symbol::Slash, Operator::BinOp(BinOp::Div), Assoc::Left),
+                (Symbol::EqEq, Operator::BinOp(BinOp::Eq), Assoc::Left),
+                (Symbol::Less, Operator::BinOp(BinOp::Lt), Assoc::Left),
+                (Symbol::LessEq, Operator::BinOp(BinOp::Le), Assoc::Left),
+                (Symbol::Greater, Operator::BinOp(BinOp::Gt), Assoc::Left),
+                (Symbol::GreaterEq, Operator::BinOp(BinOp::Ge), Assoc::Left),
+            ],
+        }
+    }
+}
+
+impl PrecedenceTable {
+    /// Return the operator symbols with their precedence and associativity,
+    /// ordered by increasing precedence.
+    #[allow(dead_code)]
+    pub fn entries(&self) -> Vec<(Symbol, u8, Assoc)> {
+        let mut entries = self
+            .entries
+            .iter()
+            .map(|(sym, op, assoc)| (*sym, op.precedence(), *assoc))
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|(_, prec, _)| *prec);
+        entries
+    }
+
+    /// Return the operator, precedence and associativity for binary
+    /// operator tokens.
+    fn lookup(&self, kind: TokenKind) -> Option<(Operator, u8, Assoc)> {
+        self.entries.iter().find_map(|(sym, op, assoc)| {
+            (kind == TokenKind::Symbol(*sym)).then_some((*op, op.precedence(), *assoc))
+        })
+    }
+}
+
+impl std::fmt::Display for PrecedenceTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "operator  precedence  associativity")?;
+        let mut entries = self.entries.clone();
+        entries.sort_by_key(|(_, op, _)| op.precedence());
+        for (_, op, assoc) in entries {
+            writeln!(f, "{:<8}  {:<10}  {assoc}", op.to_string(), op.precedence())?;
+        }
+        Ok(())
+    }
+}
+
 pub struct Parser<'src> {
     scanner: Scanner<'src>,
+    /// The binary operators.
+    operators: PrecedenceTable,
+    /// The current token.
+    token: Token<'src>,
+    /// The token following the current one, if it has already been scanned
+    /// by `peek2`.
+    lookahead: Option<Token<'src>>,
+    /// Number of nested calls of recursive parse methods in progress.
+    depth: usize,
+    /// Maximum allowed `depth`, to avoid overflowing the stack.
+    max_depth: usize,
+    /// Maximum number of diagnostics `parse_program_recovering` collects.
+    max_diagnostics: usize,
+    /// Conversion of number literals to values.
+    literals: Box<dyn LiteralParser>,
+    /// Whether names are copied out of the source.
+    owned_names: bool,
+    /// Ids of the identifiers parsed so far.
+    interner: Interner,
 }
 
+/// Default maximum nesting depth of expressions.
+const DEFAULT_MAX_DEPTH: usize = 256;
+
+/// Default maximum number of diagnostics reported at once.
+const DEFAULT_MAX_DIAGNOSTICS: usize = 100;
+
 impl<'src> Parser<'src> {
     pub fn new(input: &'src str) -> Result<Parser<'src>, ParseError> {
-        let scanner = Scanner::new(input)?;
-        Ok(Parser { scanner })
+        Parser::new_at(input, 0)
+    }
+
+    /// Create a parser whose scanner uses the given options, for example
+    /// to treat newlines as separators for `parse_lines`.
+    #[allow(dead_code)]
+    pub fn with_scan_options(
+        input: &'src str,
+        options: ScanOptions,
+    ) -> Result<Parser<'src>, ParseError> {
+        Parser::with_scan_options_at(input, 0, options)
+    }
+
+    /// Create a parser that rejects expressions nested more than `depth`
+    /// levels deep with `ParseError::NestingTooDeep`, instead of the
+    /// default of 256 levels. Every parenthesis, abstraction, operand of a
+    /// right-associative operator and so on counts as one level.
+    #[allow(dead_code)]
+    pub fn with_max_depth(input: &'src str, depth: usize) -> Result<Parser<'src>, ParseError> {
+        let mut parser = Parser::new(input)?;
+        parser.max_depth = depth;
+        Ok(parser)
+    }
+
+    /// Create a parser whose `parse_program_recovering` stops after
+    /// `max` diagnostics, instead of the default of 100, so that tools are
+    /// not flooded with errors for badly broken input. With a maximum of 0,
+    /// only the final error is reported for input with any problems.
+    #[allow(dead_code)]
+    pub fn with_max_diagnostics(input: &'src str, max: usize) -> Result<Parser<'src>, ParseError> {
+        let mut parser = Parser::new(input)?;
+        parser.max_diagnostics = max;
+        Ok(parser)
+    }
+
+    /// Create a parser that converts number literals with `literals`
+    /// instead of `I64Literals`, for dialects that decode numbers
+    /// differently.
+    #[allow(dead_code)]
+    pub fn with_literal_parser(
+        input: &'src str,
+        literals: impl LiteralParser + 'static,
+    ) -> Result<Parser<'src>, ParseError> {
+        let mut parser = Parser::new(input)?;
+        parser.literals = Box::new(literals);
+        Ok(parser)
+    }
+
+    /// Create a parser that stores owned copies of names and types in the
+    /// tree instead of borrowing them from `input`, so that `into_owned`
+    /// can turn the tree into one that outlives the source without copying
+    /// them again.
+    #[allow(dead_code)]
+    pub fn with_owned_names(input: &'src str) -> Result<Parser<'src>, ParseError> {
+        let mut parser = Parser::new(input)?;
+        parser.owned_names = true;
+        Ok(parser)
+    }
+
+    /// Create a parser that starts parsing at the given byte offset.
+    fn new_at(input: &'src str, offset: usize) -> Result<Parser<'src>, ParseError> {
+        Parser::with_scan_options_at(input, offset, ScanOptions::default())
+    }
+
+    fn with_scan_options_at(
+        input: &'src str,
+        offset: usize,
+        options: ScanOptions,
+    ) -> Result<Parser<'src>, ParseError> {
+        let scanner = Scanner::with_options_at(input, offset, options)?;
+        let token = scanner.token().clone();
+        Ok(Parser {
+            scanner,
+            operators: PrecedenceTable::default(),
+            token,
+            lookahead: None,
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_diagnostics: DEFAULT_MAX_DIAGNOSTICS,
+            literals: Box::new(I64Literals),
+            owned_names: false,
+            interner: Interner::new(),
+        })
+    }
+
+    /// Run `parse` one nesting level deeper, failing if that exceeds the
+    /// maximum depth.
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        if self.depth >= self.max_depth {
+            return Err(ParseError::NestingTooDeep {
+                depth: self.max_depth,
+                offset: self.token.start(),
+            });
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Move to the next token, taking it from the lookahead buffer if
+    /// present.
+    fn advance(&mut self) -> Result<(), ParseError> {
+        self.token = match self.lookahead.take() {
+            Some(token) => token,
+            None => {
+                self.scanner.advance()?;
+                self.scanner.token().clone()
+            }
+        };
+        Ok(())
+    }
+
+    /// Return the table of the identifiers parsed so far, to share their
+    /// ids with the evaluator; see `eval::eval_program_interned`.
+    #[allow(dead_code)]
+    pub fn into_interner(self) -> Interner {
+        self.interner
+    }
+
+    /// Return the kind of the current token without consuming it.
+    #[allow(dead_code)]
+    pub fn peek(&self) -> TokenKind {
+        self.token.kind()
+    }
+
+    /// Return the kind of the token following the current one, without
+    /// consuming either of them.
+    #[allow(dead_code)]
+    pub fn peek2(&mut self) -> Result<TokenKind, ParseError> {
+        if let Some(token) = &self.lookahead {
+            return Ok(token.kind());
+        }
+        self.scanner.advance()?;
+        let token = self.scanner.token().clone();
+        let kind = token.kind();
+        self.lookahead = Some(token);
+        Ok(kind)
     }
 
     fn accept(&mut self, kind: TokenKind) -> Result<(), ParseError> {
-        if self.scanner.token().kind() == kind {
-            let _ = self.scanner.scan()?;
-            Ok(())
+        if self.token.kind() == kind {
+            self.advance()
         } else {
-            Err(ParseError::Unexpected{expected: kind, found: self.scanner.token().kind()})
+            Err(ParseError::Unexpected {
+                expected: kind,
+                found: self.token.kind(),
+                offset: self.token.start(),
+            })
         }
     }
-    pub fn parse_program(&mut self) -> Result<(), ParseError> {
-        self.accept(TokenKind::Identifier)?;
-        self.accept(TokenKind::Symbol(Symbol::DoubleColon))?;
-        self.accept(TokenKind::Identifier)?;
+    /// Parse a sequence of declarations up to the end of input.
+    pub fn parse_program(&mut self) -> Result<Vec<Declaration<'src>>, ParseError> {
+        let mut declarations = Vec::new();
+        while self.peek() != TokenKind::Eof {
+            declarations.push(self.parse_declaration()?);
+        }
+        self.accept(TokenKind::Eof)?;
+        Ok(declarations)
+    }
+
+    /// Parse the next declaration, or return `None` at the end of input.
+    #[allow(dead_code)]
+    pub fn parse_one_declaration(&mut self) -> Result<Option<Declaration<'src>>, ParseError> {
+        if self.peek() == TokenKind::Eof {
+            return Ok(None);
+        }
+        self.parse_declaration().map(Some)
+    }
+
+    /// Parse `input`, which is the source that `old` was parsed from with
+    /// `inserted` bytes inserted at offset `at`. Declarations before the
+    /// edit are reused as they are, and declarations after it are reused
+    /// with their positions shifted, so that only the declarations around
+    /// the edit are parsed again.
+    #[allow(dead_code)]
+    pub fn reparse_after_insert(
+        input: &'src str,
+        old: &[Declaration<'src>],
+        at: usize,
+        inserted: usize,
+    ) -> Result<Reparse<'src>, ParseError> {
+        // The first declaration that may be affected is the first one that
+        // does not end before the edit.
+        let first = old.iter().position(|d| d.end() > at).unwrap_or(old.len());
+        let mut declarations = old[..first].to_vec();
+        let mut reparsed = Vec::new();
+        let start = declarations.last().map_or(0, |d| d.end());
+        let mut parser = Parser::new_at(input, start)?;
+        let shifted = old[first..].iter().map(|d| d.shifted(inserted));
+        while parser.peek() != TokenKind::Eof {
+            let decl = parser.parse_declaration()?;
+            let end = decl.end();
+            reparsed.push(declarations.len());
+            declarations.push(decl);
+            // Once a reparsed declaration ends where an old one ends, the
+            // remaining input is unchanged.
+            let mut rest = shifted.clone().skip_while(|d| d.end() < end);
+            if rest.next().is_some_and(|d| d.end() == end) {
+                declarations.extend(rest);
+                return Ok(Reparse {
+                    declarations,
+                    reparsed,
+                });
+            }
+        }
+        parser.accept(TokenKind::Eof)?;
+        Ok(Reparse {
+            declarations,
+            reparsed,
+        })
+    }
+
+    /// Parse a program like `parse_program`, but instead of stopping at the
+    /// first error, skip to the next `;` and continue with the following
+    /// declaration. Returns all problems encountered, in the order they were
+    /// found; the program is valid if none of them is an error. Scan errors
+    /// end parsing, because the scanner cannot continue past invalid input.
+    ///
+    /// A name that is defined more than once is reported as a warning, with
+    /// a hint pointing to the previous definition.
+    ///
+    /// Once a problem is found beyond the maximum number of diagnostics,
+    /// parsing stops and a final error saying so is added instead; see
+    /// `with_max_diagnostics`.
+    #[allow(dead_code)]
+    pub fn parse_program_recovering(&mut self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        let mut defined = HashMap::new();
+        while self.peek() != TokenKind::Eof {
+            let done = match self.parse_declaration() {
+                Ok(decl) => {
+                    if let Some(previous) = defined.insert(decl.name.clone(), decl.span()) {
+                        diagnostics.push(Diagnostic::warning(
+                            decl.span(),
+                            format!("{} is defined more than once", decl.name()),
+                        ));
+                        diagnostics.push(Diagnostic::hint(previous, "previous definition is here"));
+                    }
+                    for (guard, body) in decl.unreachable_guards() {
+                        diagnostics.push(Diagnostic::warning(
+                            guard.span().merge(body.span()),
+                            format!("equation of {} after otherwise is unreachable", decl.name()),
+                        ));
+                    }
+                    false
+                }
+                Err(e @ ParseError::ScanError(_)) => {
+                    diagnostics.push(e.into());
+                    true
+                }
+                Err(e) => {
+                    diagnostics.push(e.into());
+                    match self.synchronize() {
+                        Ok(()) => false,
+                        Err(e) => {
+                            diagnostics.push(e.into());
+                            true
+                        }
+                    }
+                }
+            };
+            if diagnostics.len() > self.max_diagnostics {
+                self.stop_reporting(&mut diagnostics);
+                break;
+            }
+            if done {
+                break;
+            }
+        }
+        diagnostics
+    }
+
+    /// Cut `diagnostics` down to the maximum number and add the final
+    /// error of `parse_program_recovering` at the current token.
+    fn stop_reporting(&self, diagnostics: &mut Vec<Diagnostic>) {
+        diagnostics.truncate(self.max_diagnostics);
+        let offset = self.token.start();
+        diagnostics.push(Diagnostic::error(Span::new(offset, offset), "too many errors, stopping"));
+    }
+
+    /// Skip tokens up to and including the next `;`, or up to the end of
+    /// input.
+    fn synchronize(&mut self) -> Result<(), ParseError> {
+        loop {
+            match self.peek() {
+                TokenKind::Eof => return Ok(()),
+                TokenKind::Symbol(Symbol::Semicolon) => return self.advance(),
+                _ => self.advance()?,
+            }
+        }
+    }
+
+    /// Parse a single expression followed by the end of input.
+    #[allow(dead_code)]
+    pub fn parse_script(&mut self) -> Result<Rc<Node<'src, ()>>, ParseError> {
+        let expr = self.parse_expr()?;
+        self.accept(TokenKind::Eof)?;
+        Ok(expr)
+    }
+
+    /// Parse a type as in a signature, with optional constraints, followed
+    /// by the end of input.
+    #[allow(dead_code)]
+    pub fn parse_type_only(&mut self) -> Result<Qualified, ParseError> {
+        let ty = self.parse_type()?;
+        self.accept(TokenKind::Eof)?;
+        Ok(ty)
+    }
+
+    /// Parse a sequence of expressions, each ending with a newline or the
+    /// end of input, as in an interactive session. Requires a scanner that
+    /// produces `Newline` tokens; see `ScanOptions::newline_separators`.
+    #[allow(dead_code)]
+    pub fn parse_lines(&mut self) -> Result<Vec<Rc<Node<'src, ()>>>, ParseError> {
+        self.parse_separated(TokenKind::Newline)
+    }
+
+    /// Parse a sequence of expressions separated by the symbol `sep`, e.g.
+    /// `1; 2; 3` for `Symbol::Semicolon`, up to the end of input. A
+    /// separator after the last expression is allowed.
+    #[allow(dead_code)]
+    pub fn parse_all_exprs(&mut self, sep: Symbol) -> Result<Vec<Rc<Node<'src, ()>>>, ParseError> {
+        self.parse_separated(TokenKind::Symbol(sep))
+    }
+
+    /// Parse expressions, each followed by `sep` or the end of input.
+    fn parse_separated(&mut self, sep: TokenKind) -> Result<Vec<Rc<Node<'src, ()>>>, ParseError> {
+        let mut exprs = Vec::new();
+        while self.peek() != TokenKind::Eof {
+            exprs.push(self.parse_expr()?);
+            match self.peek() {
+                TokenKind::Eof => {}
+                found if found == sep => self.advance()?,
+                found => {
+                    return Err(ParseError::Unexpected {
+                        expected: sep,
+                        found,
+                        offset: self.token.start(),
+                    })
+                }
+            }
+        }
+        Ok(exprs)
+    }
+
+    /// Parse an expression.
+    fn parse_expr(&mut self) -> Result<Rc<Node<'src, ()>>, ParseError> {
+        self.nested(|parser| match parser.peek() {
+            TokenKind::Symbol(Symbol::Backslash) | TokenKind::Keyword(Keyword::Fun) => {
+                parser.parse_abs()
+            }
+            TokenKind::Keyword(Keyword::Let) => parser.parse_let(),
+            _ => parser.parse_binary(0),
+        })
+    }
+
+    /// Parse a binding expression `let x = e1; y = e2 in body`. The body
+    /// extends as far to the right as possible.
+    fn parse_let(&mut self) -> Result<Rc<Node<'src, ()>>, ParseError> {
+        let start = self.token.span();
+        let (bindings, has_in) = self.parse_bindings()?;
+        if !has_in {
+            return Err(ParseError::Unexpected {
+                expected: TokenKind::Keyword(Keyword::In),
+                found: self.peek(),
+                offset: self.token.start(),
+            });
+        }
+        let body = self.parse_expr()?;
+        Ok(Rc::new(Node::new(
+            start.merge(body.span()),
+            (),
+            NodeKind::Let { bindings, body },
+        )))
+    }
+
+    /// Parse the bindings `let x = e1; y = e2 ...` of a `let`, including the
+    /// `;` or `in` that follows the last one, and return them together with
+    /// whether they were followed by `in`.
+    fn parse_bindings(&mut self) -> Result<(Vec<Binding<'src, ()>>, bool), ParseError> {
+        self.accept(TokenKind::Keyword(Keyword::Let))?;
+        let mut bindings = Vec::new();
+        loop {
+            let name = self.parse_name()?;
+            self.accept(TokenKind::Symbol(Symbol::Eq))?;
+            bindings.push((name, self.parse_expr()?));
+            match self.peek() {
+                TokenKind::Keyword(Keyword::In) => {
+                    self.advance()?;
+                    return Ok((bindings, true));
+                }
+                TokenKind::Symbol(Symbol::Semicolon) => self.advance()?,
+                found => {
+                    return Err(ParseError::Unexpected {
+                        expected: TokenKind::Keyword(Keyword::In),
+                        found,
+                        offset: self.token.start(),
+                    })
+                }
+            }
+            // Another binding starts with `name =`, anything else is the
+            // rest of a block.
+            if self.peek() != TokenKind::Identifier
+                || self.peek2()? != TokenKind::Symbol(Symbol::Eq)
+            {
+                return Ok((bindings, false));
+            }
+        }
+    }
+
+    /// Parse an abstraction `\ x y . body` or `fun x y . body`. Multiple
+    /// parameters result in nested abstractions, one per parameter.
+    fn parse_abs(&mut self) -> Result<Rc<Node<'src, ()>>, ParseError> {
+        let span = self.token.span();
+        self.advance()?;
+        let mut params = vec![self.parse_name()?];
+        while self.peek() == TokenKind::Identifier {
+            params.push(self.parse_name()?);
+        }
+        self.accept(TokenKind::Symbol(Symbol::Dot))?;
+        let mut body = self.parse_expr()?;
+        while let Some(param) = params.pop() {
+            let start = if params.is_empty() { span } else { param.span() };
+            body = Rc::new(Node::new(
+                start.merge(body.span()),
+                (),
+                NodeKind::Abs { param, body },
+            ));
+        }
+        Ok(body)
+    }
+
+    /// Parse a conditional `if cond then conseq else alt end` or a
+    /// multi-way conditional `if | c1 => e1 | c2 => e2 end`. Since it is
+    /// terminated by `end`, a conditional can be used like an atom.
+    fn parse_if(&mut self) -> Result<Rc<Node<'src, ()>>, ParseError> {
+        let start = self.token.span();
+        self.accept(TokenKind::Keyword(Keyword::If))?;
+        if self.peek() == TokenKind::Symbol(Symbol::Bar) {
+            return self.parse_multi_if(start);
+        }
+        let cond = self.parse_expr()?;
+        self.accept(TokenKind::Keyword(Keyword::Then))?;
+        let conseq = self.parse_expr()?;
+        self.accept(TokenKind::Keyword(Keyword::Else))?;
+        let alt = self.parse_expr()?;
+        let end = self.token.span();
+        self.accept(TokenKind::Keyword(Keyword::End))?;
+        Ok(Rc::new(Node::new(
+            start.merge(end),
+            (),
+            NodeKind::If { cond, conseq, alt },
+        )))
+    }
+
+    /// Parse the arms `| cond => body` of a multi-way conditional that
+    /// starts at `start`, up to the `end`. An arm `| otherwise => body`
+    /// applies if no condition is true, and must be the last one.
+    fn parse_multi_if(&mut self, start: Span) -> Result<Rc<Node<'src, ()>>, ParseError> {
+        let mut arms = Vec::new();
+        let mut otherwise = None;
+        while self.peek() != TokenKind::Keyword(Keyword::End) {
+            self.accept(TokenKind::Symbol(Symbol::Bar))?;
+            if self.peek() == TokenKind::Identifier
+                && self.token.text == "otherwise"
+                && self.peek2()? == TokenKind::Symbol(Symbol::FatArrow)
+            {
+                self.advance()?;
+                self.advance()?;
+                otherwise = Some(self.parse_expr()?);
+                break;
+            }
+            let cond = self.parse_expr()?;
+            self.accept(TokenKind::Symbol(Symbol::FatArrow))?;
+            arms.push((cond, self.parse_expr()?));
+        }
+        let end = self.token.span();
+        self.accept(TokenKind::Keyword(Keyword::End))?;
+        Ok(Rc::new(Node::new(
+            start.merge(end),
+            (),
+            NodeKind::MultiIf { arms, otherwise },
+        )))
+    }
+
+    /// Parse the contents of a block `{ item; ...; expr }`, where each item
+    /// is either an expression, evaluated for its effects, or bindings
+    /// `let x = e1; y = e2` that are in scope in the rest of the block. The
+    /// bindings are sequential, so `y` is also in scope in `e2`. The final
+    /// expression is the value of the block.
+    fn parse_block_items(&mut self) -> Result<Rc<Node<'src, ()>>, ParseError> {
+        let start = self.token.span();
+        let first = if self.peek() == TokenKind::Keyword(Keyword::Let) {
+            let (mut bindings, has_in) = self.parse_bindings()?;
+            if !has_in {
+                // One `let` per binding, each in the scope of the previous
+                // ones.
+                let mut body = self.nested(Self::parse_block_items)?;
+                while let Some(binding) = bindings.pop() {
+                    let start = if bindings.is_empty() { start } else { binding.0.span() };
+                    body = Rc::new(Node::new(
+                        start.merge(body.span()),
+                        (),
+                        NodeKind::Let { bindings: vec![binding], body },
+                    ));
+                }
+                return Ok(body);
+            }
+            let body = self.parse_expr()?;
+            Rc::new(Node::new(
+                start.merge(body.span()),
+                (),
+                NodeKind::Let { bindings, body },
+            ))
+        } else {
+            self.parse_expr()?
+        };
+        if self.peek() != TokenKind::Symbol(Symbol::Semicolon) {
+            return Ok(first);
+        }
+        self.advance()?;
+        let second = self.nested(Self::parse_block_items)?;
+        Ok(Rc::new(Node::new(
+            first.span().merge(second.span()),
+            (),
+            NodeKind::Seq { first, second },
+        )))
+    }
+
+    /// Parse an identifier into a `Name` node.
+    fn parse_name(&mut self) -> Result<Rc<Node<'src, ()>>, ParseError> {
+        let span = self.token.span();
+        let name = self.parse_identifier()?;
+        Ok(Rc::new(Node::new(span, (), self.name_kind(name))))
+    }
+
+    /// Parse a sequence of operands separated by binary operators of at
+    /// least the given precedence, grouping operators by precedence and
+    /// associativity.
+    fn parse_binary(&mut self, min_prec: u8) -> Result<Rc<Node<'src, ()>>, ParseError> {
+        let mut lhs = self.parse_operand()?;
+        while let Some((op, prec, assoc)) = self.operators.lookup(self.peek()) {
+            if prec < min_prec {
+                break;
+            }
+            let op_span = self.token.span();
+            self.advance()?;
+            let rhs = match assoc {
+                Assoc::Left => self.nested(|parser| parser.parse_binary(prec + 1))?,
+                Assoc::Right => self.nested(|parser| parser.parse_binary(prec))?,
+            };
+            let span = lhs.span().merge(rhs.span());
+            let kind = match op {
+                Operator::BinOp(op) => NodeKind::BinOp { op, op_span, lhs, rhs },
+                Operator::Pipe => NodeKind::App { fun: rhs, arg: lhs },
+            };
+            lhs = Rc::new(Node::new(span, (), kind));
+        }
+        Ok(lhs)
+    }
+
+    /// Parse an operand of a binary operator, an application that may be
+    /// negated.
+    fn parse_operand(&mut self) -> Result<Rc<Node<'src, ()>>, ParseError> {
+        if self.at_unary_minus() {
+            self.parse_negation()
+        } else {
+            self.parse_app()
+        }
+    }
+
+    /// Return whether the current token is a `-` that does not follow an
+    /// operand, as in `-x` or `2 * -x`, so that it negates what follows.
+    /// In `f -1`, it subtracts.
+    fn at_unary_minus(&self) -> bool {
+        // `peek2` is never called on a `-`, so the scanner is on the current
+        // token, and its previous token is the one before it.
+        self.peek() == TokenKind::Symbol(Symbol::Minus) && !self.scanner.prev_kind().is_some_and(ends_operand)
+    }
+
+    /// Parse a unary minus and its operand. A negated number literal is a
+    /// negative literal; other operands are subtracted from 0.
+    fn parse_negation(&mut self) -> Result<Rc<Node<'src, ()>>, ParseError> {
+        let op_span = self.token.span();
+        self.advance()?;
+        let operand = self.nested(Self::parse_operand)?;
+        let span = op_span.merge(operand.span());
+        let kind = match operand.kind() {
+            NodeKind::Lit { lit: Literal::Int(i) } => NodeKind::Lit { lit: Literal::Int(-i) },
+            _ => NodeKind::BinOp {
+                op: BinOp::Sub,
+                op_span,
+                lhs: Rc::new(Node::new(op_span, (), NodeKind::Lit { lit: Literal::Int(0) })),
+                rhs: operand,
+            },
+        };
+        Ok(Rc::new(Node::new(span, (), kind)))
+    }
+
+    /// Parse a sequence of atoms as a left-associative application, so that
+    /// `f x y` is `(f x) y`.
+    fn parse_app(&mut self) -> Result<Rc<Node<'src, ()>>, ParseError> {
+        let mut fun = self.parse_annotated_atom()?;
+        while starts_atom(self.peek()) {
+            let arg = self.parse_annotated_atom()?;
+            fun = Rc::new(Node::new(
+                fun.span().merge(arg.span()),
+                (),
+                NodeKind::App { fun, arg },
+            ));
+        }
+        Ok(fun)
+    }
+
+    /// Parse an atom, optionally followed by type annotations `@Type`, so
+    /// that `f x @Integer` annotates only `x`. Types other than names must
+    /// be parenthesized, as in `f @(Int -> Int)`.
+    fn parse_annotated_atom(&mut self) -> Result<Rc<Node<'src, ()>>, ParseError> {
+        let mut expr = self.parse_atom()?;
+        while self.peek() == TokenKind::Symbol(Symbol::At) {
+            self.advance()?;
+            let (span, ty) = if self.peek() == TokenKind::Symbol(Symbol::LParen) {
+                self.advance()?;
+                let ty = self.parse_fun_type()?;
+                let span = expr.span().merge(self.token.span());
+                self.accept(TokenKind::Symbol(Symbol::RParen))?;
+                (span, ty)
+            } else {
+                (expr.span().merge(self.token.span()), self.parse_type_atom()?)
+            };
+            let ty = Rc::new(Qualified { constraints: Vec::new(), ty });
+            expr = Rc::new(Node::new(span, (), NodeKind::Ann { expr, ty }));
+        }
+        Ok(expr)
+    }
+
+    /// Parse a name, a literal, the unit value `()`, a parenthesized
+    /// expression, an annotated expression `(expr : Type)`, a list or a
+    /// borrow `&atom`.
+    fn parse_atom(&mut self) -> Result<Rc<Node<'src, ()>>, ParseError> {
+        let span = self.token.span();
+        let kind = match self.peek() {
+            TokenKind::Symbol(Symbol::Ampersand) => {
+                self.advance()?;
+                let expr = self.nested(Self::parse_atom)?;
+                let span = span.merge(expr.span());
+                return Ok(Rc::new(Node::new(span, (), NodeKind::Borrow { expr })));
+            }
+            TokenKind::Keyword(Keyword::If) => return self.parse_if(),
+            TokenKind::Symbol(Symbol::LBracket) => return self.parse_list(),
+            TokenKind::Symbol(Symbol::LBrace) => {
+                self.advance()?;
+                let expr = self.parse_block_items()?;
+                let span = span.merge(self.token.span());
+                self.accept(TokenKind::Symbol(Symbol::RBrace))?;
+                // The node covers the braces, too.
+                let kind = expr.kind().map_children(Rc::clone);
+                return Ok(Rc::new(Node::new(span, (), kind)));
+            }
+            TokenKind::Symbol(Symbol::LParen) => {
+                self.advance()?;
+                if self.peek() == TokenKind::Symbol(Symbol::RParen) {
+                    let span = span.merge(self.token.span());
+                    self.advance()?;
+                    let kind = NodeKind::Lit { lit: Literal::Unit };
+                    return Ok(Rc::new(Node::new(span, (), kind)));
+                }
+                let expr = self.parse_expr()?;
+                let kind = if self.peek() == TokenKind::Symbol(Symbol::Colon) {
+                    self.advance()?;
+                    let ty = Rc::new(self.parse_type()?);
+                    NodeKind::Ann { expr, ty }
+                } else {
+                    // The node covers the parentheses, too.
+                    expr.kind().map_children(Rc::clone)
+                };
+                let span = span.merge(self.token.span());
+                self.accept(TokenKind::Symbol(Symbol::RParen))?;
+                return Ok(Rc::new(Node::new(span, (), kind)));
+            }
+            TokenKind::Identifier => self.name_kind(self.identifier_text()),
+            TokenKind::Number => {
+                let digits = self.token.text.replace('_', "");
+                let lit = self.literals.parse_int(&digits, 10).map_err(|error| match error {
+                    LitError::Overflow => ParseError::IntegerOverflow { offset: span.start },
+                    error => ParseError::InvalidLiteral { offset: span.start, error },
+                })?;
+                NodeKind::Lit { lit }
+            }
+            TokenKind::String => NodeKind::Lit {
+                lit: Literal::String(self.token.text.to_string()),
+            },
+            TokenKind::Keyword(keyword) => {
+                return Err(ParseError::UnexpectedKeywordInExpression { keyword, span })
+            }
+            found => {
+                return Err(ParseError::ExpectedExpression {
+                    offset: span.start,
+                    found,
+                })
+            }
+        };
+        self.advance()?;
+        Ok(Rc::new(Node::new(span, (), kind)))
+    }
+
+    /// Parse a list `[a, b, c]` or a list comprehension
+    /// `[body | x <- xs, y <- ys, guard]`. The generators `name <- list` come
+    /// before the guards.
+    fn parse_list(&mut self) -> Result<Rc<Node<'src, ()>>, ParseError> {
+        let start = self.token.span();
+        self.accept(TokenKind::Symbol(Symbol::LBracket))?;
+        let mut elems = Vec::new();
+        let kind = if self.peek() == TokenKind::Symbol(Symbol::RBracket) {
+            NodeKind::List { elems }
+        } else {
+            let first = self.parse_expr()?;
+            if self.peek() == TokenKind::Symbol(Symbol::Bar) {
+                self.advance()?;
+                self.parse_qualifiers(first)?
+            } else {
+                elems.push(first);
+                while self.peek() == TokenKind::Symbol(Symbol::Comma) {
+                    self.advance()?;
+                    elems.push(self.parse_expr()?);
+                }
+                NodeKind::List { elems }
+            }
+        };
+        let span = start.merge(self.token.span());
+        self.accept(TokenKind::Symbol(Symbol::RBracket))?;
+        Ok(Rc::new(Node::new(span, (), kind)))
+    }
+
+    /// Parse the comma-separated generators and guards of a comprehension
+    /// with the given body, after the `|`.
+    fn parse_qualifiers(&mut self, body: Rc<Node<'src, ()>>) -> Result<NodeKind<'src, ()>, ParseError> {
+        let mut generators = Vec::new();
+        let mut guards = Vec::new();
+        loop {
+            if guards.is_empty()
+                && self.peek() == TokenKind::Identifier
+                && self.peek2()? == TokenKind::Symbol(Symbol::LeftArrow)
+            {
+                let name = self.parse_name()?;
+                self.advance()?;
+                generators.push((name, self.parse_expr()?));
+            } else {
+                guards.push(self.parse_expr()?);
+            }
+            if self.peek() != TokenKind::Symbol(Symbol::Comma) {
+                break;
+            }
+            self.advance()?;
+        }
+        Ok(NodeKind::Comprehension { body, generators, guards })
+    }
+
+    /// Parse a definition `name param... = expr;` or a guarded definition
+    /// `name param... | guard = expr ...;`, optionally preceded by
+    /// attributes and a type signature `name :: Type;` for the same name.
+    fn parse_declaration(&mut self) -> Result<Declaration<'src>, ParseError> {
+        let start = self.token.start();
+        let mut attributes = Vec::new();
+        while self.peek() == TokenKind::Symbol(Symbol::At) {
+            attributes.push(self.parse_attribute()?);
+        }
+        let mut ty = None;
+        if self.peek2()? == TokenKind::Symbol(Symbol::DoubleColon) {
+            let name = self.parse_identifier()?;
+            self.advance()?;
+            ty = Some(self.parse_type()?);
+            self.accept(TokenKind::Symbol(Symbol::Semicolon))?;
+            if self.peek() != TokenKind::Identifier || self.token.text != name {
+                return Err(ParseError::MissingDefinition {
+                    offset: start,
+                    name: name.into_owned(),
+                });
+            }
+        }
+        let name = self.parse_identifier()?;
+        let mut params = Vec::new();
+        while self.peek() == TokenKind::Identifier {
+            params.push(self.parse_name()?);
+        }
+        let (guards, body) = if self.peek() == TokenKind::Symbol(Symbol::Bar) {
+            self.parse_guards()?
+        } else {
+            self.accept(TokenKind::Symbol(Symbol::Eq))?;
+            (Vec::new(), self.parse_expr()?)
+        };
+        let end = self.token.end();
         self.accept(TokenKind::Symbol(Symbol::Semicolon))?;
+        Ok(Declaration {
+            attributes,
+            name,
+            ty,
+            params,
+            guards,
+            body,
+            span: Span::new(start, end),
+        })
+    }
+
+    /// Parse the guarded equations `| guard = body ...` of a definition, and
+    /// desugar them into conditionals that try the guards in order. The
+    /// guard `otherwise` is always true and ends the conditionals; later
+    /// equations are kept in the guards, but never selected. Without
+    /// `otherwise`, evaluation fails if no guard is true.
+    fn parse_guards(&mut self) -> Result<(Vec<Guard<'src>>, Rc<Node<'src, ()>>), ParseError> {
+        let mut guards = Vec::new();
+        while self.peek() == TokenKind::Symbol(Symbol::Bar) {
+            self.advance()?;
+            let guard = self.parse_expr()?;
+            self.accept(TokenKind::Symbol(Symbol::Eq))?;
+            guards.push((guard, self.parse_expr()?));
+        }
+        let Some(otherwise) = guards.iter().position(|(guard, _)| guard.as_name() == Some("otherwise")) else {
+            let span = guards[0].0.span().merge(guards[guards.len() - 1].1.span());
+            let kind = NodeKind::MultiIf {
+                arms: guards.clone(),
+                otherwise: None,
+            };
+            return Ok((guards, Rc::new(Node::new(span, (), kind))));
+        };
+        let body = guards[..otherwise]
+            .iter()
+            .rev()
+            .fold(guards[otherwise].1.clone(), |alt, (cond, conseq)| {
+                Rc::new(Node::new(
+                    cond.span().merge(alt.span()),
+                    (),
+                    NodeKind::If {
+                        cond: cond.clone(),
+                        conseq: conseq.clone(),
+                        alt,
+                    },
+                ))
+            });
+        Ok((guards, body))
+    }
+
+    /// Parse an attribute `@name` or `@[entry, ...]`, where each entry is a
+    /// name or `name = value` with a number or string as the value.
+    fn parse_attribute(&mut self) -> Result<Attribute<'src>, ParseError> {
+        let start = self.token.span();
+        self.accept(TokenKind::Symbol(Symbol::At))?;
+        if self.peek() == TokenKind::Identifier {
+            let span = start.merge(self.token.span());
+            let name = self.parse_identifier()?;
+            return Ok(Attribute {
+                entries: vec![AttributeEntry::Flag(name)],
+                span,
+            });
+        }
+        self.accept(TokenKind::Symbol(Symbol::LBracket))?;
+        let mut entries = Vec::new();
+        loop {
+            let name = self.parse_identifier()?;
+            if self.peek() == TokenKind::Symbol(Symbol::Eq) {
+                self.advance()?;
+                entries.push(AttributeEntry::Value(name, self.parse_attribute_value()?));
+            } else {
+                entries.push(AttributeEntry::Flag(name));
+            }
+            if self.peek() != TokenKind::Symbol(Symbol::Comma) {
+                break;
+            }
+            self.advance()?;
+        }
+        let span = start.merge(self.token.span());
+        self.accept(TokenKind::Symbol(Symbol::RBracket))?;
+        Ok(Attribute { entries, span })
+    }
+
+    /// Parse the value of an attribute entry, a number or a string.
+    fn parse_attribute_value(&mut self) -> Result<AttributeValue<'src>, ParseError> {
+        let value = match self.peek() {
+            TokenKind::Number => AttributeValue::Int(self.token.as_i64().ok_or(
+                ParseError::IntegerOverflow {
+                    offset: self.token.start(),
+                },
+            )?),
+            TokenKind::String => AttributeValue::String(self.token.text.clone()),
+            found => {
+                return Err(ParseError::ExpectedAttributeValue {
+                    offset: self.token.start(),
+                    found,
+                })
+            }
+        };
+        self.advance()?;
+        Ok(value)
+    }
+
+    /// Parse the type of a signature, optionally preceded by constraints on
+    /// its type variables, as in `Eq a => a -> a` or
+    /// `(Eq a, Show a) => a -> String`.
+    fn parse_type(&mut self) -> Result<Qualified, ParseError> {
+        let mut constraints = Vec::new();
+        // A constraint starts with two identifiers, which no type does.
+        let ty = if self.peek() == TokenKind::Identifier && self.peek2()? == TokenKind::Identifier {
+            constraints.push(self.parse_constraint()?);
+            self.accept(TokenKind::Symbol(Symbol::FatArrow))?;
+            self.parse_fun_type()?
+        } else if self.peek() == TokenKind::Symbol(Symbol::LParen) {
+            self.advance()?;
+            if self.peek() == TokenKind::Identifier && self.peek2()? == TokenKind::Identifier {
+                constraints.push(self.parse_constraint()?);
+                while self.peek() == TokenKind::Symbol(Symbol::Comma) {
+                    self.advance()?;
+                    constraints.push(self.parse_constraint()?);
+                }
+                self.accept(TokenKind::Symbol(Symbol::RParen))?;
+                self.accept(TokenKind::Symbol(Symbol::FatArrow))?;
+                self.parse_fun_type()?
+            } else {
+                let param = self.parse_fun_type()?;
+                self.accept(TokenKind::Symbol(Symbol::RParen))?;
+                self.parse_fun_type_from(param)?
+            }
+        } else {
+            self.parse_fun_type()?
+        };
+        Ok(Qualified { constraints, ty })
+    }
+
+    /// Parse a constraint `Class var`.
+    fn parse_constraint(&mut self) -> Result<(ClassName, TypeVar), ParseError> {
+        let class = self.parse_identifier()?;
+        let var = self.parse_identifier()?;
+        Ok((class.into_owned(), var.into_owned()))
+    }
+
+    /// Parse a type without constraints, where `->` associates to the right.
+    fn parse_fun_type(&mut self) -> Result<Type, ParseError> {
+        let param = self.nested(Self::parse_type_atom)?;
+        self.parse_fun_type_from(param)
+    }
+
+    /// Parse the rest of a function type after its already parsed
+    /// parameter type, if it is followed by `->`.
+    fn parse_fun_type_from(&mut self, param: Type) -> Result<Type, ParseError> {
+        if self.peek() != TokenKind::Symbol(Symbol::Arrow) {
+            return Ok(param);
+        }
+        self.advance()?;
+        let result = self.nested(Self::parse_fun_type)?;
+        Ok(Type::Fun(Rc::new(param), Rc::new(result)))
+    }
+
+    /// Parse a type name, a type variable, which starts with a lowercase
+    /// letter, or a parenthesized type.
+    fn parse_type_atom(&mut self) -> Result<Type, ParseError> {
+        if self.peek() == TokenKind::Symbol(Symbol::LParen) {
+            self.advance()?;
+            let ty = self.parse_fun_type()?;
+            self.accept(TokenKind::Symbol(Symbol::RParen))?;
+            return Ok(ty);
+        }
+        let name = self.parse_identifier()?;
+        Ok(match name.as_ref() {
+            "Int" => Type::Int,
+            _ if name.starts_with(|c: char| c.is_ascii_lowercase() || c == '_') => Type::Var(name.into_owned()),
+            _ => Type::Con(name.into_owned()),
+        })
+    }
+
+    /// Accept an identifier and return its text.
+    fn parse_identifier(&mut self) -> Result<Cow<'src, str>, ParseError> {
+        let text = self.identifier_text();
         self.accept(TokenKind::Identifier)?;
-        self.accept(TokenKind::Symbol(Symbol::Eq))?;
-        self.accept(TokenKind::Number)?;
-        self.accept(TokenKind::Symbol(Symbol::Semicolon))?;
-        self.accept(TokenKind::Eof)?;
-        Ok(())
+        Ok(text)
+    }
+
+    /// Make the kind of a `Name` node for `name`, interning it.
+    fn name_kind(&mut self, name: Cow<'src, str>) -> NodeKind<'src, ()> {
+        let id = Some(self.interner.intern(&name));
+        NodeKind::Name { name, id }
+    }
+
+    /// Return the text of the current token, owned if names are owned.
+    fn identifier_text(&self) -> Cow<'src, str> {
+        if self.owned_names {
+            Cow::Owned(self.token.text_owned())
+        } else {
+            self.token.text.clone()
+        }
+    }
+}
+
+/// Parse the declarations of `input` one at a time, as the iterator is
+/// advanced, so that the whole program never has to be in memory. The
+/// iterator ends at the end of input or after yielding the first error.
+#[allow(dead_code)]
+pub fn declarations<'src>(input: &'src str) -> impl Iterator<Item = Result<Declaration<'src>, ParseError>> {
+    let mut parser = Some(Parser::new(input));
+    std::iter::from_fn(move || match parser.take()? {
+        Ok(mut p) => {
+            let decl = p.parse_one_declaration().transpose()?;
+            if decl.is_ok() {
+                parser = Some(Ok(p));
+            }
+            Some(decl)
+        }
+        Err(e) => Some(Err(e)),
+    })
+}
+
+/// Return whether a token of the given kind can end an operand, so that a
+/// `-` after it is a binary operator.
+fn ends_operand(kind: TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::Identifier
+            | TokenKind::Number
+            | TokenKind::Float
+            | TokenKind::String
+            | TokenKind::Char
+            | TokenKind::MetaVar
+            | TokenKind::Symbol(Symbol::RParen | Symbol::RBrace | Symbol::RBracket)
+            | TokenKind::Keyword(Keyword::End)
+    )
+}
+
+/// Return whether a token of the given kind can start an atom.
+fn starts_atom(kind: TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::Identifier
+            | TokenKind::Number
+            | TokenKind::String
+            | TokenKind::Symbol(Symbol::LParen)
+            | TokenKind::Symbol(Symbol::Ampersand)
+            | TokenKind::Symbol(Symbol::LBrace)
+            | TokenKind::Symbol(Symbol::LBracket)
+            | TokenKind::Keyword(Keyword::If)
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        ast::{alpha_eq, assert_tree_eq, into_owned},
+        diagnostic::Severity,
+    };
+
+    fn parse_err(input: &str) -> ParseError {
+        Parser::new(input)
+            .and_then(|mut parser| parser.parse_program())
+            .expect_err("should fail")
+    }
+
+    #[test]
+    fn lookahead() {
+        let mut parser = Parser::new("main :: Integer;").expect("scanning example input");
+        assert_eq!(parser.peek(), TokenKind::Identifier);
+        assert_eq!(parser.peek2().expect("peeking"), TokenKind::Symbol(Symbol::DoubleColon));
+        assert_eq!(parser.peek2().expect("peeking"), TokenKind::Symbol(Symbol::DoubleColon));
+        assert_eq!(parser.peek(), TokenKind::Identifier);
+        assert_eq!(parser.token.raw_text(), "main");
+
+        parser.accept(TokenKind::Identifier).expect("accepting identifier");
+        assert_eq!(parser.peek(), TokenKind::Symbol(Symbol::DoubleColon));
+        assert_eq!(parser.token.raw_text(), "::");
+        assert_eq!(parser.token.start(), 5);
+        assert_eq!(parser.token.end(), 7);
+
+        parser.accept(TokenKind::Symbol(Symbol::DoubleColon)).expect("accepting '::'");
+        assert_eq!(parser.peek2().expect("peeking"), TokenKind::Symbol(Symbol::Semicolon));
+        parser.accept(TokenKind::Identifier).expect("accepting identifier");
+        assert_eq!(parser.token.start(), 15);
+        parser.accept(TokenKind::Symbol(Symbol::Semicolon)).expect("accepting ';'");
+        assert_eq!(parser.peek(), TokenKind::Eof);
+    }
+
+    #[test]
+    fn error_messages() {
+        let mut parser = Parser::new("= x").expect("scanning example input");
+        let e = parser.accept(TokenKind::Symbol(Symbol::DoubleColon)).expect_err("should fail");
+        assert_eq!(e.to_string(), "expected '::', found '=' instead");
+
+        let mut parser = Parser::new("if a then b else c").expect("scanning example input");
+        let e = parser.parse_script().expect_err("should fail");
+        assert_eq!(e.to_string(), "expected 'end', found end of input instead");
+
+        let mut parser = Parser::new("main 1").expect("scanning example input");
+        let e = parser.parse_program().expect_err("should fail");
+        assert_eq!(e.to_string(), "expected '=', found number instead");
+    }
+
+    #[test]
+    fn render() {
+        let source = "main :: Integer;\nmain = 1 + ;\n";
+        let e = parse_err(source);
+        assert_eq!(e.render(source), "main = 1 + ;\n           ^");
+
+        let source = "main = $";
+        let e = parse_err(source);
+        assert!(matches!(e, ParseError::ScanError(ScanError::UnexpectedCharacter { offset: 7, .. })));
+        assert_eq!(e.render(source), "main = $\n       ^");
+
+        // At the end of input.
+        let source = "main = (1";
+        assert_eq!(parse_err(source).render(source), "main = (1\n         ^");
+    }
+
+    #[test]
+    fn recovering() {
+        let mut parser = Parser::new("main :: ; main = 2; other = ; last = 3;")
+            .expect("scanning example input");
+        let diagnostics = parser.parse_program_recovering();
+        assert_eq!(
+            diagnostics,
+            [
+                Diagnostic::error(Span::new(8, 8), "expected identifier, found ';' instead"),
+                Diagnostic::error(
+                    Span::new(28, 28),
+                    "expected expression at offset 28, found ';' instead"
+                ),
+            ]
+        );
+
+        let mut parser = Parser::new("main :: Integer; main = 2;").expect("scanning example input");
+        assert!(parser.parse_program_recovering().is_empty());
+
+        // Scan errors stop the parser.
+        let mut parser = Parser::new("main = $; main = 2;").expect("scanning example input");
+        let diagnostics = parser.parse_program_recovering();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].span, Span::new(7, 7));
+    }
+
+    #[test]
+    fn diagnostic_limit() {
+        let source = "a = ;".repeat(10);
+        let mut parser = Parser::with_max_diagnostics(&source, 3).expect("scanning example input");
+        let diagnostics = parser.parse_program_recovering();
+        assert_eq!(diagnostics.len(), 4);
+        assert_eq!(diagnostics[2].span, Span::new(14, 14));
+        assert_eq!(diagnostics[3], Diagnostic::error(Span::new(20, 20), "too many errors, stopping"));
+
+        // A warning and its hint may be cut apart.
+        let mut parser = Parser::with_max_diagnostics("a = ; b = 1; b = 2; c = 3;", 2).expect("scanning example input");
+        let diagnostics = parser.parse_program_recovering();
+        assert_eq!(diagnostics.len(), 3);
+        assert_eq!(diagnostics[1].severity, Severity::Warning);
+        assert_eq!(diagnostics[2].message, "too many errors, stopping");
+
+        // So may a warning at the end of input.
+        let mut parser = Parser::with_max_diagnostics("b = 1; b = 2;", 1).expect("scanning example input");
+        let diagnostics = parser.parse_program_recovering();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[1], Diagnostic::error(Span::new(13, 13), "too many errors, stopping"));
+
+        // Reaching the limit at the end of input is fine.
+        let mut parser = Parser::with_max_diagnostics(&source[..10], 2).expect("scanning example input");
+        assert_eq!(parser.parse_program_recovering().len(), 2);
+        // So is reaching it before valid declarations.
+        let mut parser = Parser::with_max_diagnostics("a = ; b = 1; c = 2;", 1).expect("scanning example input");
+        assert_eq!(parser.parse_program_recovering().len(), 1);
+
+        // Without any diagnostics allowed, valid input has none, and invalid
+        // input only the final error.
+        let mut parser = Parser::with_max_diagnostics("a = 1; b = 2;", 0).expect("scanning example input");
+        assert_eq!(parser.parse_program_recovering(), []);
+        let mut parser = Parser::with_max_diagnostics("a = 1; b = ;", 0).expect("scanning example input");
+        assert_eq!(
+            parser.parse_program_recovering(),
+            [Diagnostic::error(Span::new(12, 12), "too many errors, stopping")]
+        );
+
+        let source = "a = ;".repeat(150);
+        let mut parser = Parser::new(&source).expect("scanning example input");
+        assert_eq!(parser.parse_program_recovering().len(), 101);
+    }
+
+    #[test]
+    fn severities() {
+        let source = "main = 1; other = ; main = 2;";
+        let mut parser = Parser::new(source).expect("scanning example input");
+        let diagnostics = parser.parse_program_recovering();
+        assert_eq!(
+            diagnostics,
+            [
+                Diagnostic::error(
+                    Span::new(18, 18),
+                    "expected expression at offset 18, found ';' instead"
+                ),
+                Diagnostic::warning(Span::new(20, 29), "main is defined more than once"),
+                Diagnostic::hint(Span::new(0, 9), "previous definition is here"),
+            ]
+        );
+        assert_eq!(diagnostics[1].to_string(), "warning: main is defined more than once");
+        assert_eq!(diagnostics[2].render(source), format!("{source}\n^"));
+        let errors = diagnostics.iter().filter(|d| d.severity == Severity::Error).count();
+        assert_eq!(errors, 1);
+    }
+
+    fn apply_edit(source: &str, at: usize, insert: &str) -> String {
+        let mut edited = source.to_string();
+        edited.insert_str(at, insert);
+        edited
+    }
+
+    fn assert_same_declarations(actual: &[Declaration], expected: &[Declaration]) {
+        let summary = |ds: &[Declaration]| {
+            ds.iter()
+                .map(|d| {
+                    let body = d.body();
+                    (d.name().to_string(), d.start(), d.end(), body.to_string(), body.start(), body.end())
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(summary(actual), summary(expected));
+    }
+
+    #[test]
+    fn incremental() {
+        let source = "a = 1;\nb = 2;\nc = 3;";
+        let old = Parser::new(source)
+            .and_then(|mut p| p.parse_program())
+            .expect("parsing example input");
+        assert_eq!(old.len(), 3);
+
+        // Insert a digit into the second declaration.
+        let edited = apply_edit(source, 11, "0");
+        let reparse = Parser::reparse_after_insert(&edited, &old, 11, 1).expect("reparsing");
+        let reference = Parser::new(&edited)
+            .and_then(|mut p| p.parse_program())
+            .expect("parsing example input");
+        assert_same_declarations(&reparse.declarations, &reference);
+        assert_eq!(reparse.reparsed, vec![1]);
+        assert_eq!(reparse.declarations[2].start(), 15);
+        assert_eq!(reparse.declarations[2].body().start(), 19);
+
+        // Inserting a new declaration between two others.
+        let edited = apply_edit(source, 7, "x = 9; ");
+        let reparse = Parser::reparse_after_insert(&edited, &old, 7, 7).expect("reparsing");
+        let reference = Parser::new(&edited)
+            .and_then(|mut p| p.parse_program())
+            .expect("parsing example input");
+        assert_same_declarations(&reparse.declarations, &reference);
+        assert_eq!(reparse.reparsed, vec![1, 2]);
+
+        // Edits at the end of the input.
+        let edited = apply_edit(source, source.len(), " d = 4;");
+        let reparse = Parser::reparse_after_insert(&edited, &old, source.len(), 7).expect("reparsing");
+        assert_eq!(reparse.declarations.len(), 4);
+        assert_eq!(reparse.reparsed, vec![3]);
+
+        let edited = apply_edit(source, 11, "+");
+        assert!(Parser::reparse_after_insert(&edited, &old, 11, 1).is_err());
+    }
+
+    #[test]
+    fn program() {
+        let mut parser = Parser::new("main :: Integer; main = 2;").expect("scanning example input");
+        let program = parser.parse_program().expect("parsing example input");
+        assert_eq!(program.len(), 1);
+        let main = &program[0];
+        assert_eq!(main.name(), "main");
+        assert_eq!(main.ty().map(|ty| &ty.ty), Some(&Type::Con("Integer".into())));
+        assert_eq!((main.start(), main.end()), (0, 26));
+        assert!(matches!(main.body().kind(), NodeKind::Lit { lit: Literal::Int(2) }));
+        assert_eq!((main.body().start(), main.body().end()), (24, 25));
+
+        let mut parser = Parser::new("one = 1; two = one + one;").expect("scanning example input");
+        let program = parser.parse_program().expect("parsing example input");
+        assert_eq!(program.len(), 2);
+        assert_eq!(program[0].ty(), None);
+        assert_eq!(program[1].name(), "two");
+        assert_eq!(program[1].body().to_string(), "one + one");
+        assert_eq!((program[1].body().start(), program[1].body().end()), (15, 24));
+
+        let mut parser = Parser::new("main :: Integer; other = 2;").expect("scanning example input");
+        let e = parser.parse_program().expect_err("should fail");
+        assert!(matches!(e, ParseError::MissingDefinition { offset: 0, ref name } if name == "main"));
+    }
+
+    #[test]
+    fn signatures() {
+        let signature = |input: &str| {
+            let mut parser = Parser::new(input).expect("scanning example input");
+            let program = parser.parse_program().expect("parsing example input");
+            program[0].ty().cloned().expect("signature")
+        };
+        let eq = signature("eq :: Eq a => a -> a -> Bool; eq x y = 1;");
+        assert_eq!(eq.constraints, [("Eq".into(), "a".into())]);
+        let a = || Rc::new(Type::Var("a".into()));
+        let bool = Rc::new(Type::Con("Bool".into()));
+        assert_eq!(eq.ty, Type::Fun(a(), Rc::new(Type::Fun(a(), bool))));
+        assert_eq!(eq.to_string(), "Eq a => a -> a -> Bool");
+
+        let show = signature("show :: (Eq a, Show b) => (a -> b) -> String; show f = f;");
+        assert_eq!(show.constraints, [("Eq".into(), "a".into()), ("Show".into(), "b".into())]);
+        assert_eq!(show.to_string(), "(Eq a, Show b) => (a -> b) -> String");
+        let show = signature("show :: (Show a) => a -> String; show x = x;");
+        assert_eq!(show.to_string(), "Show a => a -> String");
+
+        // Parenthesized types without constraints.
+        let apply = signature("apply :: (Int -> b) -> (Int) -> b; apply f x = f x;");
+        assert!(apply.constraints.is_empty());
+        assert_eq!(apply.to_string(), "(Int -> b) -> Int -> b");
+        assert_eq!(parse_err("f :: Eq a -> a; f x = x;").to_string(), "expected '=>', found '->' instead");
+    }
+
+    #[test]
+    fn attributes() {
+        let mut parser = Parser::new("@[inline, since = \"2\"] main = 1;").expect("scanning example input");
+        let program = parser.parse_program().expect("parsing example input");
+        let attrs = program[0].attributes();
+        assert_eq!(attrs.len(), 1);
+        assert_eq!(
+            attrs[0].entries(),
+            [
+                AttributeEntry::Flag("inline".into()),
+                AttributeEntry::Value("since".into(), AttributeValue::String("2".into())),
+            ]
+        );
+        assert_eq!(attrs[0].span(), Span::new(0, 22));
+        assert!(attrs[0].has_flag("inline"));
+        assert_eq!(attrs[0].get("since"), Some(&AttributeValue::String("2".into())));
+        assert_eq!(attrs[0].get("inline"), None);
+        assert_eq!((program[0].start(), program[0].end()), (0, 32));
+
+        let mut parser = Parser::new("@deprecated @[level = 3]\nf :: T;\nf x = x;").expect("scanning example input");
+        let program = parser.parse_program().expect("parsing example input");
+        let attrs = program[0].attributes();
+        assert_eq!(attrs[0].entries(), [AttributeEntry::Flag("deprecated".into())]);
+        assert_eq!(attrs[0].span(), Span::new(0, 11));
+        assert_eq!(attrs[1].get("level"), Some(&AttributeValue::Int(3)));
+        assert_eq!(program[0].ty().map(|ty| ty.to_string()), Some("T".into()));
+
+        assert_eq!(
+            parse_err("@[since = x] main = 1;").to_string(),
+            "expected number or string as attribute value at offset 10, found identifier instead"
+        );
+        assert_eq!(parse_err("@[a b] main = 1;").to_string(), "expected ']', found identifier instead");
+    }
+
+    #[test]
+    fn lazy_declarations() {
+        let names = declarations("id x = x;\nconst x y = x;\nmain = const (id 1) 2;")
+            .map(|decl| decl.map(|decl| decl.name().to_string()))
+            .collect::<Result<Vec<_>, _>>()
+            .expect("parsing example input");
+        assert_eq!(names, ["id", "const", "main"]);
+        assert_eq!(declarations("").count(), 0);
+
+        let mut decls = declarations("a = 1; b = ; c = 3;");
+        assert_eq!(decls.next().expect("first declaration").expect("parsing example input").name(), "a");
+        assert!(matches!(decls.next(), Some(Err(ParseError::ExpectedExpression { offset: 11, .. }))));
+        assert!(decls.next().is_none());
+
+        let mut decls = declarations("$");
+        assert!(matches!(decls.next(), Some(Err(ParseError::ScanError(_)))));
+        assert!(decls.next().is_none());
+    }
+
+    #[test]
+    fn expression_lists() {
+        let parse = |input, sep| Parser::new(input).expect("scanning example input").parse_all_exprs(sep);
+        let exprs = parse("1; 2; 3", Symbol::Semicolon).expect("parsing example input");
+        assert_eq!(exprs.len(), 3);
+        for (expr, i) in exprs.iter().zip(1..) {
+            assert!(matches!(expr.kind(), NodeKind::Lit { lit: Literal::Int(n) } if *n == i));
+        }
+        assert_eq!(exprs[2].span(), Span::new(6, 7));
+
+        let exprs = parse("f x, let y = 1; z = 2 in y, 3,", Symbol::Comma).expect("parsing example input");
+        assert_eq!(exprs.iter().map(|e| e.to_string()).collect::<Vec<_>>(), ["f x", "{ let y = 1; z = 2 in y }", "3"]);
+        assert!(parse("", Symbol::Comma).expect("parsing example input").is_empty());
+        assert_eq!(
+            parse("1; 2, 3", Symbol::Semicolon).map(|_| ()).unwrap_err().to_string(),
+            "expected ';', found ',' instead"
+        );
+    }
+
+    #[test]
+    fn pipe_forward() {
+        let expr = parse("1 |> inc |> double");
+        assert!(alpha_eq(&expr, &parse("double (inc 1)")));
+        assert_eq!(expr.to_string(), "double (inc 1)");
+        assert_eq!(expr.span(), Span::new(0, 18));
+        // Lowest precedence, so operands are full arithmetic expressions.
+        assert_eq!(parse("x + 1 |> f 2").to_string(), "f 2 (x + 1)");
+        assert_eq!(parse("x |> (\\y. y)").to_string(), "(\\ y. y) x");
+        let kinds = Scanner::new("a | b |> c || d")
+            .expect("scanning example input")
+            .map(|t| t.expect("scanning example input").kind())
+            .filter(|kind| *kind != TokenKind::Identifier)
+            .collect::<Vec<_>>();
+        let [bar, pipe] = [Symbol::Bar, Symbol::PipeRight].map(TokenKind::Symbol);
+        assert_eq!(kinds, [bar, pipe, bar, bar, TokenKind::Eof]);
+    }
+
+    #[test]
+    fn unary_minus() {
+        let parse = |input| {
+            let mut parser = Parser::new(input).expect("scanning example input");
+            parser.parse_script().expect("parsing example input")
+        };
+        let expr = parse("-1");
+        assert!(matches!(expr.kind(), NodeKind::Lit { lit: Literal::Int(-1) }));
+        assert_eq!(expr.span(), Span::new(0, 2));
+        assert_eq!(parse("2 * -x").to_string(), "2 * (0 - x)");
+        assert_eq!(parse("-f x * 2").to_string(), "(0 - f x) * 2");
+        assert_eq!(parse("x - - -1").to_string(), "x - 1");
+        assert_eq!(parse("(\\x. -x) 1").to_string(), "(\\ x. 0 - x) 1");
+        // After an operand, `-` subtracts.
+        assert_eq!(parse("f -1").to_string(), "f - 1");
+        assert_eq!(parse("(f) -1").to_string(), "f - 1");
+        // Negative literals are printed so that they can be parsed again.
+        assert_eq!(parse("f (-1) + -2").to_string(), "f (-1) + -2");
+    }
+
+    #[test]
+    fn guarded_definitions() {
+        let mut parser = Parser::new("sign x | x = x / x | otherwise = 0;").expect("scanning example input");
+        let prog = parser.parse_program().expect("parsing example input");
+        let guards = prog[0].guards();
+        assert_eq!(guards.len(), 2);
+        assert_eq!(guards[0].0.to_string(), "x");
+        assert_eq!(guards[0].1.to_string(), "x / x");
+        assert_eq!(prog[0].body().to_string(), "if x then x / x else 0 end");
+        assert_eq!(prog[0].to_string(), "sign x | x = x / x | otherwise = 0;");
+
+        // Without otherwise, no guard may be true.
+        let mut parser = Parser::new("f x | x > 0 = 1;").expect("scanning example input");
+        let prog = parser.parse_program().expect("parsing example input");
+        assert_eq!(prog[0].body().to_string(), "if | x > 0 => 1 end");
+
+        // Equations after otherwise are kept, and reported as unreachable.
+        let source = "f x | x = 1 | otherwise = 2 | x > 1 = 3;";
+        let mut parser = Parser::new(source).expect("scanning example input");
+        let prog = parser.parse_program().expect("parsing example input");
+        assert_eq!(prog[0].guards().len(), 3);
+        assert_eq!(prog[0].unreachable_guards().len(), 1);
+        assert_eq!(prog[0].body().to_string(), "if x then 1 else 2 end");
+        assert_eq!(prog[0].to_string(), source);
+        let mut parser = Parser::new(source).expect("scanning example input");
+        assert_eq!(
+            parser.parse_program_recovering(),
+            [Diagnostic::warning(Span::new(30, 39), "equation of f after otherwise is unreachable")]
+        );
+    }
+
+    #[test]
+    fn lists() {
+        let parse = |input| Parser::new(input).expect("scanning example input").parse_script();
+        let list = parse("[1, f x, []]").expect("parsing example input");
+        assert!(matches!(list.kind(), NodeKind::List { elems } if elems.len() == 3));
+        assert_eq!(list.span(), Span::new(0, 12));
+        assert_eq!(list.to_string(), "[1, f x, []]");
+
+        let comp = parse("[x + y | x <- xs, y <- [x], x, y]").expect("parsing example input");
+        let NodeKind::Comprehension { body, generators, guards } = comp.kind() else {
+            panic!("expected comprehension, got {comp:?}");
+        };
+        assert_eq!(body.to_string(), "x + y");
+        assert_eq!(generators.len(), 2);
+        assert_eq!(guards.len(), 2);
+        assert_eq!(comp.to_string(), "[x + y | x <- xs, y <- [x], x, y]");
+        assert_eq!(
+            parse("[x | x, y <- ys]").map(|_| ()).unwrap_err().to_string(),
+            "expected ']', found '<-' instead"
+        );
+    }
+
+    #[test]
+    fn annotations() {
+        let postfix = parse("x @Integer");
+        let NodeKind::Ann { expr, ty } = postfix.kind() else {
+            panic!("expected annotation, got {postfix:?}");
+        };
+        assert_eq!(expr.to_string(), "x");
+        assert_eq!(ty.ty, Type::Con("Integer".into()));
+        assert_eq!(postfix.span(), Span::new(0, 10));
+        let parenthesized = parse("(x : Integer)");
+        assert!(alpha_eq(&postfix, &parenthesized));
+        assert_eq!(postfix.to_string(), "(x : Integer)");
+        assert_eq!(parenthesized.to_string(), "(x : Integer)");
+        assert!(!alpha_eq(&postfix, &parse("x @String")));
+
+        assert_eq!(parse("f x @Integer y").to_string(), "f (x : Integer) y");
+        assert_eq!(parse("(f x : Integer)").to_string(), "(f x : Integer)");
+        assert_eq!(parse("x @A @B").to_string(), "((x : A) : B)");
+
+        // Structured types.
+        let arrow = parse("(f : Int -> Int)");
+        let NodeKind::Ann { ty, .. } = arrow.kind() else {
+            panic!("expected annotation, got {arrow:?}");
+        };
+        assert_eq!(ty.ty, Type::Fun(Rc::new(Type::Int), Rc::new(Type::Int)));
+        assert_eq!(arrow.to_string(), "(f : Int -> Int)");
+        let postfix = parse("f @(Int -> Int) 1");
+        assert_eq!(postfix.to_string(), "(f : Int -> Int) 1");
+        assert_eq!(postfix.app_spine().0.span(), Span::new(0, 15));
+        assert!(alpha_eq(&arrow, postfix.app_spine().0));
+        assert_eq!(parse("(xs : Eq a => a)").to_string(), "(xs : Eq a => a)");
+    }
+
+    #[test]
+    fn parameters() {
+        let mut parser = Parser::new("const x y = x;").expect("scanning example input");
+        let program = parser.parse_program().expect("parsing example input");
+        let params = program[0].params().iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        assert_eq!(params, ["x", "y"]);
+        assert_eq!(program[0].body().to_string(), "x");
+        let desugared = program[0].desugar();
+        let NodeKind::Abs { body, .. } = desugared.kind() else {
+            panic!("expected abstraction, got {desugared:?}");
+        };
+        let NodeKind::Abs { body, .. } = body.kind() else {
+            panic!("expected abstraction, got {body:?}");
+        };
+        assert!(matches!(body.kind(), NodeKind::Name { .. }));
+        assert!(alpha_eq(&desugared, &parse("\\a. \\b. a")));
+        assert_eq!((desugared.start(), desugared.end()), (6, 13));
+
+        let mut parser = Parser::new("one = 1;").expect("scanning example input");
+        let program = parser.parse_program().expect("parsing example input");
+        assert!(Rc::ptr_eq(&program[0].desugar(), program[0].body()));
+    }
+
+    fn parse(input: &str) -> Rc<Node<'_, ()>> {
+        let mut parser = Parser::new(input).expect("scanning example input");
+        parser.parse_script().expect("parsing example input")
+    }
+
+    #[test]
+    fn application() {
+        let expr = parse("x y z");
+        assert_eq!((expr.start(), expr.end()), (0, 5));
+        let NodeKind::App { fun, arg } = expr.kind() else {
+            panic!("expected application, got {expr:?}");
+        };
+        assert!(matches!(arg.kind(), NodeKind::Name { name, .. } if name == "z"));
+        assert_eq!((arg.start(), arg.end()), (4, 5));
+        assert_eq!((fun.start(), fun.end()), (0, 3));
+        let NodeKind::App { fun: x, arg: y } = fun.kind() else {
+            panic!("expected application, got {fun:?}");
+        };
+        assert!(matches!(x.kind(), NodeKind::Name { name, .. } if name == "x"));
+        assert!(matches!(y.kind(), NodeKind::Name { name, .. } if name == "y"));
+
+        let expr = parse("f (g x) 1");
+        assert_eq!(expr.to_string(), "f (g x) 1");
+        let NodeKind::App { fun, .. } = expr.kind() else {
+            panic!("expected application, got {expr:?}");
+        };
+        assert_eq!((fun.start(), fun.end()), (0, 7));
+
+        // Application binds tighter than binary operators.
+        assert_eq!(parse("f x + g y * 2").to_string(), "f x + g y * 2");
+        assert_eq!(parse("(1 + 2) * (3)").to_string(), "(1 + 2) * 3");
+        let expr = parse("(1 + 2)");
+        assert_eq!((expr.start(), expr.end()), (0, 7));
+
+        let mut parser = Parser::new("f (x").expect("scanning example input");
+        assert!(matches!(
+            parser.parse_script(),
+            Err(ParseError::Unexpected { expected: TokenKind::Symbol(Symbol::RParen), found: TokenKind::Eof, .. })
+        ));
+    }
+
+    #[test]
+    fn abstraction() {
+        let expr = parse("\\ x . f x");
+        assert_eq!((expr.start(), expr.end()), (0, 9));
+        let NodeKind::Abs { param, body } = expr.kind() else {
+            panic!("expected abstraction, got {expr:?}");
+        };
+        assert!(matches!(param.kind(), NodeKind::Name { name, .. } if name == "x"));
+        assert_eq!((param.start(), param.end()), (2, 3));
+        assert!(matches!(body.kind(), NodeKind::App { .. }));
+        assert_eq!(expr.to_string(), "\\ x. f x");
+
+        let expr = parse("fun x y. x");
+        assert_eq!((expr.start(), expr.end()), (0, 10));
+        let NodeKind::Abs { param, body } = expr.kind() else {
+            panic!("expected abstraction, got {expr:?}");
+        };
+        assert!(matches!(param.kind(), NodeKind::Name { name, .. } if name == "x"));
+        assert_eq!((body.start(), body.end()), (6, 10));
+        let NodeKind::Abs { param, body } = body.kind() else {
+            panic!("expected abstraction, got {body:?}");
+        };
+        assert!(matches!(param.kind(), NodeKind::Name { name, .. } if name == "y"));
+        assert!(matches!(body.kind(), NodeKind::Name { name, .. } if name == "x"));
+
+        assert_eq!(parse("(\\x.x) (\\y. \\z. y z)").to_string(), "(\\ x. x) (\\ y. \\ z. y z)");
+        assert_eq!(parse("\\x. x + 1").to_string(), "\\ x. x + 1");
+
+        let mut parser = Parser::new("\\. x").expect("scanning example input");
+        assert!(matches!(
+            parser.parse_script(),
+            Err(ParseError::Unexpected { expected: TokenKind::Identifier, .. })
+        ));
+    }
+
+    #[test]
+    fn conditional() {
+        let expr = parse("if c then 1 else f x end");
+        assert_eq!((expr.start(), expr.end()), (0, 24));
+        let NodeKind::If { cond, conseq, alt } = expr.kind() else {
+            panic!("expected conditional, got {expr:?}");
+        };
+        assert!(matches!(cond.kind(), NodeKind::Name { name, .. } if name == "c"));
+        assert!(matches!(conseq.kind(), NodeKind::Lit { lit: Literal::Int(1) }));
+        assert!(matches!(alt.kind(), NodeKind::App { .. }));
+        assert_eq!(expr.to_string(), "if c then 1 else f x end");
+
+        // Nested conditionals, and conditionals as operands.
+        let expr = parse("if a then if b then 1 else 2 end else \\x. x end + 1");
+        assert_eq!(
+            expr.to_string(),
+            "if a then if b then 1 else 2 end else \\ x. x end + 1"
+        );
+        assert_eq!(parse("f if a then b else c end").to_string(), "f if a then b else c end");
+
+        let mut parser = Parser::new("if a then b else c").expect("scanning example input");
+        let e = parser.parse_script().expect_err("should fail");
+        assert!(matches!(
+            e,
+            ParseError::Unexpected { expected: TokenKind::Keyword(Keyword::End), found: TokenKind::Eof, .. }
+        ));
+    }
+
+    #[test]
+    fn multi_way_conditional() {
+        let expr = parse("if | a => 1 | f b => \\x. x | otherwise => 3 end");
+        assert_eq!((expr.start(), expr.end()), (0, 47));
+        let NodeKind::MultiIf { arms, otherwise: Some(otherwise) } = expr.kind() else {
+            panic!("expected multi-way conditional, got {expr:?}");
+        };
+        assert_eq!(arms.len(), 2);
+        assert!(matches!(arms[0].0.kind(), NodeKind::Name { name, .. } if name == "a"));
+        assert!(matches!(arms[1].1.kind(), NodeKind::Abs { .. }));
+        assert!(matches!(otherwise.kind(), NodeKind::Lit { lit: Literal::Int(3) }));
+        assert_eq!(expr.to_string(), "if | a => 1 | f b => \\ x. x | otherwise => 3 end");
+        // `otherwise` is only special before `=>`.
+        assert_eq!(parse("if | otherwise x => 1 end + 1").to_string(), "if | otherwise x => 1 end + 1");
+
+        let mut parser = Parser::new("if | otherwise => 1 | a => 2 end").expect("scanning example input");
+        assert!(matches!(
+            parser.parse_script(),
+            Err(ParseError::Unexpected { expected: TokenKind::Keyword(Keyword::End), offset: 20, .. })
+        ));
+    }
+
+    #[test]
+    fn owned_tree() {
+        let source = String::from("(\\name. name + 1) (x : Int)");
+        let mut parser = Parser::with_owned_names(&source).expect("scanning example input");
+        let expr = parser.parse_script().expect("parsing example input");
+        drop(parser);
+        let NodeKind::App { fun, .. } = expr.kind() else {
+            panic!("expected application, got {expr:?}");
+        };
+        let NodeKind::Abs { param, .. } = fun.kind() else {
+            panic!("expected abstraction, got {fun:?}");
+        };
+        let NodeKind::Name { name: Cow::Owned(name), .. } = param.kind() else {
+            panic!("expected owned name, got {param:?}");
+        };
+        let buffer = name.as_ptr();
+        let expr = into_owned(expr);
+        drop(source);
+        assert_eq!(expr.to_string(), "(\\ name. name + 1) (x : Int)");
+        assert_eq!((expr.start(), expr.end()), (0, 27));
+        // The names were moved, not copied.
+        let NodeKind::App { fun, .. } = expr.kind() else {
+            panic!("expected application, got {expr:?}");
+        };
+        let NodeKind::Abs { param, .. } = fun.kind() else {
+            panic!("expected abstraction, got {fun:?}");
+        };
+        assert!(matches!(param.kind(), NodeKind::Name { name, .. } if name.as_ptr() == buffer));
+
+        let source = String::from("f x");
+        let token = {
+            let scanner = crate::scanner::Scanner::new(&source).expect("scanning example input");
+            scanner.token().text_owned()
+        };
+        drop(source);
+        assert_eq!(token, "f");
+    }
+
+    #[test]
+    fn block() {
+        let expr = parse("{ let x = 1; f x; x + 1 }");
+        assert_eq!((expr.start(), expr.end()), (0, 25));
+        let NodeKind::Let { bindings, body } = expr.kind() else {
+            panic!("expected let, got {expr:?}");
+        };
+        let [(name, value)] = &bindings[..] else {
+            panic!("expected one binding, got {bindings:?}");
+        };
+        assert!(matches!(name.kind(), NodeKind::Name { name, .. } if name == "x"));
+        assert!(matches!(value.kind(), NodeKind::Lit { lit: Literal::Int(1) }));
+        let NodeKind::Seq { first, second } = body.kind() else {
+            panic!("expected sequence, got {body:?}");
+        };
+        assert_eq!(first.to_string(), "f x");
+        assert_eq!(second.to_string(), "x + 1");
+        assert_eq!((body.start(), body.end()), (13, 23));
+        assert_eq!(expr.to_string(), "{ let x = 1; f x; x + 1 }");
+
+        assert_eq!(parse("{ 1 }").to_string(), "1");
+        assert_eq!(parse("f { a; b } c").to_string(), "f { a; b } c");
+
+        let mut parser = Parser::new("{ let x = 1; }").expect("scanning example input");
+        assert!(matches!(
+            parser.parse_script(),
+            Err(ParseError::ExpectedExpression { found: TokenKind::Symbol(Symbol::RBrace), .. })
+        ));
+    }
+
+    #[test]
+    fn let_in() {
+        let expr = parse("let x = 1; y = 2 in x + y");
+        assert_eq!((expr.start(), expr.end()), (0, 25));
+        let NodeKind::Let { bindings, body } = expr.kind() else {
+            panic!("expected let, got {expr:?}");
+        };
+        let names = bindings.iter().map(|(n, _)| n.to_string()).collect::<Vec<_>>();
+        assert_eq!(names, ["x", "y"]);
+        assert_eq!(body.to_string(), "x + y");
+        assert_eq!(expr.to_string(), "{ let x = 1; y = 2 in x + y }");
+        assert_tree_eq!(parse(&expr.to_string()), expr);
+
+        // The body extends as far as possible, and `let` may start a block.
+        assert_eq!(parse("\\f. let x = f in x x").to_string(), "\\ f. { let x = f; x x }");
+        assert_eq!(parse("{ let x = 1 in x; x }").to_string(), "{ { let x = 1; x }; x }");
+        assert_eq!(parse("{ let x = 1; y = 2 in x; y }").to_string(), "{ { let x = 1; y = 2 in x }; y }");
+        // Bindings in a block are sequential, with one `let` each.
+        let expr = parse("{ let x = 1; y = x; y }");
+        assert_eq!(expr.to_string(), "{ let x = 1; let y = x; y }");
+        assert_tree_eq!(expr, parse("{ let x = 1; let y = x; y }"));
+        let NodeKind::Let { bindings, body } = expr.kind() else {
+            panic!("expected let, got {expr:?}");
+        };
+        assert_eq!(bindings.len(), 1);
+        assert_eq!((expr.span(), body.span()), (Span::new(0, 23), Span::new(13, 21)));
+
+        let mut parser = Parser::new("let x = 1; x").expect("scanning example input");
+        assert!(matches!(
+            parser.parse_script(),
+            Err(ParseError::Unexpected { expected: TokenKind::Keyword(Keyword::In), found: TokenKind::Identifier, offset: 11 })
+        ));
+        assert_eq!(parse_err("main = let x = 1 = 2;").to_string(), "expected 'in', found '=' instead");
+        assert_eq!(parse_err("main = let x = 1").to_string(), "expected 'in', found end of input instead");
+
+        // The body is a full expression.
+        let expr = parse("let id = \\x. x in \\y. id y + 1 * (let z = y in z)");
+        assert_eq!(expr.to_string(), "{ let id = \\ x. x; \\ y. id y + 1 * { let z = y; z } }");
+    }
+
+    #[test]
+    fn precedence_table() {
+        let table = PrecedenceTable::default();
+        let entries = table.entries();
+        let find = |sym| entries.iter().find(|(s, _, _)| *s == sym).expect("operator in table");
+        let (_, star, star_assoc) = find(Symbol::Star);
+        let (_, plus, plus_assoc) = find(Symbol::Plus);
+        assert!(star > plus);
+        assert_eq!((*star_assoc, *plus_assoc), (Assoc::Left, Assoc::Left));
+        assert_eq!(entries.first().map(|e| e.0), Some(Symbol::PipeRight));
+        assert_eq!(
+            table.to_string(),
+            "operator  precedence  associativity\n\
+             |>        0           left\n\
+             ==        1           left\n\
+             <         1           left\n\
+             <=        1           left\n\
+             >         1           left\n\
+             >=        1           left\n\
+             +         2           left\n\
+             -         2           left\n\
+             *         3           left\n\
+             /         3           left\n"
+        );
+    }
+
+    #[test]
+    fn keyword_in_expression() {
+        let mut parser = Parser::new("1 + end").expect("scanning example input");
+        let e = parser.parse_script().expect_err("should fail");
+        assert!(matches!(
+            e,
+            ParseError::UnexpectedKeywordInExpression { keyword: Keyword::End, span: Span { start: 4, end: 7 } }
+        ));
+        assert_eq!(e.to_string(), "unexpected `end` in expression at 4..7");
+
+        let mut parser = Parser::new("\\x. else").expect("scanning example input");
+        assert!(matches!(
+            parser.parse_script(),
+            Err(ParseError::UnexpectedKeywordInExpression { keyword: Keyword::Else, span: Span { start: 4, end: 8 } })
+        ));
+
+        let mut parser = Parser::new("f (else)").expect("scanning example input");
+        assert!(matches!(
+            parser.parse_script(),
+            Err(ParseError::UnexpectedKeywordInExpression { keyword: Keyword::Else, span: Span { start: 3, end: 7 } })
+        ));
+    }
+
+    #[test]
+    fn borrow() {
+        let mut parser = Parser::new("&x").expect("scanning example input");
+        let expr = parser.parse_script().expect("parsing example input");
+        assert_eq!((expr.start(), expr.end()), (0, 2));
+        let NodeKind::Borrow { expr: inner } = expr.kind() else {
+            panic!("expected borrow, got {expr:?}");
+        };
+        assert!(matches!(inner.kind(), NodeKind::Name { name, .. } if name == "x"));
+        assert_eq!((inner.start(), inner.end()), (1, 2));
+
+        let mut parser = Parser::new("&&x").expect("scanning example input");
+        assert!(parser.parse_script().is_err());
+    }
+
+    #[test]
+    fn script() {
+        let mut parser = Parser::new("1 + 2 * 3").expect("scanning example input");
+        let expr = parser.parse_script().expect("parsing example input");
+        assert_eq!((expr.start(), expr.end()), (0, 9));
+        let NodeKind::BinOp { op: BinOp::Add, op_span, lhs, rhs } = expr.kind() else {
+            panic!("expected addition, got {expr:?}");
+        };
+        assert_eq!(*op_span, Span::new(2, 3));
+        assert!(matches!(lhs.kind(), NodeKind::Lit { lit: Literal::Int(1) }));
+        let NodeKind::BinOp { op: BinOp::Mul, op_span, lhs, rhs } = rhs.kind() else {
+            panic!("expected multiplication, got {rhs:?}");
+        };
+        assert_eq!(*op_span, Span::new(6, 7));
+        assert!(matches!(lhs.kind(), NodeKind::Lit { lit: Literal::Int(2) }));
+        assert!(matches!(rhs.kind(), NodeKind::Lit { lit: Literal::Int(3) }));
+
+        // Operators of equal precedence group to the left.
+        let mut parser = Parser::new("8 - 4 - 2").expect("scanning example input");
+        let expr = parser.parse_script().expect("parsing example input");
+        let NodeKind::BinOp { op: BinOp::Sub, lhs, .. } = expr.kind() else {
+            panic!("expected subtraction, got {expr:?}");
+        };
+        assert!(matches!(lhs.kind(), NodeKind::BinOp { op: BinOp::Sub, .. }));
+        assert_eq!((lhs.start(), lhs.end()), (0, 5));
+
+        // The operator span covers just the operator, also in parentheses
+        // and after shifting.
+        let mut parser = Parser::new("(a  +  b)").expect("scanning example input");
+        let expr = parser.parse_script().expect("parsing example input");
+        assert!(matches!(expr.kind(), NodeKind::BinOp { op_span, .. } if *op_span == Span::new(4, 5)));
+        let shifted = expr.shifted(10);
+        assert!(matches!(shifted.kind(), NodeKind::BinOp { op_span, .. } if *op_span == Span::new(14, 15)));
+
+        let mut parser = Parser::new("1 +").expect("scanning example input");
+        let e = parser.parse_script().expect_err("should fail");
+        assert!(matches!(e, ParseError::ExpectedExpression { offset: 3, found: TokenKind::Eof }));
+        assert_eq!(e.to_string(), "expected expression at offset 3, found end of input instead");
+    }
+
+    fn parse_lines(input: &str) -> Result<Vec<String>, ParseError> {
+        let options = ScanOptions {
+            newline_separators: true,
+            ..ScanOptions::default()
+        };
+        let mut parser = Parser::with_scan_options(input, options)?;
+        let exprs = parser.parse_lines()?;
+        Ok(exprs.iter().map(|e| e.to_string()).collect())
+    }
+
+    #[test]
+    fn lines() {
+        assert_eq!(parse_lines("1 + 1\n2 * 2").expect("parsing example input"), ["1 + 1", "2 * 2"]);
+        assert_eq!(parse_lines("1 + 1\n\n2 * 2\n").expect("parsing example input"), ["1 + 1", "2 * 2"]);
+        // Lines continued after an operator, with a backslash, or inside
+        // parentheses.
+        assert_eq!(parse_lines("1 +\n  2").expect("parsing example input"), ["1 + 2"]);
+        assert_eq!(parse_lines("f x \\\n  y").expect("parsing example input"), ["f x y"]);
+        assert_eq!(parse_lines("f (x\n  y)\nz").expect("parsing example input"), ["f (x y)", "z"]);
+        assert_eq!(parse_lines("let x = 1 in\nx").expect("parsing example input"), ["{ let x = 1; x }"]);
+        assert!(parse_lines("").expect("parsing example input").is_empty());
+
+        let e = parse_lines("1 2 )\n").expect_err("should fail");
+        assert_eq!(e.to_string(), "expected newline, found ')' instead");
+    }
+
+    #[test]
+    fn nesting_depth() {
+        let input = format!("{}x{}", "(".repeat(100_000), ")".repeat(100_000));
+        let mut parser = Parser::new(&input).expect("scanning example input");
+        let e = parser.parse_script().expect_err("should fail");
+        assert!(matches!(e, ParseError::NestingTooDeep { depth: 256, offset: 256 }));
+
+        let input = format!("{}x", "& ".repeat(100_000));
+        let mut parser = Parser::new(&input).expect("scanning example input");
+        let e = parser.parse_script().expect_err("should fail");
+        assert!(matches!(e, ParseError::NestingTooDeep { depth: 256, .. }));
+
+        let mut parser = Parser::with_max_depth("\\x. \\y. (x y)", 4).expect("scanning example input");
+        assert!(parser.parse_script().is_ok());
+        let mut parser = Parser::with_max_depth("\\x. \\y. ((x y))", 4).expect("scanning example input");
+        let e = parser.parse_script().expect_err("should fail");
+        assert!(matches!(e, ParseError::NestingTooDeep { depth: 4, offset: 10 }));
+        assert_eq!(e.to_string(), "expression at offset 10 is nested more than 4 levels deep");
+
+        // The depth is reset after an error, so that recovery can continue.
+        let input = format!("a = {}1; b = 2; c = (3);", "(".repeat(1000));
+        let mut parser = Parser::new(&input).expect("scanning example input");
+        assert_eq!(parser.parse_program_recovering().len(), 1);
     }
 }
@@ -0,0 +1,231 @@
+use crate::{
+    ast::{Node, Program},
+    error::Error,
+    eval::Value,
+    json::{self, Json},
+    scanner::Scanner,
+    token::{HighlightKind, Token},
+};
+
+/// Produces the output of the command-line tool, so that the same commands
+/// can print plain text for people or JSON for other tools.
+pub trait Renderer {
+    /// Render the tokens of the input, including the final `Eof` token.
+    fn render_tokens(&self, tokens: &[Token<'_>]) -> String;
+
+    /// Render a parsed program.
+    fn render_ast(&self, prog: &Program<'_>) -> String;
+
+    /// Render the value of an evaluated expression.
+    fn render_result(&self, value: &Value<'_, ()>) -> String;
+
+    /// Render an error. `source` is the input the error refers to.
+    fn render_error(&self, error: &Error, source: &str) -> String;
+}
+
+/// Renders everything as plain text: tokens one per line as `kind
+/// start..end raw`, programs and values as source, and errors with the
+/// offending line of the input.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PlainRenderer;
+
+impl Renderer for PlainRenderer {
+    fn render_tokens(&self, tokens: &[Token<'_>]) -> String {
+        tokens
+            .iter()
+            .map(|t| format!("{:?} {} {:?}\n", t.kind(), t.span(), t.raw_text()))
+            .collect()
+    }
+
+    fn render_ast(&self, prog: &Program<'_>) -> String {
+        prog.iter().map(|decl| format!("{decl}\n")).collect()
+    }
+
+    fn render_result(&self, value: &Value<'_, ()>) -> String {
+        format!("{value}\n")
+    }
+
+    fn render_error(&self, error: &Error, source: &str) -> String {
+        format!("{}\n", error.render(source))
+    }
+}
+
+/// Print a node like `Display`, with names, literals, keywords and
+/// operators highlighted by ANSI color codes, unless the `NO_COLOR`
+/// environment variable is set to a non-empty value.
+pub fn show_colored<Anno>(node: &Node<'_, Anno>) -> String {
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    show_colored_with(node, !no_color)
+}
+
+/// Print a node like `show_colored`, with colors only if `color` is true.
+/// Without colors, the output is the same as that of `Display`.
+pub fn show_colored_with<Anno>(node: &Node<'_, Anno>, color: bool) -> String {
+    let plain = node.to_string();
+    if !color {
+        return plain;
+    }
+    // The printed term can be parsed again, so highlighting its tokens
+    // follows the syntax exactly.
+    let Ok(scanner) = Scanner::new(&plain) else {
+        return plain;
+    };
+    let mut out = String::new();
+    let mut end = 0;
+    for token in scanner {
+        let Ok(token) = token else {
+            return plain;
+        };
+        out.push_str(&plain[end..token.start()]);
+        match token.kind().highlight().and_then(ansi_color) {
+            Some(code) => out.push_str(&format!("\x1b[{code}m{}\x1b[0m", token.raw_text())),
+            None => out.push_str(token.raw_text()),
+        }
+        end = token.end();
+    }
+    out.push_str(&plain[end..]);
+    out
+}
+
+/// The ANSI select graphic rendition parameters for a kind of token, or
+/// `None` to leave it uncolored.
+fn ansi_color(kind: HighlightKind) -> Option<&'static str> {
+    match kind {
+        HighlightKind::Name => Some("36"),
+        HighlightKind::Literal => Some("32"),
+        HighlightKind::Keyword => Some("1;35"),
+        HighlightKind::Operator => Some("33"),
+        HighlightKind::Comment => Some("2"),
+        HighlightKind::Punctuation => None,
+    }
+}
+
+/// Renders everything as JSON, in the formats of the `json` module. Tokens
+/// are objects with their `kind`, `span` and `text`, and results are
+/// objects with the member `result`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JsonRenderer;
+
+impl Renderer for JsonRenderer {
+    fn render_tokens(&self, tokens: &[Token<'_>]) -> String {
+        let tokens = tokens.iter().map(json::token_to_json).collect();
+        format!("{}\n", Json::Array(tokens))
+    }
+
+    fn render_ast(&self, prog: &Program<'_>) -> String {
+        format!("{}\n", json::program_to_json(prog))
+    }
+
+    fn render_result(&self, value: &Value<'_, ()>) -> String {
+        format!("{}\n", json::value_to_json(value))
+    }
+
+    fn render_error(&self, error: &Error, source: &str) -> String {
+        let offset = match error {
+            Error::Scan(e) => Some(e.offset()),
+            Error::Parse(e) => Some(e.offset()),
+            _ => None,
+        };
+        format!("{}\n", json::message_to_json(&error.to_string(), offset, source))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn json_output() {
+        let source = r#"@[inline] id x = "a\"b";"#;
+        let prog = Parser::new(source)
+            .and_then(|mut parser| parser.parse_program())
+            .expect("parsing example input");
+        assert_eq!(PlainRenderer.render_ast(&prog), "@[inline]\nid x = \"a\\\"b\";\n");
+        assert_eq!(
+            JsonRenderer.render_ast(&prog),
+            r#"{
+  "declarations": [
+    {
+      "attributes": [
+        {
+          "entries": [
+            {
+              "name": "inline"
+            }
+          ],
+          "span": [
+            0,
+            9
+          ]
+        }
+      ],
+      "name": "id",
+      "type": null,
+      "params": [
+        {
+          "kind": "Name",
+          "span": [
+            13,
+            14
+          ],
+          "name": "x"
+        }
+      ],
+      "guards": [],
+      "body": {
+        "kind": "Lit",
+        "span": [
+          17,
+          23
+        ],
+        "value": "a\"b"
+      },
+      "span": [
+        0,
+        24
+      ]
+    }
+  ]
+}
+"#
+        );
+
+        let e = Parser::new("main = (;")
+            .and_then(|mut parser| parser.parse_program())
+            .expect_err("should fail");
+        assert_eq!(
+            JsonRenderer.render_error(&e.into(), "main = (;"),
+            "{\n  \"error\": {\n    \"message\": \"expected expression at offset 8, found ';' instead\",\n    \
+             \"offset\": 8,\n    \"line\": 1,\n    \"column\": 9\n  }\n}\n"
+        );
+        assert_eq!(
+            JsonRenderer.render_result(&Value::String("a\"b".into())),
+            "{\n  \"result\": \"a\\\"b\"\n}\n"
+        );
+        assert_eq!(
+            JsonRenderer.render_error(&Error::Other("oops".into()), ""),
+            "{\n  \"error\": {\n    \"message\": \"oops\"\n  }\n}\n"
+        );
+    }
+
+    #[test]
+    fn colored_output() {
+        let expr = Parser::new("if x then f 1 else \"s\" end")
+            .and_then(|mut parser| parser.parse_script())
+            .expect("parsing example input");
+        let colored = show_colored_with(&expr, true);
+        assert!(colored.starts_with("\x1b[1;35mif\x1b[0m \x1b[36mx\x1b[0m "), "{colored:?}");
+        assert!(colored.contains("\x1b[32m1\x1b[0m"), "{colored:?}");
+        assert!(colored.contains("\x1b[32m\"s\"\x1b[0m"), "{colored:?}");
+        assert_eq!(show_colored_with(&expr, false), expr.to_string());
+
+        let expr = Parser::new("(\\x. x + 1) 2")
+            .and_then(|mut parser| parser.parse_script())
+            .expect("parsing example input");
+        assert_eq!(
+            show_colored_with(&expr, true),
+            "(\x1b[33m\\\x1b[0m \x1b[36mx\x1b[0m. \x1b[36mx\x1b[0m \x1b[33m+\x1b[0m \x1b[32m1\x1b[0m) \x1b[32m2\x1b[0m"
+        );
+    }
+}
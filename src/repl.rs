@@ -0,0 +1,62 @@
+use std::io::{BufRead, IsTerminal, Write};
+
+use crate::{
+    error::Error,
+    parser::Parser,
+    render::show_colored,
+};
+
+/// Read expressions from standard input, one per line, and print the
+/// parsed expression or the parse error for each, until end of input.
+/// Expressions are highlighted when standard output is a terminal.
+pub fn run_repl() -> Result<(), Error> {
+    let stdin = std::io::stdin();
+    let color = std::io::stdout().is_terminal();
+    repl(stdin.lock(), &mut std::io::stdout(), color)
+}
+
+/// Implementation of `run_repl`, reading from `input` and writing prompts
+/// and results to `output`, with colors if `color` is true.
+fn repl<R: BufRead, W: Write>(mut input: R, output: &mut W, color: bool) -> Result<(), Error> {
+    // The parser borrows its input, so each line is parsed while its buffer
+    // is still alive and before the next line is read into it.
+    let mut line = String::new();
+    loop {
+        write!(output, "> ")?;
+        output.flush()?;
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            writeln!(output)?;
+            return Ok(());
+        }
+        let source = line.trim_end_matches(['\n', '\r']);
+        if source.trim().is_empty() {
+            continue;
+        }
+        match Parser::new(source).and_then(|mut parser| parser.parse_script()) {
+            Ok(expr) if color => writeln!(output, "{}", show_colored(&expr))?,
+            Ok(expr) => writeln!(output, "{expr}")?,
+            Err(e) => writeln!(output, "{}", Error::from(e).render(source))?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn session() {
+        let input = "(\\x. x) y\n\n   \n1 + \n";
+        let mut output = Vec::new();
+        repl(input.as_bytes(), &mut output, false).expect("running repl");
+        let output = String::from_utf8(output).expect("utf-8 output");
+        assert_eq!(
+            output,
+            "> (\\ x. x) y\n\
+             > > > expected expression at offset 4, found end of input instead\n\
+             1 + \n    ^\n\
+             > \n"
+        );
+    }
+}
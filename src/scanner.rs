@@ -1,6 +1,9 @@
-use std::{borrow::Cow, str::CharIndices};
+use std::{borrow::Cow, iter::FusedIterator, str::CharIndices};
 
-use crate::token::{Keyword, Symbol, Token, TokenKind};
+use crate::{
+    span::{render_snippet, Span},
+    token::{Keyword, StringPart, Symbol, Token, TokenKind},
+};
 
 #[derive(Debug)]
 #[allow(dead_code)]
@@ -10,6 +13,18 @@ pub enum ScanError {
     UnexpectedCharacterInEscapeSequence { offset: usize, unexpected: char },
     UnexpectedEndOfInputInString { offset: usize, string_start: usize },
     UnexpectedEndOfInputInEscapeSequence { offset: usize },
+    UnknownNumericSuffix { offset: usize, suffix: String },
+    UnexpectedEndOfInputInCharLiteral { offset: usize, char_start: usize },
+    UnexpectedEndOfLineInCharLiteral { offset: usize, char_start: usize },
+    EmptyCharLiteral { offset: usize },
+    TooManyCharactersInCharLiteral { offset: usize },
+    MissingBraceInUnicodeEscape { offset: usize },
+    EmptyUnicodeEscape { offset: usize },
+    InvalidCodepointInUnicodeEscape { offset: usize, codepoint: u32 },
+    InputTooLarge { offset: usize, limit: usize },
+    TokenTooLong { offset: usize, kind: TokenKind, limit: usize },
+    ExpectedEndOfInput { span: Span, found: TokenKind },
+    InvalidUtf8 { offset: usize, byte: u8 },
 }
 
 impl std::error::Error for ScanError {}
@@ -41,17 +56,169 @@ impl std::fmt::Display for ScanError {
                     "unexpected end of input at offset {offset} in escape sequence"
                 )
             }
+            ScanError::UnknownNumericSuffix { offset, suffix } => {
+                write!(f, "unknown numeric suffix {suffix:?} at offset {offset}")
+            }
+            ScanError::UnexpectedEndOfInputInCharLiteral { offset, char_start } => {
+                write!(f, "unexpected end of input at offset {offset} in character literal starting at {char_start}")
+            }
+            ScanError::UnexpectedEndOfLineInCharLiteral { offset, char_start } => {
+                write!(f, "unexpected end of line at offset {offset} in character literal starting at {char_start}")
+            }
+            ScanError::EmptyCharLiteral { offset } => {
+                write!(f, "empty character literal at offset {offset}")
+            }
+            ScanError::TooManyCharactersInCharLiteral { offset } => {
+                write!(f, "character literal at offset {offset} contains more than one character")
+            }
+            ScanError::MissingBraceInUnicodeEscape { offset } => {
+                write!(f, "expected brace in unicode escape sequence at offset {offset}")
+            }
+            ScanError::EmptyUnicodeEscape { offset } => {
+                write!(f, "empty unicode escape sequence at offset {offset}")
+            }
+            ScanError::InvalidCodepointInUnicodeEscape { offset, codepoint } => {
+                write!(
+                    f,
+                    "invalid codepoint {codepoint:#x} in unicode escape sequence at offset {offset}"
+                )
+            }
+            ScanError::InputTooLarge { offset, limit } => {
+                write!(f, "input exceeds the limit of {limit} bytes at offset {offset}")
+            }
+            ScanError::TokenTooLong { offset, kind, limit } => {
+                write!(f, "{kind} exceeds the limit of {limit} bytes at offset {offset}")
+            }
+            ScanError::ExpectedEndOfInput { span, found } => {
+                write!(f, "expected end of input, found {found} at {span}")
+            }
+            ScanError::InvalidUtf8 { offset, byte } => {
+                write!(f, "invalid UTF-8 byte {byte:#04x} at offset {offset}")
+            }
         }
     }
 }
 
+#[allow(dead_code)]
+impl ScanError {
+    /// Byte offset in the input at which the error was detected.
+    pub fn offset(&self) -> usize {
+        match self {
+            ScanError::UnexpectedEndOfInput { offset }
+            | ScanError::UnexpectedCharacter { offset, .. }
+            | ScanError::UnexpectedCharacterInEscapeSequence { offset, .. }
+            | ScanError::UnexpectedEndOfInputInString { offset, .. }
+            | ScanError::UnexpectedEndOfInputInEscapeSequence { offset }
+            | ScanError::UnknownNumericSuffix { offset, .. }
+            | ScanError::UnexpectedEndOfInputInCharLiteral { offset, .. }
+            | ScanError::UnexpectedEndOfLineInCharLiteral { offset, .. }
+            | ScanError::EmptyCharLiteral { offset }
+            | ScanError::TooManyCharactersInCharLiteral { offset }
+            | ScanError::MissingBraceInUnicodeEscape { offset }
+            | ScanError::EmptyUnicodeEscape { offset }
+            | ScanError::InvalidCodepointInUnicodeEscape { offset, .. }
+            | ScanError::InputTooLarge { offset, .. }
+            | ScanError::TokenTooLong { offset, .. }
+            | ScanError::InvalidUtf8 { offset, .. } => *offset,
+            ScanError::ExpectedEndOfInput { span, .. } => span.start,
+        }
+    }
+
+    /// Show the line of `source` where the error occurred, with a caret
+    /// under the offending character. `source` must be the scanned input.
+    pub fn render(&self, source: &str) -> String {
+        render_snippet(source, self.offset())
+    }
+}
+
+/// Type suffixes that may follow a numeric literal, as in `42i64` or `3.0f32`.
+const NUMERIC_SUFFIXES: &[&str] = &[
+    "i8", "i16", "i32", "i64", "u8", "u16", "u32", "u64", "f32", "f64",
+];
+
+/// Options controlling the behavior of the scanner. The default options
+/// give the standard behavior.
+#[derive(Debug, Default, Clone)]
+pub struct ScanOptions {
+    /// Keep underscores in the text of number tokens, so that `text` is the
+    /// same as `raw_text` (minus any type suffix).
+    pub preserve_number_formatting: bool,
+    /// Maximum number of input bytes to scan, counted from where scanning
+    /// starts. Scanning past the limit fails with `InputTooLarge`, so that
+    /// large inputs cannot make the scanner do unbounded work.
+    pub max_bytes: Option<usize>,
+    /// Return line comments as `Comment` tokens instead of skipping them,
+    /// and record the whitespace before each token in the token.
+    pub preserve_trivia: bool,
+    /// Return a `Newline` token for each newline that ends an expression,
+    /// so that every line is a separate expression. A newline does not end
+    /// an expression inside parentheses, brackets or braces, after a token that needs
+    /// a right-hand side like an operator, `\` or `let`, or when it follows
+    /// a backslash at the end of a line, which is skipped with it. Blank
+    /// lines produce no tokens.
+    pub newline_separators: bool,
+    /// Also start line comments with `--`, as in Haskell and SQL. `-->` is
+    /// still `-` followed by `->`, and `- -` with a space in between is two
+    /// minus signs.
+    pub double_dash_comment: bool,
+    /// Scan every maximal run of these characters, for example
+    /// `+-*/<>=!&|^%`, as a single `Operator` token instead of as symbols,
+    /// and leave its meaning to the parser. A run stops before a comment.
+    pub operator_chars: Option<String>,
+    /// Interpolate names in string literals: `$name` refers to the name,
+    /// which must start with a letter or underscore and has no primes, and
+    /// `$$` is a literal `$`. Any other `$` is literal, too. The text of the
+    /// token keeps each `$name` as written, and `Token::parts` splits it
+    /// into literal text and names.
+    pub interpolate_names: bool,
+    /// Maximum length in bytes of an identifier or keyword. Longer ones
+    /// fail with `TokenTooLong`.
+    pub max_identifier_len: Option<usize>,
+    /// Maximum length in bytes of a number, including underscores and its
+    /// type suffix. Longer ones fail with `TokenTooLong`.
+    pub max_number_len: Option<usize>,
+}
+
+#[derive(Clone)]
 pub struct Scanner<'src> {
     input: &'src str,
+    options: ScanOptions,
+    /// Offset in `input` where scanning started.
+    base: usize,
+    /// Offset in `input` where `chars` starts.
+    chars_start: usize,
     chars: CharIndices<'src>,
     last_char: Option<char>,
     current_char: Option<char>,
     position: usize,
     token: Token<'src>,
+    /// Whether the current token has been returned by the iterator.
+    token_yielded: bool,
+    /// Whether the iterator has returned the `Eof` token or an error.
+    exhausted: bool,
+    /// Number of parentheses, brackets and braces opened by the tokens so
+    /// far and not closed yet.
+    nesting: usize,
+    /// Kind of the last token that was not a comment, or `Eof` at the start.
+    previous: TokenKind,
+    /// For scanners created by `from_bytes`, the first byte that is not
+    /// valid UTF-8. `input` ends before it.
+    invalid_byte: Option<u8>,
+}
+
+/// The state of a scanner at some token, saved by `Scanner::checkpoint` so
+/// that the scanner can later be moved back to it with `Scanner::restore`.
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct Checkpoint<'src> {
+    position: usize,
+    last_char: Option<char>,
+    current_char: Option<char>,
+    token: Token<'src>,
+    token_yielded: bool,
+    exhausted: bool,
+    nesting: usize,
+    previous: TokenKind,
 }
 
 impl<'src> Scanner<'src> {
@@ -60,16 +227,79 @@ impl<'src> Scanner<'src> {
     /// # Errors
     /// Returns an error if the string does not start with a valid token.
     pub fn new(input: &'src str) -> Result<Scanner<'src>, ScanError> {
+        Scanner::with_options(input, ScanOptions::default())
+    }
+
+    /// Create a new scanner for the given string, using the given options.
+    ///
+    /// # Errors
+    /// Returns an error if the string does not start with a valid token.
+    pub fn with_options(
+        input: &'src str,
+        options: ScanOptions,
+    ) -> Result<Scanner<'src>, ScanError> {
+        Scanner::with_options_at(input, 0, options)
+    }
+
+    /// Create a new scanner for input that may not be valid UTF-8. The input
+    /// is scanned normally up to the first invalid byte, and scanning fails
+    /// with `InvalidUtf8` when it reaches that byte.
+    ///
+    /// # Errors
+    /// Returns an error if the input does not start with a valid token.
+    #[allow(dead_code)]
+    pub fn from_bytes(input: &'src [u8]) -> Result<Scanner<'src>, ScanError> {
+        let (valid, invalid_byte) = match std::str::from_utf8(input) {
+            Ok(valid) => (valid, None),
+            Err(e) => {
+                let (valid, rest) = input.split_at(e.valid_up_to());
+                let valid = std::str::from_utf8(valid).expect("prefix is valid UTF-8");
+                (valid, Some(rest[0]))
+            }
+        };
+        Scanner::start(valid, 0, ScanOptions::default(), invalid_byte)
+    }
+
+    /// Create a new scanner for the given string that starts scanning at the
+    /// given byte offset instead of the start of the string. Token positions
+    /// are still relative to the start of the string.
+    ///
+    /// # Errors
+    /// Returns an error if the string does not have a valid token at the
+    /// given offset.
+    pub fn with_options_at(
+        input: &'src str,
+        offset: usize,
+        options: ScanOptions,
+    ) -> Result<Scanner<'src>, ScanError> {
+        Scanner::start(input, offset, options, None)
+    }
+
+    /// Create a scanner and scan the first token.
+    fn start(
+        input: &'src str,
+        offset: usize,
+        options: ScanOptions,
+        invalid_byte: Option<u8>,
+    ) -> Result<Scanner<'src>, ScanError> {
         let mut scanner = Scanner {
             input,
-            chars: input.char_indices(),
+            options,
+            base: offset,
+            chars_start: offset,
+            chars: input[offset..].char_indices(),
             last_char: None,
             current_char: None,
-            position: 0,
+            position: offset,
             token: Token::new(TokenKind::Eof),
+            token_yielded: false,
+            exhausted: false,
+            nesting: 0,
+            previous: TokenKind::Eof,
+            invalid_byte,
         };
         scanner.scan_char()?;
-        scanner.scan()?;
+        scanner.advance()?;
         Ok(scanner)
     }
 
@@ -78,12 +308,57 @@ impl<'src> Scanner<'src> {
         if let Some((ofs, ch)) = self.chars.next() {
             self.last_char = self.current_char;
             self.current_char = Some(ch);
-            self.position = ofs;
+            self.position = self.chars_start + ofs;
         } else {
-            self.position += self.last_char.map_or(1, |c| c.len_utf8());
+            self.position += self.current_char.map_or(0, |c| c.len_utf8());
+            self.last_char = self.current_char;
             self.current_char = None;
         }
-        Ok(())
+        match self.options.max_bytes {
+            Some(limit) if self.position - self.base > limit => Err(ScanError::InputTooLarge {
+                offset: self.position,
+                limit,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Return the character following the current one, without moving the
+    /// scanner.
+    fn peek_char(&self) -> Option<char> {
+        self.chars.clone().next().map(|(_, ch)| ch)
+    }
+
+    /// Return whether the scanner is at `--` that starts a comment, that is,
+    /// one that is not followed by `>`.
+    fn at_double_dash_comment(&self) -> bool {
+        let mut rest = self.chars.clone().map(|(_, ch)| ch);
+        rest.next() == Some('-') && rest.next() != Some('>')
+    }
+
+    /// Return whether the scanner is at the start of a line comment.
+    fn at_comment(&self) -> bool {
+        match self.current_char {
+            Some('/') => self.peek_char() == Some('/'),
+            Some('-') => self.options.double_dash_comment && self.at_double_dash_comment(),
+            _ => false,
+        }
+    }
+
+    /// Return whether `ch` is one of the configured operator characters.
+    fn is_operator_char(&self, ch: char) -> bool {
+        self.options.operator_chars.as_ref().is_some_and(|chars| chars.contains(ch))
+    }
+
+    /// Scan a maximal run of operator characters as an `Operator` token.
+    fn scan_operator(&mut self) -> Result<(), ScanError> {
+        while let Some(ch) = self.current_char {
+            if !self.is_operator_char(ch) || self.at_comment() {
+                break;
+            }
+            self.scan_char()?;
+        }
+        self.finish_token(TokenKind::Operator)
     }
 
     /// Move the scanner to the next non-whitespace character.
@@ -97,12 +372,61 @@ impl<'src> Scanner<'src> {
         Ok(())
     }
 
+    /// Like `skip_whitespace`, but stop at a newline that ends an
+    /// expression, and skip line continuations; see
+    /// `ScanOptions::newline_separators`. Returns whether the scanner
+    /// stopped at a newline.
+    fn skip_whitespace_in_lines(&mut self) -> Result<bool, ScanError> {
+        loop {
+            match self.current_char {
+                Some('\n') if self.ends_expression() => return Ok(true),
+                Some('\\') if self.at_line_continuation() => {
+                    while self.current_char != Some('\n') {
+                        self.scan_char()?;
+                    }
+                    self.scan_char()?;
+                }
+                Some(ch) if ch.is_whitespace() => self.scan_char()?,
+                _ => return Ok(false),
+            }
+        }
+    }
+
+    /// Return whether a newline at the current position ends an expression.
+    fn ends_expression(&self) -> bool {
+        self.nesting == 0
+            && match self.previous {
+                TokenKind::Identifier
+                | TokenKind::Number
+                | TokenKind::Float
+                | TokenKind::String
+                | TokenKind::Char
+                | TokenKind::MetaVar => true,
+                TokenKind::Symbol(sym) => {
+                    matches!(sym, Symbol::RParen | Symbol::RBracket | Symbol::RBrace | Symbol::Question)
+                }
+                TokenKind::Keyword(kw) => kw == Keyword::End,
+                TokenKind::Eof | TokenKind::Newline | TokenKind::Comment | TokenKind::Operator => false,
+            }
+    }
+
+    /// Return whether the current character is a backslash followed by
+    /// nothing but whitespace up to the end of the line.
+    fn at_line_continuation(&self) -> bool {
+        let mut rest = self
+            .chars
+            .clone()
+            .map(|(_, ch)| ch)
+            .skip_while(|ch| *ch != '\n' && ch.is_whitespace());
+        rest.next() == Some('\n')
+    }
+
     /// Set the kind and end position, and the text/raw text fields of
     /// the token to the scanned porition of the input.
     fn finish_token(&mut self, kind: TokenKind) -> Result<(), ScanError> {
         self.token.kind = kind;
-        self.token.end = self.position;
-        self.token.raw_text = &self.input[self.token.start..self.token.end];
+        self.token.span.end = self.position;
+        self.token.raw_text = &self.input[self.token.span.start..self.token.span.end];
         self.token.text = self.token.raw_text.into();
         Ok(())
     }
@@ -124,9 +448,12 @@ impl<'src> Scanner<'src> {
             scanner.finish_token(TokenKind::Identifier)?;
             if let Some(kw) = match scanner.token.raw_text {
                 "if" => Some(Keyword::If),
+                "then" => Some(Keyword::Then),
                 "else" => Some(Keyword::Else),
                 "end" => Some(Keyword::End),
                 "fun" => Some(Keyword::Fun),
+                "let" => Some(Keyword::Let),
+                "in" => Some(Keyword::In),
                 _ => None,
             } {
                 scanner.token.kind = TokenKind::Keyword(kw);
@@ -139,37 +466,108 @@ impl<'src> Scanner<'src> {
                 'a'..='z' | 'A'..='Z' | '_' | '0'..='9' => {
                     self.scan_char()?;
                 }
-                _ => {
-                    return finish(self);
-                }
+                _ => break,
             }
         }
-        self.finish_token(TokenKind::Identifier)
+        // Identifiers may end in primes, as in `x'` or `f''`.
+        while self.current_char == Some('\'') {
+            self.scan_char()?;
+        }
+        self.check_token_len(TokenKind::Identifier, self.options.max_identifier_len)?;
+        finish(self)
     }
 
+    /// Move the scanner over a sequence of digits and underscores.
+    fn scan_digits(&mut self) -> Result<(), ScanError> {
+        while let Some(ch) = self.current_char {
+            match ch {
+                '0'..='9' | '_' => self.scan_char()?,
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Scan an integer or floating point literal, optionally followed by a
+    /// type suffix like `i64` or `f32`. The token text holds the numeric part
+    /// only (without underscores), the suffix is stored separately.
+    ///
+    /// A number with an exponent like `1e3` or `1e-3` is a float. The `e`
+    /// only starts an exponent if it is followed by a digit, or by a sign and
+    /// a digit, so `1-3` is a subtraction, and `1e-x` and `1e- 3` are errors
+    /// because `e` is not a valid suffix.
     fn scan_number(&mut self) -> Result<(), ScanError> {
         fn cleanup_number(token: &mut Token) -> Result<(), ScanError> {
-            let s = token
-                .raw_text
+            let suffix_len = token.num_suffix.map_or(0, str::len);
+            let s = token.raw_text[..token.raw_text.len() - suffix_len]
                 .chars()
-                .filter(|c| matches!(*c, '0'..='9'))
+                .filter(|c| *c != '_')
                 .collect::<String>();
             token.text = s.into();
             Ok(())
         }
+        fn keep_number(token: &mut Token) -> Result<(), ScanError> {
+            let suffix_len = token.num_suffix.map_or(0, str::len);
+            token.text = token.raw_text[..token.raw_text.len() - suffix_len].into();
+            Ok(())
+        }
+        let mut kind = TokenKind::Number;
+        self.scan_char()?;
+        self.scan_digits()?;
+        if self.current_char == Some('.') && self.peek_char().is_some_and(|c| c.is_ascii_digit()) {
+            kind = TokenKind::Float;
+            self.scan_char()?;
+            self.scan_digits()?;
+        }
+        if let Some('e' | 'E') = self.current_char {
+            let mut ahead = self.chars.clone().map(|(_, ch)| ch);
+            let digit = match ahead.next() {
+                Some('+' | '-') => ahead.next(),
+                ch => ch,
+            };
+            if digit.is_some_and(|c| c.is_ascii_digit()) {
+                kind = TokenKind::Float;
                 self.scan_char()?;
-        while let Some(ch) = self.current_char {
-            match ch {
-                '0'..='9' | '_' => {
+                if let Some('+' | '-') = self.current_char {
                     self.scan_char()?;
                 }
-                _ => {
-                    return self.finish_token_with(TokenKind::Number, cleanup_number);
-                }
+                self.scan_digits()?;
+            }
+        }
+        let cleanup = if self.options.preserve_number_formatting {
+            keep_number
+        } else {
+            cleanup_number
+        };
+        let number_end = self.position;
+        if let Some('a'..='z' | 'A'..='Z') = self.current_char {
+            while let Some('a'..='z' | 'A'..='Z' | '_' | '0'..='9') = self.current_char {
+                self.scan_char()?;
             }
+            let suffix = &self.input[number_end..self.position];
+            if !NUMERIC_SUFFIXES.contains(&suffix) {
+                return Err(ScanError::UnknownNumericSuffix {
+                    offset: number_end,
+                    suffix: suffix.to_string(),
+                });
+            }
+            self.token.num_suffix = Some(suffix);
+        }
+        self.check_token_len(kind, self.options.max_number_len)?;
+        self.finish_token_with(kind, cleanup)
+    }
+
+    /// Fail with `TokenTooLong` if the token scanned so far is longer than
+    /// `limit`. The error is reported at the first byte past the limit.
+    fn check_token_len(&self, kind: TokenKind, limit: Option<usize>) -> Result<(), ScanError> {
+        match limit {
+            Some(limit) if self.position - self.token.span.start > limit => Err(ScanError::TokenTooLong {
+                offset: self.token.span.start + limit,
+                kind,
+                limit,
+            }),
+            _ => Ok(()),
         }
-        
-        self.finish_token_with(TokenKind::Number, cleanup_number)
     }
 
     fn single_symbol(&mut self, symbol: Symbol) -> Result<(), ScanError> {
@@ -185,24 +583,109 @@ impl<'src> Scanner<'src> {
     ) -> Result<(), ScanError> {
         self.scan_char()?;
         match self.current_char {
-            Some(ch) if ch == expected => return self.single_symbol(double_symbol),
+            Some(ch) if ch == expected => self.single_symbol(double_symbol),
 
-            _ => return self.finish_token(TokenKind::Symbol(single_symbol)),
+            _ => self.finish_token(TokenKind::Symbol(single_symbol)),
         }
     }
 
     fn current_text(&self) -> &'src str {
-        &self.input[self.token.start..self.position]
+        &self.input[self.token.span.start..self.position]
+    }
+
+    /// Decode the escape sequence following a backslash. The scanner must be
+    /// positioned on the first character after the backslash, and is left
+    /// positioned after the escape sequence.
+    fn scan_escape(&mut self) -> Result<char, ScanError> {
+        let decoded = match self.current_char {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('\\') => '\\',
+            Some('"') => '"',
+            Some('\'') => '\'',
+            Some('u') => return self.scan_unicode_escape(),
+            Some(ch) => {
+                return Err(ScanError::UnexpectedCharacterInEscapeSequence {
+                    offset: self.position,
+                    unexpected: ch,
+                })
+            }
+            None => {
+                return Err(ScanError::UnexpectedEndOfInputInEscapeSequence {
+                    offset: self.position,
+                })
+            }
+        };
+        self.scan_char()?;
+        Ok(decoded)
+    }
+
+    /// Decode a `\u{HEX}` escape sequence with 1 to 6 hex digits. The
+    /// scanner must be positioned on the `u`.
+    fn scan_unicode_escape(&mut self) -> Result<char, ScanError> {
+        let escape_start = self.position;
+        self.scan_char()?;
+        if self.current_char != Some('{') {
+            return Err(ScanError::MissingBraceInUnicodeEscape {
+                offset: self.position,
+            });
+        }
+        self.scan_char()?;
+        let mut codepoint = 0u32;
+        let mut digits = 0;
+        loop {
+            match self.current_char {
+                Some('}') if digits == 0 => {
+                    return Err(ScanError::EmptyUnicodeEscape {
+                        offset: escape_start,
+                    })
+                }
+                Some('}') => break,
+                Some(ch) if digits == 6 && ch.is_ascii_hexdigit() => {
+                    return Err(ScanError::MissingBraceInUnicodeEscape {
+                        offset: self.position,
+                    })
+                }
+                Some(ch) if ch.is_ascii_hexdigit() => {
+                    codepoint = codepoint * 16 + ch.to_digit(16).unwrap_or(0);
+                    digits += 1;
+                    self.scan_char()?;
+                }
+                Some(ch) => {
+                    return Err(ScanError::UnexpectedCharacterInEscapeSequence {
+                        offset: self.position,
+                        unexpected: ch,
+                    })
+                }
+                None => {
+                    return Err(ScanError::UnexpectedEndOfInputInEscapeSequence {
+                        offset: self.position,
+                    })
+                }
+            }
+        }
+        self.scan_char()?;
+        char::from_u32(codepoint).ok_or(ScanError::InvalidCodepointInUnicodeEscape {
+            offset: escape_start,
+            codepoint,
+        })
     }
 
     fn scan_string(&mut self) -> Result<(), ScanError> {
-        let mut clean_string = None;
+        let mut clean_string: Option<String> = None;
+        let mut parts = Vec::new();
+        // Offset in `clean_string` where the current literal part starts.
+        let mut part_start = 0;
         self.scan_char()?;
         while let Some(ch) = self.current_char {
             match ch {
                 '"' => {
                     self.scan_char()?;
                     let text = if let Some(cs) = clean_string {
+                        if !parts.is_empty() && part_start < cs.len() {
+                            parts.push(StringPart::Literal(cs[part_start..].to_string()));
+                        }
                         Cow::from(cs)
                     } else {
                         let ct = self.current_text();
@@ -213,45 +696,25 @@ impl<'src> Scanner<'src> {
                     // return self.finish_token_with_text(TokenKind::String, Some(text));
                     self.finish_token(TokenKind::String)?;
                     self.token.text = text;
+                    self.token.parts = parts;
                     return Ok(());
                 }
+                '$' if self.options.interpolate_names => {
+                    let mut s = clean_string.take().unwrap_or_else(|| self.current_text()[1..].to_string());
+                    self.scan_interpolation(&mut s, &mut parts, &mut part_start)?;
+                    clean_string = Some(s);
+                }
                 '\\' => {
-                    self.scan_char()?;
-                    match self.current_char {
-                        Some(ch) if "nrt\\\"'".contains(ch) => {
-                            let mut s = match clean_string.take() {
-                                None => {
-                                    let ct = self.current_text();
-                                    // trim off quote at the start and the backslash that 
-                                    // introduced the current escape sequence.
-                                    ct[1..ct.len() - 1].to_string()
-                                }
-                                Some(s) => s,
-                            };
-                            match ch {
-                                'n' => s.push('\n'),
-                                'r' => s.push('\r'),
-                                't' => s.push('\t'),
-                                '\\' => s.push('\\'),
-                                '"' => s.push('"'),
-                                '\'' => s.push('\''),
-                                _ => unreachable!(),
-                            }
-                            clean_string = Some(s);
-                            self.scan_char()?
-                        }
-                        Some(ch) => {
-                            return Err(ScanError::UnexpectedCharacterInEscapeSequence {
-                                offset: self.position,
-                                unexpected: ch,
-                            })
-                        }
+                    let mut s = match clean_string.take() {
                         None => {
-                            return Err(ScanError::UnexpectedEndOfInputInEscapeSequence {
-                                offset: self.position,
-                            })
+                            // trim off the quote at the start.
+                            self.current_text()[1..].to_string()
                         }
-                    }
+                        Some(s) => s,
+                    };
+                    self.scan_char()?;
+                    s.push(self.scan_escape()?);
+                    clean_string = Some(s);
                 }
                 _ => {
                     if let Some(s) = &mut clean_string {
@@ -263,48 +726,299 @@ impl<'src> Scanner<'src> {
         }
         Err(ScanError::UnexpectedEndOfInputInString {
             offset: self.position,
-            string_start: self.token.start,
+            string_start: self.token.span.start,
         })
     }
 
+    /// Scan a `$` in an interpolating string and append what it stands for
+    /// to `text`, the decoded string so far. For a name, the literal text
+    /// since `part_start` and the name are added to `parts`.
+    fn scan_interpolation(
+        &mut self,
+        text: &mut String,
+        parts: &mut Vec<StringPart<'src>>,
+        part_start: &mut usize,
+    ) -> Result<(), ScanError> {
+        self.scan_char()?;
+        match self.current_char {
+            Some('$') => {
+                self.scan_char()?;
+                text.push('$');
+            }
+            Some('a'..='z' | 'A'..='Z' | '_') => {
+                let start = self.position;
+                while let Some('a'..='z' | 'A'..='Z' | '_' | '0'..='9') = self.current_char {
+                    self.scan_char()?;
+                }
+                let name = &self.input[start..self.position];
+                if *part_start < text.len() {
+                    parts.push(StringPart::Literal(text[*part_start..].to_string()));
+                }
+                parts.push(StringPart::Name {
+                    name,
+                    span: Span::new(start, self.position),
+                });
+                text.push('$');
+                text.push_str(name);
+                *part_start = text.len();
+            }
+            _ => text.push('$'),
+        }
+        Ok(())
+    }
+
+    /// Scan a metavariable `?name`, or a `?` symbol if it is not
+    /// immediately followed by an identifier.
+    fn scan_question_or_metavar(&mut self) -> Result<(), ScanError> {
+        self.scan_char()?;
+        if !matches!(self.current_char, Some('a'..='z' | 'A'..='Z' | '_')) {
+            return self.finish_token(TokenKind::Symbol(Symbol::Question));
+        }
+        while let Some('a'..='z' | 'A'..='Z' | '_' | '0'..='9') = self.current_char {
+            self.scan_char()?;
+        }
+        while self.current_char == Some('\'') {
+            self.scan_char()?;
+        }
+        self.finish_token(TokenKind::MetaVar)?;
+        self.token.text = Cow::from(&self.token.raw_text[1..]);
+        Ok(())
+    }
+
+    /// Scan a raw string literal `r"..."` or `r#"..."#`, with any number of
+    /// `#`s. There are no escape sequences, and the string ends at the first
+    /// `"` followed by as many `#`s as there are at the start.
+    fn scan_raw_string(&mut self) -> Result<(), ScanError> {
+        self.scan_char()?;
+        let mut hashes = 0;
+        while self.current_char == Some('#') {
+            hashes += 1;
+            self.scan_char()?;
+        }
+        match self.current_char {
+            Some('"') => self.scan_char()?,
+            Some(ch) => {
+                return Err(ScanError::UnexpectedCharacter {
+                    offset: self.position,
+                    unexpected: ch,
+                })
+            }
+            None => {
+                return Err(ScanError::UnexpectedEndOfInputInString {
+                    offset: self.position,
+                    string_start: self.token.span.start,
+                })
+            }
+        }
+        let content_start = self.position;
+        while let Some(ch) = self.current_char {
+            if ch != '"' {
+                self.scan_char()?;
+                continue;
+            }
+            let content_end = self.position;
+            self.scan_char()?;
+            let mut closing = 0;
+            while closing < hashes && self.current_char == Some('#') {
+                closing += 1;
+                self.scan_char()?;
+            }
+            if closing == hashes {
+                self.finish_token(TokenKind::String)?;
+                self.token.text = Cow::from(&self.input[content_start..content_end]);
+                return Ok(());
+            }
+        }
+        Err(ScanError::UnexpectedEndOfInputInString {
+            offset: self.position,
+            string_start: self.token.span.start,
+        })
+    }
+
+    /// Scan a character literal like `'a'` or `'\n'`. The token text is the
+    /// decoded character.
+    fn scan_char_literal(&mut self) -> Result<(), ScanError> {
+        self.scan_char()?;
+        let decoded = match self.current_char {
+            Some('\'') => {
+                return Err(ScanError::EmptyCharLiteral {
+                    offset: self.token.span.start,
+                })
+            }
+            Some('\\') => {
+                self.scan_char()?;
+                self.scan_escape()?
+            }
+            Some(ch) => {
+                self.scan_char()?;
+                ch
+            }
+            None => {
+                return Err(ScanError::UnexpectedEndOfInputInCharLiteral {
+                    offset: self.position,
+                    char_start: self.token.span.start,
+                })
+            }
+        };
+        if self.current_char == Some('\'') {
+            self.scan_char()?;
+            self.finish_token(TokenKind::Char)?;
+            self.token.text = decoded.to_string().into();
+            return Ok(());
+        }
+        // Find the closing quote on the same line to tell a too-long literal
+        // from an unterminated one.
+        while let Some(ch) = self.current_char {
+            match ch {
+                '\'' => {
+                    return Err(ScanError::TooManyCharactersInCharLiteral {
+                        offset: self.token.span.start,
+                    })
+                }
+                '\n' => {
+                    return Err(ScanError::UnexpectedEndOfLineInCharLiteral {
+                        offset: self.position,
+                        char_start: self.token.span.start,
+                    })
+                }
+                _ => self.scan_char()?,
+            }
+        }
+        Err(ScanError::UnexpectedEndOfInputInCharLiteral {
+            offset: self.position,
+            char_start: self.token.span.start,
+        })
+    }
+
+    /// Skip a line comment up to, but not including, the end of the line.
     fn skip_line_comment(&mut self) -> Result<(), ScanError> {
         while let Some(ch) = self.current_char {
             if ch == '\n' {
-                return self.scan_char();
+                break;
             }
             self.scan_char()?;
         }
         Ok(())
     }
 
+    /// Scan a line comment up to, but not including, the end of the line.
+    fn scan_line_comment(&mut self) -> Result<(), ScanError> {
+        while let Some(ch) = self.current_char {
+            if ch == '\n' {
+                break;
+            }
+            self.scan_char()?;
+        }
+        self.finish_token(TokenKind::Comment)
+    }
+
     /// Advance the scanner to the next token, skipping over whitespace and comments.
-    pub fn scan(&mut self) -> Result<(), ScanError> {
+    pub fn advance(&mut self) -> Result<(), ScanError> {
+        self.token_yielded = false;
+        match self.token.kind {
+            TokenKind::Symbol(Symbol::LParen | Symbol::LBracket | Symbol::LBrace) => self.nesting += 1,
+            TokenKind::Symbol(Symbol::RParen | Symbol::RBracket | Symbol::RBrace) => {
+                self.nesting = self.nesting.saturating_sub(1)
+            }
+            _ => {}
+        }
+        if self.token.kind != TokenKind::Comment {
+            self.previous = self.token.kind;
+        }
+        let result = self.scan_token();
+        // Tokens ended by an invalid byte are kept, but reaching the end of
+        // the valid input, or failing there, means the byte is next.
+        match self.invalid_byte {
+            Some(byte) if self.current_char.is_none() && (result.is_err() || self.token.kind == TokenKind::Eof) => {
+                Err(ScanError::InvalidUtf8 {
+                    offset: self.input.len(),
+                    byte,
+                })
+            }
+            _ => result,
+        }
+    }
+
+    /// Implementation of `advance`, scanning the next token.
+    fn scan_token(&mut self) -> Result<(), ScanError> {
         loop {
-            self.skip_whitespace()?;
-            self.token.start = self.position;
+            let whitespace_start = self.position;
+            let at_newline = if self.options.newline_separators {
+                self.skip_whitespace_in_lines()?
+            } else {
+                self.skip_whitespace()?;
+                false
+            };
+            if self.options.preserve_trivia {
+                self.token.leading_whitespace = &self.input[whitespace_start..self.position];
+            }
+            self.token.span.start = self.position;
+            self.token.num_suffix = None;
+            self.token.parts.clear();
+            if at_newline {
+                self.scan_char()?;
+                return self.finish_token(TokenKind::Newline);
+            }
             if let Some(ch) = self.current_char {
                 match ch {
+                    ch if self.is_operator_char(ch) && !self.at_comment() => return self.scan_operator(),
                     '/' => {
                         self.scan_char()?;
                         match self.current_char {
+                            Some('/') if self.options.preserve_trivia => {
+                                return self.scan_line_comment()
+                            }
                             Some('/') => self.skip_line_comment()?,
                             _ => return self.finish_token(TokenKind::Symbol(Symbol::Slash)),
                         }
                     }
+                    'r' if matches!(self.peek_char(), Some('"' | '#')) => {
+                        return self.scan_raw_string()
+                    }
                     'a'..='z' | 'A'..='Z' | '_' => return self.scan_identifier_or_keyword(),
                     '0'..='9' => return self.scan_number(),
                     ':' => {
                         return self.maybe_double_symbol(':', Symbol::Colon, Symbol::DoubleColon)
                     }
+                    '=' if self.peek_char() == Some('>') => {
+                        self.scan_char()?;
+                        return self.single_symbol(Symbol::FatArrow);
+                    }
                     '=' => return self.maybe_double_symbol('=', Symbol::Eq, Symbol::EqEq),
                     ';' => return self.single_symbol(Symbol::Semicolon),
                     ',' => return self.single_symbol(Symbol::Comma),
+                    '(' => return self.single_symbol(Symbol::LParen),
+                    ')' => return self.single_symbol(Symbol::RParen),
+                    '{' => return self.single_symbol(Symbol::LBrace),
+                    '}' => return self.single_symbol(Symbol::RBrace),
+                    '[' => return self.single_symbol(Symbol::LBracket),
+                    ']' => return self.single_symbol(Symbol::RBracket),
+                    '@' => return self.single_symbol(Symbol::At),
                     '.' => return self.single_symbol(Symbol::Dot),
                     '+' => return self.single_symbol(Symbol::Plus),
                     '*' => return self.single_symbol(Symbol::Star),
+                    '&' => {
+                        return self.maybe_double_symbol('&', Symbol::Ampersand, Symbol::AmpAmp)
+                    }
+                    '-' if self.options.double_dash_comment && self.at_double_dash_comment() => {
+                        self.scan_char()?;
+                        if self.options.preserve_trivia {
+                            return self.scan_line_comment();
+                        }
+                        self.skip_line_comment()?;
+                    }
                     '-' => return self.maybe_double_symbol('>', Symbol::Minus, Symbol::Arrow),
+                    '<' if self.peek_char() == Some('-') => {
+                        self.scan_char()?;
+                        return self.single_symbol(Symbol::LeftArrow);
+                    }
+                    '<' => return self.maybe_double_symbol('=', Symbol::Less, Symbol::LessEq),
+                    '>' => return self.maybe_double_symbol('=', Symbol::Greater, Symbol::GreaterEq),
+                    '|' => return self.maybe_double_symbol('>', Symbol::Bar, Symbol::PipeRight),
                     '\\' => return self.single_symbol(Symbol::Backslash),
                     '"' => return self.scan_string(),
+                    '\'' => return self.scan_char_literal(),
+                    '?' => return self.scan_question_or_metavar(),
                     _ => {
                         return Err(ScanError::UnexpectedCharacter {
                             offset: self.position,
@@ -321,23 +1035,382 @@ impl<'src> Scanner<'src> {
     pub fn token(&self) -> &Token<'src> {
         &self.token
     }
+
+    /// The kind of the token before the current one, not counting comments,
+    /// or `None` if the current token is the first.
+    pub fn prev_kind(&self) -> Option<TokenKind> {
+        (self.previous != TokenKind::Eof).then_some(self.previous)
+    }
+
+    /// Return the token after the current one if it starts right where the
+    /// current one ends, without whitespace or comments in between, so that
+    /// callers can merge the two, e.g. into a custom operator. The scanner
+    /// does not move. Returns `None` at the end of input and if the next
+    /// token cannot be scanned; the error is reported by `advance`.
+    #[allow(dead_code)]
+    pub fn peek_adjacent(&self) -> Option<Token<'src>> {
+        if self.token.kind == TokenKind::Eof || self.position != self.token.end() {
+            return None;
+        }
+        let mut lookahead = self.clone();
+        lookahead.advance().ok()?;
+        let token = lookahead.token;
+        (token.kind != TokenKind::Eof && token.start() == self.token.end()).then_some(token)
+    }
+
+    /// Save the state of the scanner at the current token, so that a
+    /// backtracking parser can return to it with `restore`. This copies the
+    /// current token, which only allocates if its text is owned, as for
+    /// strings with escape sequences.
+    #[allow(dead_code)]
+    pub fn checkpoint(&self) -> Checkpoint<'src> {
+        Checkpoint {
+            position: self.position,
+            last_char: self.last_char,
+            current_char: self.current_char,
+            token: self.token.clone(),
+            token_yielded: self.token_yielded,
+            exhausted: self.exhausted,
+            nesting: self.nesting,
+            previous: self.previous,
+        }
+    }
+
+    /// Move the scanner back to a checkpoint taken from this scanner. The
+    /// character iterator is re-created from the saved position, which takes
+    /// constant time, so backtracking only costs scanning the same tokens
+    /// again.
+    #[allow(dead_code)]
+    pub fn restore(&mut self, cp: Checkpoint<'src>) {
+        // `chars` continues after the current character.
+        self.chars_start = cp.position + cp.current_char.map_or(0, char::len_utf8);
+        self.chars = self.input[self.chars_start..].char_indices();
+        self.position = cp.position;
+        self.last_char = cp.last_char;
+        self.current_char = cp.current_char;
+        self.token = cp.token;
+        self.token_yielded = cp.token_yielded;
+        self.exhausted = cp.exhausted;
+        self.nesting = cp.nesting;
+        self.previous = cp.previous;
+    }
+
+    /// Check that all of the input has been scanned, that is, that the
+    /// current token is `Eof`.
+    #[allow(dead_code)]
+    pub fn expect_eof(&self) -> Result<(), ScanError> {
+        match self.token.kind() {
+            TokenKind::Eof => Ok(()),
+            found => Err(ScanError::ExpectedEndOfInput {
+                span: self.token.span(),
+                found,
+            }),
+        }
+    }
+}
+
+/// Produce a stable, line-oriented dump of all tokens in the input, one
+/// token per line in the form `kind start..end raw`, including the final
+/// `Eof` token. A scan error ends the dump with an `error:` line.
+#[allow(dead_code)]
+pub fn tokenize_debug(input: &str) -> String {
+    let mut output = String::new();
+    let tokens = match Scanner::new(input) {
+        Ok(scanner) => scanner,
+        Err(e) => return format!("error: {e}\n"),
+    };
+    for token in tokens {
+        match token {
+            Ok(t) => output.push_str(&format!(
+                "{:?} {} {:?}\n",
+                t.kind(),
+                t.span(),
+                t.raw_text()
+            )),
+            Err(e) => output.push_str(&format!("error: {e}\n")),
+        }
+    }
+    output
+}
+
+/// Iterating over a scanner yields the current token and all following
+/// tokens, up to and including the `Eof` token. After an error or the `Eof`
+/// token, the iterator only returns `None`.
+impl<'src> Iterator for Scanner<'src> {
+    type Item = Result<Token<'src>, ScanError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.exhausted {
+            return None;
+        }
+        if self.token_yielded {
+            if let Err(e) = self.advance() {
+                self.exhausted = true;
+                return Some(Err(e));
+            }
+        }
+        self.token_yielded = true;
+        if self.token.kind == TokenKind::Eof {
+            self.exhausted = true;
+        }
+        Some(Ok(self.token.clone()))
+    }
 }
 
+impl<'src> FusedIterator for Scanner<'src> {}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::token::IdentCase;
 
-    fn run(input: &str) -> Result<Vec<Token>, ScanError> {
-        let mut scanner = Scanner::new(input)?;
-        let mut output = Vec::new();
-        loop {
-            output.push(scanner.token().clone());
-            if scanner.token().kind() == TokenKind::Eof {
-                break;
-            }
-            scanner.scan()?;
-        }
-        Ok(output)
+    fn run(input: &str) -> Result<Vec<Token<'_>>, ScanError> {
+        Scanner::new(input)?.collect()
+    }
+
+    #[test]
+    fn expect_eof() {
+        let mut scanner = Scanner::new("a b").expect("scanning example input");
+        scanner.advance().expect("scanning example input");
+        let e = scanner.expect_eof().expect_err("should fail");
+        assert!(matches!(
+            e,
+            ScanError::ExpectedEndOfInput { span: Span { start: 2, end: 3 }, found: TokenKind::Identifier }
+        ));
+        assert_eq!(e.to_string(), "expected end of input, found identifier at 2..3");
+        scanner.advance().expect("scanning example input");
+        assert!(scanner.expect_eof().is_ok());
+    }
+
+    #[test]
+    fn double_dash_comments() {
+        use TokenKind::{Comment, Eof, Identifier, Number};
+        let minus = TokenKind::Symbol(Symbol::Minus);
+        let arrow = TokenKind::Symbol(Symbol::Arrow);
+        let kinds = |input, options: ScanOptions| {
+            Scanner::with_options(input, options)
+                .expect("scanning example input")
+                .map(|t| t.map(|t| t.kind()))
+                .collect::<Result<Vec<_>, _>>()
+                .expect("scanning example input")
+        };
+        let options = ScanOptions {
+            double_dash_comment: true,
+            ..ScanOptions::default()
+        };
+        assert_eq!(kinds("a -- comment\n- 1", options.clone()), [Identifier, minus, Number, Eof]);
+        assert_eq!(kinds("a - -b", options.clone()), [Identifier, minus, minus, Identifier, Eof]);
+        assert_eq!(kinds("a -->b", options.clone()), [Identifier, minus, arrow, Identifier, Eof]);
+        assert_eq!(kinds("a ---\n", options.clone()), [Identifier, Eof]);
+
+        let ts = Scanner::with_options(
+            "x --y",
+            ScanOptions {
+                preserve_trivia: true,
+                ..options
+            },
+        )
+        .expect("scanning example input")
+        .collect::<Result<Vec<_>, _>>()
+        .expect("scanning example input");
+        assert_eq!(ts[1].kind(), Comment);
+        assert_eq!(ts[1].raw_text(), "--y");
+
+        // Without the option, `--` is two minus signs.
+        assert_eq!(kinds("a --b", ScanOptions::default()), [Identifier, minus, minus, Identifier, Eof]);
+    }
+
+    #[test]
+    fn operator_runs() {
+        let tokens = |input, options: ScanOptions| {
+            Scanner::with_options(input, options)
+                .expect("scanning example input")
+                .map(|t| t.map(|t| (t.kind(), t.raw_text().to_string())))
+                .collect::<Result<Vec<_>, _>>()
+                .expect("scanning example input")
+        };
+        let op = |text: &str| (TokenKind::Operator, text.to_string());
+        let ident = |text: &str| (TokenKind::Identifier, text.to_string());
+        let eof = (TokenKind::Eof, String::new());
+        let options = ScanOptions {
+            operator_chars: Some("+-*/<>=!&|^%".into()),
+            ..ScanOptions::default()
+        };
+        assert_eq!(tokens("a <=> b", options.clone()), [ident("a"), op("<=>"), ident("b"), eof.clone()]);
+        assert_eq!(tokens("m>>=f", options.clone()), [ident("m"), op(">>="), ident("f"), eof.clone()]);
+        // Other symbols are not affected, and runs stop before comments.
+        assert_eq!(
+            tokens("(a)+// comment\n;", options.clone()),
+            [
+                (TokenKind::Symbol(Symbol::LParen), "(".into()),
+                ident("a"),
+                (TokenKind::Symbol(Symbol::RParen), ")".into()),
+                op("+"),
+                (TokenKind::Symbol(Symbol::Semicolon), ";".into()),
+                eof.clone(),
+            ]
+        );
+        let options = ScanOptions {
+            double_dash_comment: true,
+            ..options
+        };
+        assert_eq!(tokens("a *-- comment", options.clone()), [ident("a"), op("*"), eof.clone()]);
+        assert_eq!(tokens("a --> b", options), [ident("a"), op("-->"), ident("b"), eof.clone()]);
+
+        // By default, the characters are scanned as symbols.
+        assert_eq!(tokens("a +- b", ScanOptions::default()).len(), 5);
+    }
+
+    #[test]
+    fn newline_separators() {
+        use TokenKind::{Eof, Newline, Number};
+        let options = ScanOptions {
+            newline_separators: true,
+            ..ScanOptions::default()
+        };
+        let kinds = |input| {
+            Scanner::with_options(input, options.clone())
+                .expect("scanning example input")
+                .map(|t| t.map(|t| t.kind()))
+                .collect::<Result<Vec<_>, _>>()
+                .expect("scanning example input")
+        };
+        let plus = TokenKind::Symbol(Symbol::Plus);
+        let star = TokenKind::Symbol(Symbol::Star);
+        assert_eq!(
+            kinds("\n1 + 1\n\n2 * 2 // two\n"),
+            [Number, plus, Number, Newline, Number, star, Number, Newline, Eof]
+        );
+        // Continued lines.
+        assert_eq!(kinds("1 +\n2"), [Number, plus, Number, Eof]);
+        assert_eq!(kinds("1 \\  \n + 2"), [Number, plus, Number, Eof]);
+        assert_eq!(kinds("(1\n+ 2)").len(), 6);
+        assert_eq!(kinds("f (1\n+ 2)\nx").iter().filter(|k| **k == Newline).count(), 1);
+
+        // Brackets nest like parentheses, and a list ends an expression.
+        let (lbracket, rbracket, comma) = (
+            TokenKind::Symbol(Symbol::LBracket),
+            TokenKind::Symbol(Symbol::RBracket),
+            TokenKind::Symbol(Symbol::Comma),
+        );
+        assert_eq!(
+            kinds("[1, 2]\n3"),
+            [lbracket, Number, comma, Number, rbracket, Newline, Number, Eof]
+        );
+        assert_eq!(kinds("[1\n, 2]"), [lbracket, Number, comma, Number, rbracket, Eof]);
+
+        // Without the option, newlines are whitespace.
+        let ts = run("1 + 1\n2").expect("scanning example input");
+        assert_eq!(ts.len(), 5);
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let mut scanner = Scanner::from_bytes(b"a b\xff c").expect("scanning example input");
+        assert_eq!(scanner.token().text(), "a");
+        // The token right before the invalid byte is still scanned.
+        scanner.advance().expect("scanning example input");
+        assert_eq!(scanner.token().text(), "b");
+        let e = scanner.advance().expect_err("should fail");
+        assert!(matches!(e, ScanError::InvalidUtf8 { offset: 3, byte: 0xff }));
+        assert_eq!(e.to_string(), "invalid UTF-8 byte 0xff at offset 3");
+
+        // Strings must be valid UTF-8, too.
+        let mut scanner = Scanner::from_bytes(b"x \"a\xc3(\" y").expect("scanning example input");
+        assert!(matches!(scanner.advance(), Err(ScanError::InvalidUtf8 { offset: 4, byte: 0xc3 })));
+        assert!(matches!(Scanner::from_bytes(b"\x80"), Err(ScanError::InvalidUtf8 { offset: 0, .. })));
+        let mut scanner = Scanner::from_bytes(b"abc\xff").expect("scanning example input");
+        assert_eq!(scanner.token().kind, TokenKind::Identifier);
+        assert_eq!(scanner.token().text(), "abc");
+        assert!(matches!(scanner.advance(), Err(ScanError::InvalidUtf8 { offset: 3, byte: 0xff })));
+        let mut scanner = Scanner::from_bytes(b"f 12\xff").expect("scanning example input");
+        scanner.advance().expect("scanning example input");
+        assert_eq!(scanner.token().text(), "12");
+        assert!(matches!(scanner.advance(), Err(ScanError::InvalidUtf8 { offset: 4, byte: 0xff })));
+
+        // Valid input is scanned as usual.
+        let tokens = Scanner::from_bytes("\"λ\" 2".as_bytes())
+            .expect("scanning example input")
+            .collect::<Result<Vec<_>, _>>()
+            .expect("scanning example input");
+        assert_eq!(tokens[0].text(), "λ");
+        assert_eq!(tokens.len(), 3);
+    }
+
+    #[test]
+    fn iterator() {
+        let mut scanner = Scanner::new("a 1").expect("scanning example input");
+        assert_eq!(scanner.next().unwrap().unwrap().kind(), TokenKind::Identifier);
+        assert_eq!(scanner.next().unwrap().unwrap().kind(), TokenKind::Number);
+        assert_eq!(scanner.next().unwrap().unwrap().kind(), TokenKind::Eof);
+        assert!(scanner.next().is_none());
+        assert!(scanner.next().is_none());
+
+        // Errors are returned once, then the iterator is fused.
+        let mut scanner = Scanner::new("a $ b").expect("scanning example input");
+        assert_eq!(scanner.next().unwrap().unwrap().kind(), TokenKind::Identifier);
+        assert!(matches!(
+            scanner.next(),
+            Some(Err(ScanError::UnexpectedCharacter { offset: 2, unexpected: '$' }))
+        ));
+        assert!(scanner.next().is_none());
+
+        // Iteration continues from tokens scanned manually.
+        let mut scanner = Scanner::new("a b c").expect("scanning example input");
+        scanner.advance().expect("scanning example input");
+        let texts = scanner
+            .map(|t| t.map(|t| t.text().to_string()))
+            .collect::<Result<Vec<_>, _>>()
+            .expect("scanning example input");
+        assert_eq!(texts, vec!["b", "c", ""]);
+    }
+
+    #[test]
+    fn snapshot_numbers() {
+        assert_eq!(
+            tokenize_debug("1 1_000 3.25f64 7u8"),
+            "\
+Number 0..1 \"1\"
+Number 2..7 \"1_000\"
+Float 8..15 \"3.25f64\"
+Number 16..19 \"7u8\"
+Eof 19..19 \"\"
+"
+        );
+    }
+
+    #[test]
+    fn snapshot_strings() {
+        assert_eq!(
+            tokenize_debug(r#""a\tb" 'c' "\u{41}" "oops"#),
+            r#"String 0..6 "\"a\\tb\""
+Char 7..10 "'c'"
+String 11..19 "\"\\u{41}\""
+error: unexpected end of input at offset 25 in string starting at 20
+"#
+        );
+    }
+
+    #[test]
+    fn snapshot_comments_and_symbols() {
+        assert_eq!(
+            tokenize_debug("main :: Integer; // signature\nmain = \\x. x -> x;"),
+            r#"Identifier 0..4 "main"
+Symbol(DoubleColon) 5..7 "::"
+Identifier 8..15 "Integer"
+Symbol(Semicolon) 15..16 ";"
+Identifier 30..34 "main"
+Symbol(Eq) 35..36 "="
+Symbol(Backslash) 37..38 "\\"
+Identifier 38..39 "x"
+Symbol(Dot) 39..40 "."
+Identifier 41..42 "x"
+Symbol(Arrow) 43..45 "->"
+Identifier 46..47 "x"
+Symbol(Semicolon) 47..48 ";"
+Eof 48..48 ""
+"#
+        );
     }
 
     #[test]
@@ -354,6 +1427,29 @@ mod test {
         assert_eq!(ts[1].raw_text(), "");
         assert_eq!(ts[1].start(), 4);
         assert_eq!(ts[1].end(), 4);
+
+        let ts = run("").expect("scanning example input");
+        assert_eq!(ts[0].kind(), TokenKind::Eof);
+        assert_eq!(ts[0].start(), 0);
+
+        let ts = run("\"é\"").expect("scanning example input");
+        assert_eq!(ts[0].end(), 4);
+        assert_eq!(ts[1].start(), 4);
+    }
+
+    #[test]
+    fn start_at_offset() {
+        let mut scanner = Scanner::with_options_at("a bc d", 2, ScanOptions::default())
+            .expect("scanning example input");
+        assert_eq!(scanner.token().text(), "bc");
+        assert_eq!(scanner.token().start(), 2);
+        assert_eq!(scanner.token().end(), 4);
+        scanner.advance().expect("scanning example input");
+        assert_eq!(scanner.token().text(), "d");
+        assert_eq!(scanner.token().start(), 5);
+        scanner.advance().expect("scanning example input");
+        assert_eq!(scanner.token().kind(), TokenKind::Eof);
+        assert_eq!(scanner.token().start(), 6);
     }
 
     #[test]
@@ -398,6 +1494,186 @@ mod test {
         assert_eq!(ts[0].end(), 5);
     }
 
+    #[test]
+    fn number_suffixes() {
+        let ts = run("42i64").expect("scanning example input");
+        assert_eq!(ts[0].kind(), TokenKind::Number);
+        assert_eq!(ts[0].text(), "42");
+        assert_eq!(ts[0].raw_text(), "42i64");
+        assert_eq!(ts[0].num_suffix(), Some("i64"));
+        assert_eq!(ts[0].start(), 0);
+        assert_eq!(ts[0].end(), 5);
+
+        let ts = run("3.0f32 255u8 7").expect("scanning example input");
+        assert_eq!(ts[0].kind(), TokenKind::Float);
+        assert_eq!(ts[0].text(), "3.0");
+        assert_eq!(ts[0].raw_text(), "3.0f32");
+        assert_eq!(ts[0].num_suffix(), Some("f32"));
+
+        assert_eq!(ts[1].kind(), TokenKind::Number);
+        assert_eq!(ts[1].text(), "255");
+        assert_eq!(ts[1].num_suffix(), Some("u8"));
+
+        assert_eq!(ts[2].kind(), TokenKind::Number);
+        assert_eq!(ts[2].num_suffix(), None);
+
+        // A dot not followed by a digit is not part of the number.
+        let ts = run("1.x").expect("scanning example input");
+        assert_eq!(ts[0].kind(), TokenKind::Number);
+        assert_eq!(ts[0].text(), "1");
+        assert_eq!(ts[1].kind(), TokenKind::Symbol(Symbol::Dot));
+
+        let e = run("42z").expect_err("should fail");
+        assert!(matches!(e, ScanError::UnknownNumericSuffix { offset: 2, ref suffix } if suffix == "z"));
+        // A name directly after a number is never split off, so `2x` is an
+        // error rather than `2` applied to `x`.
+        let e = run("2x").expect_err("should fail");
+        assert!(matches!(e, ScanError::UnknownNumericSuffix { offset: 1, ref suffix } if suffix == "x"));
+        let e = run("2_x").expect_err("should fail");
+        assert!(matches!(e, ScanError::UnknownNumericSuffix { offset: 2, ref suffix } if suffix == "x"));
+        let ts = run("2 x").expect("scanning example input");
+        assert_eq!(ts[0].kind(), TokenKind::Number);
+        assert_eq!(ts[1].kind(), TokenKind::Identifier);
+    }
+
+    #[test]
+    fn exponents() {
+        let kinds = |input| {
+            run(input)
+                .expect("scanning example input")
+                .iter()
+                .map(|t| (t.kind(), t.raw_text().to_string()))
+                .collect::<Vec<_>>()
+        };
+        let float = |text: &str| (TokenKind::Float, text.to_string());
+        let number = |text: &str| (TokenKind::Number, text.to_string());
+        let eof = (TokenKind::Eof, String::new());
+        let minus = (TokenKind::Symbol(Symbol::Minus), "-".to_string());
+        assert_eq!(kinds("1e-3"), [float("1e-3"), eof.clone()]);
+        assert_eq!(kinds("2.5E+10 1e3f64"), [float("2.5E+10"), float("1e3f64"), eof.clone()]);
+        assert_eq!(kinds("1-3"), [number("1"), minus.clone(), number("3"), eof.clone()]);
+        assert_eq!(kinds("1e3-3"), [float("1e3"), minus, number("3"), eof]);
+
+        // Without a digit, the `e` is taken as an unknown suffix.
+        for input in ["1e-x", "1e- 3", "1e"] {
+            assert!(matches!(
+                run(input),
+                Err(ScanError::UnknownNumericSuffix { offset: 1, ref suffix }) if suffix == "e"
+            ));
+        }
+    }
+
+    #[test]
+    fn checkpoints() {
+        let tokens = |scanner: &mut Scanner| {
+            scanner
+                .map(|t| t.map(|t| (t.kind(), t.span(), t.text().to_string())))
+                .collect::<Result<Vec<_>, _>>()
+                .expect("scanning example input")
+        };
+        let input = "f (x + 1) \"\\u{e9}t\u{e9}\" // c\ny";
+        let mut scanner = Scanner::new(input).expect("scanning example input");
+        scanner.advance().expect("scanning example input");
+        assert_eq!(scanner.token().kind(), TokenKind::Symbol(Symbol::LParen));
+        let cp = scanner.checkpoint();
+        let expected = tokens(&mut scanner.clone());
+        assert_eq!(expected.len(), 8);
+        for _ in 0..4 {
+            scanner.advance().expect("scanning example input");
+        }
+        assert_eq!(scanner.token().kind(), TokenKind::Symbol(Symbol::RParen));
+        scanner.restore(cp.clone());
+        assert_eq!(scanner.prev_kind(), Some(TokenKind::Identifier));
+        assert_eq!(tokens(&mut scanner), expected);
+
+        // Restoring after the end of input.
+        scanner.restore(cp);
+        assert_eq!(tokens(&mut scanner), expected);
+        let cp = scanner.checkpoint();
+        scanner.restore(cp);
+        assert!(scanner.next().is_none());
+    }
+
+    #[test]
+    fn token_length_limits() {
+        let options = ScanOptions {
+            max_identifier_len: Some(4),
+            max_number_len: Some(3),
+            ..ScanOptions::default()
+        };
+        let scan = |input| {
+            Scanner::with_options(input, options.clone())
+                .and_then(|scanner| scanner.collect::<Result<Vec<_>, _>>())
+                .map(|tokens| tokens.len())
+        };
+        assert!(matches!(scan("abcd 123 if"), Ok(4)));
+        assert!(matches!(
+            scan("f abcde"),
+            Err(ScanError::TokenTooLong {
+                offset: 6,
+                kind: TokenKind::Identifier,
+                limit: 4
+            })
+        ));
+        assert!(matches!(
+            scan("f 1234"),
+            Err(ScanError::TokenTooLong {
+                offset: 5,
+                kind: TokenKind::Number,
+                limit: 3
+            })
+        ));
+        // Underscores and suffixes count.
+        assert!(matches!(scan("1_00"), Err(ScanError::TokenTooLong { offset: 3, .. })));
+        assert!(matches!(scan("1i64"), Err(ScanError::TokenTooLong { offset: 3, .. })));
+        assert_eq!(
+            scan("1234").map_err(|e| e.to_string()),
+            Err("number exceeds the limit of 3 bytes at offset 3".into())
+        );
+        // Unlimited by default.
+        let long = "x".repeat(10_000);
+        assert!(matches!(Scanner::new(&long).map(|s| s.count()), Ok(2)));
+    }
+
+    #[test]
+    fn byte_budget() {
+        let options = ScanOptions {
+            max_bytes: Some(16),
+            ..ScanOptions::default()
+        };
+        let input = format!("x \"{}\" y", "a".repeat(100));
+        let mut scanner = Scanner::with_options(&input, options.clone()).expect("scanning example input");
+        assert!(matches!(
+            scanner.advance(),
+            Err(ScanError::InputTooLarge { offset: 17, limit: 16 })
+        ));
+
+        // Input that fits into the budget exactly.
+        let tokens = Scanner::with_options("\"0123456789abcd\"", options)
+            .expect("scanning example input")
+            .collect::<Result<Vec<_>, _>>()
+            .expect("scanning example input");
+        assert_eq!(tokens.len(), 2);
+    }
+
+    #[test]
+    fn number_formatting() {
+        let options = ScanOptions {
+            preserve_number_formatting: true,
+            ..ScanOptions::default()
+        };
+        let scanner = Scanner::with_options("1_000i32", options).expect("scanning example input");
+        let t = scanner.token();
+        assert_eq!(t.kind(), TokenKind::Number);
+        assert_eq!(t.text(), "1_000");
+        assert_eq!(t.raw_text(), "1_000i32");
+        assert_eq!(t.as_i64(), Some(1000));
+
+        let ts = run("1_000").expect("scanning example input");
+        assert_eq!(ts[0].text(), "1000");
+        assert_eq!(ts[0].as_i64(), Some(1000));
+    }
+
     #[test]
     fn identifiers() {
         let ts = run("a").expect("scanning example input");
@@ -445,6 +1721,98 @@ mod test {
         assert_eq!(ts[0].raw_text(), "a_1");
     }
 
+    #[test]
+    fn identifier_primes() {
+        let ts = run("x' f'' g'x 'a' y").expect("scanning example input");
+        let kinds = ts.iter().map(|t| (t.kind(), t.raw_text())).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                (TokenKind::Identifier, "x'"),
+                (TokenKind::Identifier, "f''"),
+                (TokenKind::Identifier, "g'"),
+                (TokenKind::Identifier, "x"),
+                (TokenKind::Char, "'a'"),
+                (TokenKind::Identifier, "y"),
+                (TokenKind::Eof, ""),
+            ]
+        );
+        assert_eq!(ts[1].text(), "f''");
+        assert_eq!((ts[1].start(), ts[1].end()), (3, 6));
+        // A prime at the end of input.
+        let ts = run("end'").expect("scanning example input");
+        assert_eq!(ts[0].kind(), TokenKind::Identifier);
+        assert_eq!(ts[0].text(), "end'");
+    }
+
+    #[test]
+    fn identifier_case() {
+        let ts = run("Foo foo _foo _Foo F1 x123 123 if").expect("scanning example input");
+        assert_eq!(ts[0].ident_case(), Some(IdentCase::Upper));
+        assert_eq!(ts[1].ident_case(), Some(IdentCase::Lower));
+        assert_eq!(ts[2].ident_case(), Some(IdentCase::Lower));
+        assert_eq!(ts[3].ident_case(), Some(IdentCase::Lower));
+        assert_eq!(ts[4].ident_case(), Some(IdentCase::Upper));
+        assert_eq!(ts[5].ident_case(), Some(IdentCase::Lower));
+        assert_eq!(ts[6].ident_case(), None);
+        assert_eq!(ts[7].ident_case(), None);
+        assert_eq!(ts[8].ident_case(), None);
+    }
+
+    #[test]
+    fn previous_kind() {
+        let mut scanner = Scanner::new("f (x) // comment\n- 1").expect("scanning example input");
+        assert_eq!(scanner.prev_kind(), None);
+        let mut previous = Vec::new();
+        while scanner.token().kind() != TokenKind::Eof {
+            scanner.advance().expect("scanning example input");
+            previous.push(scanner.prev_kind());
+        }
+        assert_eq!(
+            previous,
+            [
+                Some(TokenKind::Identifier),
+                Some(TokenKind::Symbol(Symbol::LParen)),
+                Some(TokenKind::Identifier),
+                // Comments are skipped.
+                Some(TokenKind::Symbol(Symbol::RParen)),
+                Some(TokenKind::Symbol(Symbol::Minus)),
+                Some(TokenKind::Number),
+            ]
+        );
+    }
+
+    #[test]
+    fn adjacent_tokens() {
+        let mut scanner = Scanner::new("*+ * + f(x) //\n;").expect("scanning example input");
+        let next = scanner.peek_adjacent().expect("'+' follows '*' directly");
+        assert_eq!(next.kind(), TokenKind::Symbol(Symbol::Plus));
+        assert_eq!(next.span(), Span::new(1, 2));
+        assert_eq!(scanner.token().kind(), TokenKind::Symbol(Symbol::Star));
+
+        scanner.advance().expect("scanning example input");
+        scanner.advance().expect("scanning example input");
+        assert_eq!(scanner.token().span(), Span::new(3, 4));
+        assert!(scanner.peek_adjacent().is_none());
+
+        scanner.advance().expect("scanning example input");
+        scanner.advance().expect("scanning example input");
+        let next = scanner.peek_adjacent().expect("'(' follows 'f' directly");
+        assert_eq!(next.kind(), TokenKind::Symbol(Symbol::LParen));
+
+        scanner.advance().expect("scanning example input");
+        scanner.advance().expect("scanning example input");
+        scanner.advance().expect("scanning example input");
+        assert_eq!(scanner.token().kind(), TokenKind::Symbol(Symbol::RParen));
+        // A comment separates tokens like whitespace.
+        assert!(scanner.peek_adjacent().is_none());
+
+        let scanner = Scanner::new("x$").expect("scanning example input");
+        assert!(scanner.peek_adjacent().is_none());
+        let scanner = Scanner::new("x").expect("scanning example input");
+        assert!(scanner.peek_adjacent().is_none());
+    }
+
     #[test]
     fn symbols() {
         let ts = run("; :: : = == , \\").expect("scanning example input");
@@ -475,6 +1843,47 @@ mod test {
         assert_eq!(ts[4].kind(), TokenKind::Symbol(Symbol::EqEq));
         assert_eq!(ts[5].kind(), TokenKind::Symbol(Symbol::Comma));
         assert_eq!(ts[6].kind(), TokenKind::Symbol(Symbol::Backslash));
+
+        let ts = run("< <= <- > >= =>").expect("scanning example input");
+        let kinds = ts.iter().map(|t| t.kind()).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                TokenKind::Symbol(Symbol::Less),
+                TokenKind::Symbol(Symbol::LessEq),
+                TokenKind::Symbol(Symbol::LeftArrow),
+                TokenKind::Symbol(Symbol::Greater),
+                TokenKind::Symbol(Symbol::GreaterEq),
+                TokenKind::Symbol(Symbol::FatArrow),
+                TokenKind::Eof,
+            ]
+        );
+
+        let ts = run("(f)").expect("scanning example input");
+        assert_eq!(ts[0].kind(), TokenKind::Symbol(Symbol::LParen));
+        assert_eq!(ts[1].kind(), TokenKind::Identifier);
+        assert_eq!(ts[2].kind(), TokenKind::Symbol(Symbol::RParen));
+        assert_eq!(ts[2].start(), 2);
+
+        let ts = run("{}").expect("scanning example input");
+        assert_eq!(ts[0].kind(), TokenKind::Symbol(Symbol::LBrace));
+        assert_eq!(ts[1].kind(), TokenKind::Symbol(Symbol::RBrace));
+
+        let ts = run("@[x]").expect("scanning example input");
+        assert_eq!(ts[0].kind(), TokenKind::Symbol(Symbol::At));
+        assert_eq!(ts[1].kind(), TokenKind::Symbol(Symbol::LBracket));
+        assert_eq!(ts[3].kind(), TokenKind::Symbol(Symbol::RBracket));
+        assert_eq!(ts[3].start(), 3);
+
+        let ts = run("&x && &&&").expect("scanning example input");
+        assert_eq!(ts[0].kind(), TokenKind::Symbol(Symbol::Ampersand));
+        assert_eq!(ts[0].end(), 1);
+        assert_eq!(ts[1].kind(), TokenKind::Identifier);
+        assert_eq!(ts[2].kind(), TokenKind::Symbol(Symbol::AmpAmp));
+        assert_eq!(ts[2].raw_text(), "&&");
+        assert_eq!(ts[3].kind(), TokenKind::Symbol(Symbol::AmpAmp));
+        assert_eq!(ts[4].kind(), TokenKind::Symbol(Symbol::Ampersand));
+        assert_eq!(ts[4].start(), 8);
     }
 
     #[test]
@@ -515,6 +1924,16 @@ mod test {
         assert_eq!(ts[5].raw_text(), "funny");
         assert_eq!(ts[5].start(), 23);
         assert_eq!(ts[5].end(), 28);
+
+        let ts = run("then let in inner").expect("scanning example input");
+        assert_eq!(ts[0].kind(), TokenKind::Keyword(Keyword::Then));
+        assert_eq!(ts[1].kind(), TokenKind::Keyword(Keyword::Let));
+        assert_eq!(ts[2].kind(), TokenKind::Keyword(Keyword::In));
+        assert_eq!(ts[3].kind(), TokenKind::Identifier);
+
+        // Keywords are recognized at the end of input, too.
+        let ts = run("end").expect("scanning example input");
+        assert_eq!(ts[0].kind(), TokenKind::Keyword(Keyword::End));
     }
 
     #[test]
@@ -545,6 +1964,45 @@ mod test {
         assert_eq!(ts[3].end(), 20);
     }
 
+    #[test]
+    fn interpolated_names() {
+        let options = ScanOptions {
+            interpolate_names: true,
+            ..ScanOptions::default()
+        };
+        let scan = |input| {
+            Scanner::with_options(input, options.clone())
+                .and_then(|scanner| scanner.collect::<Result<Vec<_>, _>>())
+                .expect("scanning example input")
+        };
+        let ts = scan(r#""hi $name""#);
+        assert_eq!(ts[0].text(), "hi $name");
+        assert_eq!(
+            ts[0].parts(),
+            [
+                StringPart::Literal("hi ".into()),
+                StringPart::Name {
+                    name: "name",
+                    span: Span::new(5, 9)
+                },
+            ]
+        );
+        let ts = scan(r#""$$" "\t$x_1!$ $2" "a$b$c""#);
+        assert_eq!(ts[0].text(), "$");
+        assert_eq!(ts[0].parts(), []);
+        let x = StringPart::Name {
+            name: "x_1",
+            span: Span::new(9, 12),
+        };
+        assert_eq!(ts[1].parts(), [StringPart::Literal("\t".into()), x, StringPart::Literal("!$ $2".into())]);
+        assert_eq!(ts[2].parts().len(), 3);
+
+        // Without the option, `$` has no special meaning.
+        let ts = run(r#""$$ $x""#).expect("scanning example input");
+        assert_eq!(ts[0].text(), "$$ $x");
+        assert_eq!(ts[0].parts(), []);
+    }
+
     #[test]
     fn strings_errors() {
         let e = run(r#"""#).expect_err("should fail");
@@ -555,6 +2013,149 @@ mod test {
         assert!(matches!(e, ScanError::UnexpectedEndOfInputInEscapeSequence { offset: 3 }));
     }
 
+    #[test]
+    fn raw_strings() {
+        let ts = run(r###"r"a\n b" r#"say "hi""# r##"a"#b"## r"""###).expect("scanning example input");
+        assert_eq!(ts[0].kind(), TokenKind::String);
+        assert_eq!(ts[0].text(), r"a\n b");
+        assert_eq!(ts[0].raw_text(), r#"r"a\n b""#);
+        assert_eq!((ts[0].start(), ts[0].end()), (0, 8));
+        assert_eq!(ts[1].text(), r#"say "hi""#);
+        assert_eq!(ts[1].raw_text(), r##"r#"say "hi""#"##);
+        assert_eq!(ts[2].text(), r##"a"#b"##);
+        assert_eq!(ts[3].text(), "");
+        // `r` on its own is still an identifier.
+        let ts = run("r rr r1").expect("scanning example input");
+        assert!(ts[..3].iter().all(|t| t.kind() == TokenKind::Identifier));
+
+        assert!(matches!(
+            run(r##"x r#"abc"##),
+            Err(ScanError::UnexpectedEndOfInputInString { offset: 8, string_start: 2 })
+        ));
+        assert!(matches!(
+            run("r#x"),
+            Err(ScanError::UnexpectedCharacter { offset: 2, unexpected: 'x' })
+        ));
+    }
+
+    #[test]
+    fn trivia() {
+        let input = "main = 1; // the answer\n  // more\nx";
+        let kinds = |options| {
+            Scanner::with_options(input, options)
+                .expect("scanning example input")
+                .map(|t| t.map(|t| (t.kind(), t.raw_text, t.leading_whitespace())))
+                .collect::<Result<Vec<_>, _>>()
+                .expect("scanning example input")
+        };
+        let options = ScanOptions {
+            preserve_trivia: true,
+            ..ScanOptions::default()
+        };
+        assert_eq!(
+            kinds(options),
+            [
+                (TokenKind::Identifier, "main", ""),
+                (TokenKind::Symbol(Symbol::Eq), "=", " "),
+                (TokenKind::Number, "1", " "),
+                (TokenKind::Symbol(Symbol::Semicolon), ";", ""),
+                (TokenKind::Comment, "// the answer", " "),
+                (TokenKind::Comment, "// more", "\n  "),
+                (TokenKind::Identifier, "x", "\n"),
+                (TokenKind::Eof, "", ""),
+            ]
+        );
+        let default = kinds(ScanOptions::default());
+        assert!(default.iter().all(|(kind, _, ws)| *kind != TokenKind::Comment && ws.is_empty()));
+        assert_eq!(default.len(), 6);
+    }
+
+    #[test]
+    fn metavariables() {
+        let ts = run("?x ? x ?if ?f' ?").expect("scanning example input");
+        let kinds = ts.iter().map(|t| (t.kind(), t.text())).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                (TokenKind::MetaVar, "x"),
+                (TokenKind::Symbol(Symbol::Question), "?"),
+                (TokenKind::Identifier, "x"),
+                (TokenKind::MetaVar, "if"),
+                (TokenKind::MetaVar, "f'"),
+                (TokenKind::Symbol(Symbol::Question), "?"),
+                (TokenKind::Eof, ""),
+            ]
+        );
+        assert_eq!(ts[0].raw_text(), "?x");
+        assert_eq!((ts[0].start(), ts[0].end()), (0, 2));
+    }
+
+    #[test]
+    fn multi_line_strings() {
+        let ts = run("\"one\ntwo\" x").expect("scanning example input");
+        assert_eq!(ts[0].kind(), TokenKind::String);
+        assert_eq!(ts[0].text(), "one\ntwo");
+        assert_eq!(ts[1].start(), 10);
+    }
+
+    #[test]
+    fn unicode_escapes() {
+        let ts = run(r#""\u{48}\u{69}" "caf\u{e9}" '\u{1F600}'"#).expect("scanning example input");
+        assert_eq!(ts[0].kind(), TokenKind::String);
+        assert_eq!(ts[0].text(), "Hi");
+        assert_eq!(ts[1].text(), "café");
+        assert_eq!(ts[2].kind(), TokenKind::Char);
+        assert_eq!(ts[2].text(), "\u{1F600}");
+
+        let e = run(r#""\u{D800}""#).expect_err("should fail");
+        assert!(matches!(e, ScanError::InvalidCodepointInUnicodeEscape { offset: 2, codepoint: 0xd800 }));
+        let e = run(r#""\u{110000}""#).expect_err("should fail");
+        assert!(matches!(e, ScanError::InvalidCodepointInUnicodeEscape { offset: 2, codepoint: 0x110000 }));
+        let e = run(r#""\u48""#).expect_err("should fail");
+        assert!(matches!(e, ScanError::MissingBraceInUnicodeEscape { offset: 3 }));
+        let e = run(r#""\u{1234567}""#).expect_err("should fail");
+        assert!(matches!(e, ScanError::MissingBraceInUnicodeEscape { offset: 10 }));
+        let e = run(r#""\u{}""#).expect_err("should fail");
+        assert!(matches!(e, ScanError::EmptyUnicodeEscape { offset: 2 }));
+        let e = run(r#""\u{4g}""#).expect_err("should fail");
+        assert!(matches!(e, ScanError::UnexpectedCharacterInEscapeSequence { offset: 5, unexpected: 'g' }));
+    }
+
+    #[test]
+    fn char_literals() {
+        let ts = run(r"'a' '\n' '\''").expect("scanning example input");
+        assert_eq!(ts[0].kind(), TokenKind::Char);
+        assert_eq!(ts[0].text(), "a");
+        assert_eq!(ts[0].raw_text(), "'a'");
+        assert_eq!(ts[0].start(), 0);
+        assert_eq!(ts[0].end(), 3);
+
+        assert_eq!(ts[1].kind(), TokenKind::Char);
+        assert_eq!(ts[1].text(), "\n");
+        assert_eq!(ts[1].raw_text(), r"'\n'");
+        assert_eq!(ts[1].start(), 4);
+        assert_eq!(ts[1].end(), 8);
+
+        assert_eq!(ts[2].kind(), TokenKind::Char);
+        assert_eq!(ts[2].text(), "'");
+        assert_eq!(ts[2].raw_text(), r"'\''");
+    }
+
+    #[test]
+    fn char_literals_errors() {
+        let e = run("''").expect_err("should fail");
+        assert!(matches!(e, ScanError::EmptyCharLiteral { offset: 0 }));
+        let e = run("'ab'").expect_err("should fail");
+        assert!(matches!(e, ScanError::TooManyCharactersInCharLiteral { offset: 0 }));
+        let e = run("'a").expect_err("should fail");
+        assert!(matches!(e, ScanError::UnexpectedEndOfInputInCharLiteral { offset: 2, char_start: 0 }));
+        // A quote on a later line does not close the literal.
+        let e = run("'ab\nx = 'c'").expect_err("should fail");
+        assert!(matches!(e, ScanError::UnexpectedEndOfLineInCharLiteral { offset: 3, char_start: 0 }));
+        let e = run(r"'\x'").expect_err("should fail");
+        assert!(matches!(e, ScanError::UnexpectedCharacterInEscapeSequence { offset: 2, unexpected: 'x' }));
+    }
+
     #[test]
     fn comments() {
         let ts = run(r###"hello
@@ -0,0 +1,172 @@
+use std::fmt::Display;
+
+/// A range of byte offsets in the source, from `start` (inclusive) to `end`
+/// (exclusive).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[allow(dead_code)]
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    /// Return the smallest span covering both spans.
+    pub fn merge(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+
+    /// Length of the span in bytes.
+    pub fn len(self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.start == self.end
+    }
+
+    /// Move the span by `delta` bytes, towards the end of the source if it is
+    /// positive. Offsets that would become negative are clamped to 0.
+    pub fn shift(self, delta: isize) -> Span {
+        Span {
+            start: self.start.saturating_add_signed(delta),
+            end: self.end.saturating_add_signed(delta),
+        }
+    }
+}
+
+/// Adjust spans after an edit at offset `at` that changed the length of the
+/// source by `delta` bytes: spans that start at or after `at` are moved by
+/// `delta`, and spans that end before it are kept. A span that contains the
+/// edit keeps its start and moves its end, so that it still covers the
+/// edited text. Offsets in text that the edit removed are clamped to `at`.
+#[allow(dead_code)]
+pub fn shift_spans_after(spans: &mut [Span], at: usize, delta: isize) {
+    for span in spans {
+        if span.start >= at {
+            let shifted = span.shift(delta);
+            *span = Span::new(shifted.start.max(at), shifted.end.max(at));
+        } else if span.end > at {
+            span.end = span.end.saturating_add_signed(delta).max(at);
+        }
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+/// A value together with the span of the source it was found at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(dead_code)]
+pub struct Spanned<T> {
+    pub value: T,
+    pub span: Span,
+}
+
+#[allow(dead_code)]
+impl<T> Spanned<T> {
+    pub fn new(value: T, span: Span) -> Spanned<T> {
+        Spanned { value, span }
+    }
+}
+
+/// Return the 1-based line and column of a byte offset in `source`.
+/// Columns count characters, not bytes. Offsets past the end of the source
+/// are treated as the end of the source.
+#[allow(dead_code)]
+pub fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..floor_char_boundary(source, offset)];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+/// Return the line of `source` containing the byte offset, followed by a
+/// line with a `^` under the character at the offset. An offset at the end
+/// of the source points just past the last character.
+#[allow(dead_code)]
+pub fn render_snippet(source: &str, offset: usize) -> String {
+    let offset = floor_char_boundary(source, offset);
+    let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[offset..].find('\n').map_or(source.len(), |i| offset + i);
+    let line = source[line_start..line_end].trim_end_matches('\r');
+    // Keep tabs, so that the caret lines up however tabs are displayed.
+    let padding = source[line_start..offset]
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect::<String>();
+    format!("{line}\n{padding}^")
+}
+
+/// The largest character boundary in `source` not after `offset`.
+fn floor_char_boundary(source: &str, offset: usize) -> usize {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn merge() {
+        let a = Span::new(2, 5);
+        let b = Span::new(7, 9);
+        assert_eq!(a.merge(b), Span::new(2, 9));
+        assert_eq!(b.merge(a), Span::new(2, 9));
+        assert_eq!(a.merge(Span::new(3, 4)), a);
+        assert_eq!(a.len(), 3);
+        assert!(!a.is_empty());
+        assert!(Span::new(4, 4).is_empty());
+        assert_eq!(a.to_string(), "2..5");
+    }
+
+    #[test]
+    fn shift() {
+        assert_eq!(Span::new(2, 5).shift(3), Span::new(5, 8));
+        assert_eq!(Span::new(2, 5).shift(-2), Span::new(0, 3));
+        assert_eq!(Span::new(2, 5).shift(-4), Span::new(0, 1));
+
+        // Insert 3 bytes at offset 4.
+        let mut spans = [Span::new(0, 2), Span::new(0, 4), Span::new(4, 6), Span::new(2, 8)];
+        shift_spans_after(&mut spans, 4, 3);
+        assert_eq!(spans, [Span::new(0, 2), Span::new(0, 4), Span::new(7, 9), Span::new(2, 11)]);
+
+        // Delete 3 bytes at offset 4. The end of the last span was deleted.
+        let mut spans = [Span::new(0, 2), Span::new(7, 9), Span::new(2, 11), Span::new(2, 6), Span::new(5, 8)];
+        shift_spans_after(&mut spans, 4, -3);
+        assert_eq!(
+            spans,
+            [Span::new(0, 2), Span::new(4, 6), Span::new(2, 8), Span::new(2, 4), Span::new(4, 5)]
+        );
+    }
+
+    #[test]
+    fn snippets() {
+        let source = "main :: Integer;\nmain = @;\n\tx";
+        assert_eq!(line_col(source, 0), (1, 1));
+        assert_eq!(line_col(source, 24), (2, 8));
+        assert_eq!(render_snippet(source, 24), "main = @;\n       ^");
+        // Tabs are kept, the offset at the end of input points past the
+        // last character.
+        assert_eq!(line_col(source, source.len()), (3, 3));
+        assert_eq!(render_snippet(source, source.len()), "\tx\n\t ^");
+        assert_eq!(render_snippet("a\n", 2), "\n^");
+        assert_eq!(render_snippet("", 0), "\n^");
+        // Columns count characters.
+        assert_eq!(line_col("\"é\" @", 5), (1, 5));
+        assert_eq!(render_snippet("\"é\" @", 5), "\"é\" @\n    ^");
+    }
+}
@@ -0,0 +1,327 @@
+use std::{borrow::Cow, rc::Rc};
+
+use crate::{
+    ast::{BinOp, Literal, Node, NodeKind},
+    parser::Parser,
+    span::Span,
+};
+
+/// Errors from `deserialize_stable`. Lines are 1-based.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum StableError {
+    /// The line is not indented as expected, or its kind or fields are
+    /// invalid.
+    InvalidLine { line: usize },
+    /// The node on the line has fewer children than its kind requires.
+    MissingChildren { line: usize },
+    /// The input continues after the tree.
+    TrailingInput { line: usize },
+    /// The input has no lines.
+    EmptyInput,
+}
+
+impl std::error::Error for StableError {}
+
+impl std::fmt::Display for StableError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StableError::InvalidLine { line } => write!(f, "invalid node on line {line}"),
+            StableError::MissingChildren { line } => write!(f, "node on line {line} is missing children"),
+            StableError::TrailingInput { line } => write!(f, "unexpected input after the tree on line {line}"),
+            StableError::EmptyInput => "no tree in empty input".fmt(f),
+        }
+    }
+}
+
+/// Serialize a tree in a line-oriented format that is stable under edits
+/// of the source, so that stored trees give meaningful diffs. Every node is
+/// on a line of its own, indented by two spaces per level, with its kind
+/// and the fields that are not child nodes, followed by its children in
+/// the order of `NodeKind::children`. Positions and annotations are not
+/// included. Kinds with a varying number of children give the counts:
+/// `MultiIf` the number of arms, followed by `otherwise` if it has one,
+/// `Let` the number of bindings, `List` the number of elements and
+/// `Comprehension` the numbers of generators and guards.
+#[allow(dead_code)]
+pub fn serialize_stable<Anno>(node: &Node<'_, Anno>) -> String {
+    let mut output = String::new();
+    write_node(node, 0, &mut output);
+    output
+}
+
+fn write_node<Anno>(node: &Node<'_, Anno>, level: usize, output: &mut String) {
+    output.push_str(&"  ".repeat(level));
+    let line = match node.kind() {
+        // Binders erased by `to_debruijn` have empty names.
+        NodeKind::Name { name, .. } if name.is_empty() => "Name".into(),
+        NodeKind::Name { name, .. } => format!("Name {name}"),
+        NodeKind::App { .. } => "App".into(),
+        NodeKind::Abs { .. } => "Abs".into(),
+        NodeKind::Lit { lit: Literal::Int(i) } => format!("Lit int {i}"),
+        NodeKind::Lit { lit: Literal::String(s) } => format!("Lit string {}", escape(s)),
+        NodeKind::Lit { lit: Literal::Unit } => "Lit unit".into(),
+        NodeKind::Lit { lit: Literal::BigInt(digits) } => format!("Lit bigint {digits}"),
+        NodeKind::BinOp { op, .. } => format!("BinOp {op}"),
+        NodeKind::Borrow { .. } => "Borrow".into(),
+        NodeKind::Ann { ty, .. } => format!("Ann {ty}"),
+        NodeKind::If { .. } => "If".into(),
+        NodeKind::MultiIf { arms, otherwise: None } => format!("MultiIf {}", arms.len()),
+        NodeKind::MultiIf { arms, otherwise: Some(_) } => format!("MultiIf {} otherwise", arms.len()),
+        NodeKind::Let { bindings, .. } => format!("Let {}", bindings.len()),
+        NodeKind::Seq { .. } => "Seq".into(),
+        NodeKind::BoundVar { index } => format!("BoundVar {index}"),
+        NodeKind::List { elems } => format!("List {}", elems.len()),
+        NodeKind::Comprehension { generators, guards, .. } => {
+            format!("Comprehension {} {}", generators.len(), guards.len())
+        }
+    };
+    output.push_str(&line);
+    output.push('\n');
+    for child in node.kind().children() {
+        write_node(child, level + 1, output);
+    }
+}
+
+/// Quote a string, escaping quotes, backslashes and control characters, so
+/// that it fits on one line.
+fn escape(s: &str) -> String {
+    let mut quoted = String::from('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            ch if ch.is_control() => quoted.push_str(&format!("\\u{{{:x}}}", ch as u32)),
+            ch => quoted.push(ch),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Inverse of `escape`.
+fn unescape(quoted: &str) -> Option<String> {
+    let mut chars = quoted.strip_prefix('"')?.strip_suffix('"')?.chars();
+    let mut s = String::new();
+    while let Some(ch) = chars.next() {
+        s.push(match ch {
+            '"' => return None,
+            '\\' => match chars.next()? {
+                '"' => '"',
+                '\\' => '\\',
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                'u' => {
+                    let rest = chars.as_str().strip_prefix('{')?;
+                    let (hex, rest) = rest.split_once('}')?;
+                    let ch = char::from_u32(u32::from_str_radix(hex, 16).ok()?)?;
+                    chars = rest.chars();
+                    ch
+                }
+                _ => return None,
+            },
+            ch => ch,
+        });
+    }
+    Some(s)
+}
+
+/// Read a tree written by `serialize_stable`. The nodes have default
+/// positions and no annotations. The result is equal to the serialized
+/// tree according to `alpha_eq`, and serializes to the same text.
+#[allow(dead_code)]
+pub fn deserialize_stable(input: &str) -> Result<Rc<Node<'static, ()>>, StableError> {
+    let mut lines = input.lines().enumerate().peekable();
+    if lines.peek().is_none() {
+        return Err(StableError::EmptyInput);
+    }
+    let node = read_node(&mut lines, 0, 0)?;
+    match lines.next() {
+        Some((index, _)) => Err(StableError::TrailingInput { line: index + 1 }),
+        None => Ok(node),
+    }
+}
+
+type Lines<'a> = std::iter::Peekable<std::iter::Enumerate<std::str::Lines<'a>>>;
+
+/// Read the node on the next line, which must be indented for `level`, and
+/// its children. A missing node is reported at the line of its `parent`.
+fn read_node(lines: &mut Lines<'_>, level: usize, parent: usize) -> Result<Rc<Node<'static, ()>>, StableError> {
+    let indent = "  ".repeat(level);
+    let Some((index, text)) = lines.next_if(|(_, text)| text.starts_with(&indent)) else {
+        return Err(StableError::MissingChildren { line: parent });
+    };
+    let line = index + 1;
+    let invalid = || StableError::InvalidLine { line };
+    let text = &text[indent.len()..];
+    let (kind, fields) = text.split_once(' ').unwrap_or((text, ""));
+    let count = |field: &str| field.parse::<usize>().map_err(|_| invalid());
+    let mut children = |n: usize| (0..n).map(|_| read_node(lines, level + 1, line)).collect::<Result<Vec<_>, _>>();
+    let kind = match (kind, fields) {
+        ("Name", name) => NodeKind::Name {
+            name: Cow::Owned(name.to_string()),
+            id: None,
+        },
+        ("App", "") => {
+            let [fun, arg] = array(children(2)?);
+            NodeKind::App { fun, arg }
+        }
+        ("Abs", "") => {
+            let [param, body] = array(children(2)?);
+            NodeKind::Abs { param, body }
+        }
+        ("Lit", fields) => {
+            let lit = match fields.split_once(' ').unwrap_or((fields, "")) {
+                ("int", i) => Literal::Int(i.parse().map_err(|_| invalid())?),
+                ("string", quoted) => Literal::String(unescape(quoted).ok_or_else(invalid)?),
+                ("unit", "") => Literal::Unit,
+                ("bigint", digits) if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) => {
+                    Literal::BigInt(digits.to_string())
+                }
+                _ => return Err(invalid()),
+            };
+            NodeKind::Lit { lit }
+        }
+        ("BinOp", op) => {
+            let op = BinOp::ALL
+                .into_iter()
+                .find(|candidate| candidate.to_string() == op)
+                .ok_or_else(invalid)?;
+            let [lhs, rhs] = array(children(2)?);
+            NodeKind::BinOp {
+                op,
+                op_span: Span::default(),
+                lhs,
+                rhs,
+            }
+        }
+        ("Borrow", "") => {
+            let [expr] = array(children(1)?);
+            NodeKind::Borrow { expr }
+        }
+        ("Ann", ty) => {
+            let ty = Parser::new(ty)
+                .and_then(|mut parser| parser.parse_type_only())
+                .map_err(|_| invalid())?;
+            let [expr] = array(children(1)?);
+            NodeKind::Ann { expr, ty: Rc::new(ty) }
+        }
+        ("If", "") => {
+            let [cond, conseq, alt] = array(children(3)?);
+            NodeKind::If { cond, conseq, alt }
+        }
+        ("MultiIf", fields) => {
+            let (n, has_otherwise) = match fields.strip_suffix(" otherwise") {
+                Some(n) => (n, true),
+                None => (fields, false),
+            };
+            let mut nodes = children(2 * count(n)? + has_otherwise as usize)?;
+            let otherwise = if has_otherwise { nodes.pop() } else { None };
+            NodeKind::MultiIf {
+                arms: pairs(nodes),
+                otherwise,
+            }
+        }
+        ("Let", n) => {
+            let mut nodes = children(2 * count(n)? + 1)?;
+            let body = nodes.pop().expect("let has a body");
+            NodeKind::Let {
+                bindings: pairs(nodes),
+                body,
+            }
+        }
+        ("Seq", "") => {
+            let [first, second] = array(children(2)?);
+            NodeKind::Seq { first, second }
+        }
+        ("BoundVar", index) => NodeKind::BoundVar { index: count(index)? },
+        ("List", n) => NodeKind::List {
+            elems: children(count(n)?)?,
+        },
+        ("Comprehension", counts) => {
+            let (generators, guards) = counts.split_once(' ').ok_or_else(invalid)?;
+            let (generators, guards) = (count(generators)?, count(guards)?);
+            let mut nodes = children(1 + 2 * generators + guards)?.into_iter();
+            let body = nodes.next().expect("comprehension has a body");
+            NodeKind::Comprehension {
+                body,
+                generators: pairs(nodes.by_ref().take(2 * generators).collect()),
+                guards: nodes.collect(),
+            }
+        }
+        _ => return Err(invalid()),
+    };
+    Ok(Rc::new(Node::new(Span::default(), (), kind)))
+}
+
+/// Convert the children read for a node of fixed arity to an array.
+fn array<T, const N: usize>(children: Vec<T>) -> [T; N] {
+    children
+        .try_into()
+        .unwrap_or_else(|_| unreachable!("exactly {N} children were read"))
+}
+
+/// Group the nodes of bindings or generators, which alternate between a
+/// name and a value, into pairs.
+fn pairs<T>(nodes: Vec<T>) -> Vec<(T, T)> {
+    let mut nodes = nodes.into_iter();
+    std::iter::from_fn(|| Some((nodes.next()?, nodes.next()?))).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        ast::{alpha_eq, to_debruijn},
+        parser::Parser,
+    };
+
+    #[test]
+    fn round_trip() {
+        let input = "{ let f = \\x. x + 1; g = &f in [f y | y <- [1, -2], y]; print (\"a\\\"b\\n\" (x : Int) (g : (Int -> Int) -> Int)); if g 0 then () else f 1 / 2 end }";
+        let mut parser = Parser::new(input).expect("scanning example input");
+        let term = parser.parse_script().expect("parsing example input");
+        let serialized = serialize_stable(&term);
+        let restored = deserialize_stable(&serialized).expect("deserializing");
+        assert!(alpha_eq(&term, &restored));
+        assert_eq!(serialize_stable(&restored), serialized);
+        assert_eq!(restored.to_string(), term.to_string());
+        let debruijn = to_debruijn(&term);
+        let restored = deserialize_stable(&serialize_stable(&debruijn)).expect("deserializing");
+        assert_eq!(serialize_stable(&restored), serialize_stable(&debruijn));
+
+        let mut parser = Parser::new("f (\\x. x) 1").expect("scanning example input");
+        assert_eq!(
+            serialize_stable(&parser.parse_script().expect("parsing example input")),
+            "App\n  App\n    Name f\n    Abs\n      Name x\n      Name x\n  Lit int 1\n"
+        );
+        let error = |input| deserialize_stable(input).map(|_| ()).unwrap_err();
+        assert_eq!(error("App\n  Name f\n"), StableError::MissingChildren { line: 1 });
+        assert_eq!(error("App\n  Name f\nName x\n"), StableError::MissingChildren { line: 1 });
+        assert_eq!(error("App\n  Name f\n   Name x\n"), StableError::InvalidLine { line: 3 });
+        assert_eq!(error("Lit int x\n"), StableError::InvalidLine { line: 1 });
+        assert_eq!(error("Name x\nName y\n"), StableError::TrailingInput { line: 2 });
+        assert_eq!(error(""), StableError::EmptyInput);
+    }
+
+    #[test]
+    fn minimal_diffs() {
+        let serialize = |input: &str| {
+            let mut parser = Parser::new(input).expect("scanning example input");
+            serialize_stable(&parser.parse_script().expect("parsing example input"))
+        };
+        // The leaf changes, and the spans after it, too.
+        let (old, new) = (serialize("f (g x) 1 + h"), serialize("f (g xyz) 1   + h"));
+        let changed = old
+            .lines()
+            .zip(new.lines())
+            .filter(|(a, b)| a != b)
+            .collect::<Vec<_>>();
+        assert_eq!(old.lines().count(), new.lines().count());
+        assert_eq!(changed, [("        Name x", "        Name xyz")]);
+    }
+}
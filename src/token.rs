@@ -1,28 +1,117 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, fmt::Display};
+
+use crate::span::Span;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Symbol {
     Eq,
     EqEq,
+    Less,
+    LessEq,
+    Greater,
+    GreaterEq,
     Comma,
     Colon,
     DoubleColon,
     Semicolon,
     Backslash,
     Arrow,
+    FatArrow,
+    LeftArrow,
+    Bar,
+    PipeRight,
     Dot,
     Plus,
     Minus,
     Slash,
     Star,
+    Ampersand,
+    AmpAmp,
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+    At,
+    Question,
+}
+
+impl Symbol {
+    /// The symbol as written in the source.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Symbol::Eq => "=",
+            Symbol::EqEq => "==",
+            Symbol::Less => "<",
+            Symbol::LessEq => "<=",
+            Symbol::Greater => ">",
+            Symbol::GreaterEq => ">=",
+            Symbol::Comma => ",",
+            Symbol::Colon => ":",
+            Symbol::DoubleColon => "::",
+            Symbol::Semicolon => ";",
+            Symbol::Backslash => "\\",
+            Symbol::Arrow => "->",
+            Symbol::FatArrow => "=>",
+            Symbol::LeftArrow => "<-",
+            Symbol::Bar => "|",
+            Symbol::PipeRight => "|>",
+            Symbol::Dot => ".",
+            Symbol::Plus => "+",
+            Symbol::Minus => "-",
+            Symbol::Slash => "/",
+            Symbol::Star => "*",
+            Symbol::Ampersand => "&",
+            Symbol::AmpAmp => "&&",
+            Symbol::LParen => "(",
+            Symbol::RParen => ")",
+            Symbol::LBrace => "{",
+            Symbol::RBrace => "}",
+            Symbol::LBracket => "[",
+            Symbol::RBracket => "]",
+            Symbol::At => "@",
+            Symbol::Question => "?",
+        }
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_str().fmt(f)
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Keyword {
     If,
+    Then,
     Else,
     End,
     Fun,
+    Let,
+    In,
+}
+
+impl Keyword {
+    /// The keyword as written in the source.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Keyword::If => "if",
+            Keyword::Then => "then",
+            Keyword::Else => "else",
+            Keyword::End => "end",
+            Keyword::Fun => "fun",
+            Keyword::Let => "let",
+            Keyword::In => "in",
+        }
+    }
+}
+
+impl Display for Keyword {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_str().fmt(f)
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -30,19 +119,121 @@ pub enum TokenKind {
     Eof,
     Identifier,
     Number,
+    Float,
     Symbol(Symbol),
     String,
+    Char,
     Keyword(Keyword),
+    /// Metavariable `?name` in a template; the text is the name.
+    MetaVar,
+    /// Line comment, only produced when trivia is preserved.
+    Comment,
+    /// Newline ending an expression, only produced when newlines are
+    /// separators.
+    Newline,
+    /// Run of operator characters, only produced when the scanner is
+    /// configured with a set of operator characters; the text is the run.
+    Operator,
+}
+
+/// Describes the kind of token for error messages. Symbols and keywords
+/// are quoted.
+impl Display for TokenKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenKind::Eof => "end of input".fmt(f),
+            TokenKind::Identifier => "identifier".fmt(f),
+            TokenKind::Number => "number".fmt(f),
+            TokenKind::Float => "floating-point number".fmt(f),
+            TokenKind::Symbol(sym) => write!(f, "'{sym}'"),
+            TokenKind::String => "string".fmt(f),
+            TokenKind::Char => "character".fmt(f),
+            TokenKind::Keyword(kw) => write!(f, "'{kw}'"),
+            TokenKind::MetaVar => "metavariable".fmt(f),
+            TokenKind::Comment => "comment".fmt(f),
+            TokenKind::Newline => "newline".fmt(f),
+            TokenKind::Operator => "operator".fmt(f),
+        }
+    }
+}
+
+/// Classification of tokens for syntax highlighting.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum HighlightKind {
+    Name,
+    Literal,
+    Keyword,
+    Operator,
+    /// Brackets, separators and other symbols without a meaning of their
+    /// own.
+    Punctuation,
+    Comment,
+}
+
+impl TokenKind {
+    /// How to highlight tokens of this kind, or `None` for tokens without
+    /// text of their own, like `Eof`.
+    pub fn highlight(self) -> Option<HighlightKind> {
+        let kind = match self {
+            TokenKind::Identifier | TokenKind::MetaVar => HighlightKind::Name,
+            TokenKind::Number | TokenKind::Float | TokenKind::String | TokenKind::Char => {
+                HighlightKind::Literal
+            }
+            TokenKind::Keyword(_) => HighlightKind::Keyword,
+            TokenKind::Operator => HighlightKind::Operator,
+            TokenKind::Symbol(sym) => match sym {
+                Symbol::Comma
+                | Symbol::Colon
+                | Symbol::Semicolon
+                | Symbol::Dot
+                | Symbol::LParen
+                | Symbol::RParen
+                | Symbol::LBrace
+                | Symbol::RBrace
+                | Symbol::LBracket
+                | Symbol::RBracket
+                | Symbol::At
+                | Symbol::Question => HighlightKind::Punctuation,
+                _ => HighlightKind::Operator,
+            },
+            TokenKind::Comment => HighlightKind::Comment,
+            TokenKind::Eof | TokenKind::Newline => return None,
+        };
+        Some(kind)
+    }
+}
+
+/// Capitalization of an identifier, as determined by its first character.
+/// Identifiers starting with an underscore count as lowercase.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum IdentCase {
+    Upper,
+    Lower,
+}
+
+/// A piece of an interpolating string literal: literal text, with escape
+/// sequences decoded, or a name `$name`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum StringPart<'src> {
+    Literal(String),
+    /// A name to interpolate, with the span of the name without the `$`.
+    Name { name: &'src str, span: Span },
 }
 
 #[derive(Debug, Clone)]
 // #[allow(dead_code)]
 pub struct Token<'src> {
     pub(crate) kind: TokenKind,
-    pub(crate) start: usize,
-    pub(crate) end: usize,
+    pub(crate) span: Span,
     pub(crate) raw_text: &'src str,
     pub(crate) text: Cow<'src, str>,
+    pub(crate) num_suffix: Option<&'src str>,
+    /// Whitespace directly before the token, only recorded when trivia is
+    /// preserved.
+    pub(crate) leading_whitespace: &'src str,
+    /// The parts of a string literal that interpolates names.
+    pub(crate) parts: Vec<StringPart<'src>>,
 }
 
 impl<'src> Token<'src> {
@@ -50,21 +241,28 @@ impl<'src> Token<'src> {
     pub fn new(kind: TokenKind) -> Token<'src> {
         Token {
             kind,
-            start: 0,
-            end: 0,
+            span: Span::default(),
             raw_text: "",
             text: "".into(),
+            num_suffix: None,
+            leading_whitespace: "",
+            parts: Vec::new(),
         }
     }
 
     #[allow(dead_code)]
     pub fn start(&self) -> usize {
-        self.start
+        self.span.start
     }
 
     #[allow(dead_code)]
     pub fn end(&self) -> usize {
-        self.end
+        self.span.end
+    }
+
+    #[allow(dead_code)]
+    pub fn span(&self) -> Span {
+        self.span
     }
 
     #[allow(dead_code)]
@@ -77,8 +275,67 @@ impl<'src> Token<'src> {
         self.text.as_ref()
     }
     
+    /// The text of the token as an owned string, which does not borrow
+    /// from the source.
+    #[allow(dead_code)]
+    pub fn text_owned(&self) -> String {
+        self.text.to_string()
+    }
+
     #[allow(dead_code)]
     pub fn raw_text(&self) -> &str {
         self.raw_text
     }
+
+    /// The type suffix of a numeric literal, e.g. `i64` for `42i64`.
+    #[allow(dead_code)]
+    pub fn num_suffix(&self) -> Option<&str> {
+        self.num_suffix
+    }
+
+    /// The literal parts and interpolated names of a string literal, in
+    /// order. Empty unless the scanner interpolates names and the string
+    /// contains at least one `$name`; the string is then the concatenation
+    /// of the parts.
+    #[allow(dead_code)]
+    pub fn parts(&self) -> &[StringPart<'src>] {
+        &self.parts
+    }
+
+    /// The whitespace between the previous token and this one. Always
+    /// empty unless the scanner preserves trivia.
+    #[allow(dead_code)]
+    pub fn leading_whitespace(&self) -> &'src str {
+        self.leading_whitespace
+    }
+
+    /// The capitalization of an identifier token, or `None` for all other
+    /// tokens.
+    #[allow(dead_code)]
+    pub fn ident_case(&self) -> Option<IdentCase> {
+        if self.kind != TokenKind::Identifier {
+            return None;
+        }
+        match self.raw_text.chars().next() {
+            Some(ch) if ch.is_uppercase() => Some(IdentCase::Upper),
+            Some(_) => Some(IdentCase::Lower),
+            None => None,
+        }
+    }
+
+    /// The value of an integer literal token, or `None` if the token is not
+    /// an integer or does not fit into an `i64`. Works regardless of whether
+    /// the scanner preserved the number formatting.
+    #[allow(dead_code)]
+    pub fn as_i64(&self) -> Option<i64> {
+        if self.kind != TokenKind::Number {
+            return None;
+        }
+        self.text
+            .chars()
+            .filter(|c| *c != '_')
+            .collect::<String>()
+            .parse()
+            .ok()
+    }
 }
@@ -0,0 +1,214 @@
+use std::{borrow::Cow, fmt::Display, rc::Rc};
+
+use crate::ast::{Node, NodeKind};
+
+/// Type of an expression, as attached to the nodes of a tree by type
+/// inference.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[allow(dead_code)]
+pub enum Type {
+    /// Type variable, printed by its name.
+    Var(String),
+    Int,
+    /// Named type other than `Int`, like `Bool`.
+    Con(String),
+    /// Function type `param -> result`.
+    Fun(Rc<Type>, Rc<Type>),
+}
+
+/// Prints types with `->` associating to the right, so that only function
+/// types in parameter position are parenthesized.
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Var(name) => name.fmt(f),
+            Type::Int => "Int".fmt(f),
+            Type::Con(name) => name.fmt(f),
+            Type::Fun(param, result) => {
+                if let Type::Fun(..) = **param {
+                    write!(f, "({param}) -> {result}")
+                } else {
+                    write!(f, "{param} -> {result}")
+                }
+            }
+        }
+    }
+}
+
+/// Name of a type class, like `Eq`.
+pub type ClassName = String;
+
+/// Name of a type variable.
+pub type TypeVar = String;
+
+/// A type with the constraints on its type variables, as written in a
+/// signature `Eq a => a -> a -> Bool`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[allow(dead_code)]
+pub struct Qualified {
+    pub constraints: Vec<(ClassName, TypeVar)>,
+    pub ty: Type,
+}
+
+/// Prints a single constraint without parentheses and several in a
+/// parenthesized list, each followed by `=>` and the type.
+impl Display for Qualified {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.constraints[..] {
+            [] => {}
+            [(class, var)] => write!(f, "{class} {var} => ")?,
+            constraints => {
+                "(".fmt(f)?;
+                for (i, (class, var)) in constraints.iter().enumerate() {
+                    if i > 0 {
+                        ", ".fmt(f)?;
+                    }
+                    write!(f, "{class} {var}")?;
+                }
+                ") => ".fmt(f)?;
+            }
+        }
+        self.ty.fmt(f)
+    }
+}
+
+/// Print a tree annotated with types. Every atom is printed together with
+/// its type as `(x : a)`, and the type of the whole term is appended as
+/// `(term) : type`, so that `\x. x` prints as `(\ x. (x : a)) : a -> a`.
+/// The names introduced by abstractions and `let` are printed without
+/// their types. Other inner nodes are printed as by `Display`.
+#[allow(dead_code)]
+pub fn show_typed(node: &Node<'_, Type>) -> String {
+    let shown = with_types(node, false);
+    match node.kind() {
+        NodeKind::Name { .. } | NodeKind::Lit { .. } | NodeKind::BoundVar { .. } => shown.to_string(),
+        _ => format!("({shown}) : {}", node.anno()),
+    }
+}
+
+/// Return a copy of the tree in which every atom that is not a binder is
+/// replaced by a name showing the atom and its type.
+fn with_types<'src>(node: &Node<'src, Type>, binder: bool) -> Rc<Node<'src, ()>> {
+    let annotated = |node: &Node<'src, Type>| with_types(node, false);
+    let kind = match node.kind() {
+        NodeKind::Name { name, id } if binder => NodeKind::Name { name: name.clone(), id: *id },
+        NodeKind::Name { .. } | NodeKind::Lit { .. } | NodeKind::BoundVar { .. } => {
+            let shown = Node::new(node.span(), (), atom_without_anno(node.kind())).to_string();
+            NodeKind::Name {
+                name: Cow::Owned(format!("({shown} : {})", node.anno())),
+                id: None,
+            }
+        }
+        NodeKind::App { fun, arg } => NodeKind::App {
+            fun: annotated(fun),
+            arg: annotated(arg),
+        },
+        NodeKind::Abs { param, body } => NodeKind::Abs {
+            param: with_types(param, true),
+            body: annotated(body),
+        },
+        NodeKind::BinOp { op, op_span, lhs, rhs } => NodeKind::BinOp {
+            op: *op,
+            op_span: *op_span,
+            lhs: annotated(lhs),
+            rhs: annotated(rhs),
+        },
+        NodeKind::Borrow { expr } => NodeKind::Borrow { expr: annotated(expr) },
+        NodeKind::Ann { expr, ty } => NodeKind::Ann {
+            expr: annotated(expr),
+            ty: ty.clone(),
+        },
+        NodeKind::If { cond, conseq, alt } => NodeKind::If {
+            cond: annotated(cond),
+            conseq: annotated(conseq),
+            alt: annotated(alt),
+        },
+        NodeKind::MultiIf { arms, otherwise } => NodeKind::MultiIf {
+            arms: arms.iter().map(|(cond, body)| (annotated(cond), annotated(body))).collect(),
+            otherwise: otherwise.as_ref().map(|otherwise| annotated(otherwise)),
+        },
+        NodeKind::Let { bindings, body } => NodeKind::Let {
+            bindings: bindings
+                .iter()
+                .map(|(name, value)| (with_types(name, true), annotated(value)))
+                .collect(),
+            body: annotated(body),
+        },
+        NodeKind::Seq { first, second } => NodeKind::Seq {
+            first: annotated(first),
+            second: annotated(second),
+        },
+        NodeKind::List { elems } => NodeKind::List {
+            elems: elems.iter().map(|elem| annotated(elem)).collect(),
+        },
+        NodeKind::Comprehension { body, generators, guards } => NodeKind::Comprehension {
+            body: annotated(body),
+            generators: generators
+                .iter()
+                .map(|(name, list)| (with_types(name, true), annotated(list)))
+                .collect(),
+            guards: guards.iter().map(|guard| annotated(guard)).collect(),
+        },
+    };
+    Rc::new(Node::new(node.span(), (), kind))
+}
+
+/// Copy an atom to a tree without annotations.
+fn atom_without_anno<'src>(kind: &NodeKind<'src, Type>) -> NodeKind<'src, ()> {
+    match kind {
+        NodeKind::Name { name, id } => NodeKind::Name { name: name.clone(), id: *id },
+        NodeKind::Lit { lit } => NodeKind::Lit { lit: lit.clone() },
+        NodeKind::BoundVar { index } => NodeKind::BoundVar { index: *index },
+        _ => unreachable!("not an atom"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ast::Literal, span::Span};
+
+    fn node(anno: Type, kind: NodeKind<'static, Type>) -> Rc<Node<'static, Type>> {
+        Rc::new(Node::new(Span::default(), anno, kind))
+    }
+
+    fn name(anno: Type, name: &'static str) -> Rc<Node<'static, Type>> {
+        node(anno, NodeKind::Name { name: name.into(), id: None })
+    }
+
+    fn var(name: &str) -> Type {
+        Type::Var(name.into())
+    }
+
+    fn fun(param: Type, result: Type) -> Type {
+        Type::Fun(Rc::new(param), Rc::new(result))
+    }
+
+    #[test]
+    fn typed_trees() {
+        // \x. x
+        let id = node(
+            fun(var("a"), var("a")),
+            NodeKind::Abs {
+                param: name(var("a"), "x"),
+                body: name(var("a"), "x"),
+            },
+        );
+        assert_eq!(show_typed(&id), "(\\ x. (x : a)) : a -> a");
+
+        // f 1
+        let app = node(
+            var("b"),
+            NodeKind::App {
+                fun: name(fun(Type::Int, var("b")), "f"),
+                arg: node(Type::Int, NodeKind::Lit { lit: Literal::Int(1) }),
+            },
+        );
+        assert_eq!(show_typed(&app), "((f : Int -> b) (1 : Int)) : b");
+        assert_eq!(show_typed(&name(var("a"), "x")), "(x : a)");
+        assert_eq!(
+            fun(fun(var("a"), var("b")), fun(var("a"), var("b"))).to_string(),
+            "(a -> b) -> a -> b"
+        );
+    }
+}